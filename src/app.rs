@@ -1,16 +1,23 @@
-use crate::audio::AudioEngine;
-use crate::constants::{
-    animation, audio as audio_const, defrag_type::DefragStyle, disk, ui as ui_const,
-};
-use crate::dos_files::DosFileProvider;
-
-use crate::models::{ClusterState, DefragPhase, DefragStats};
-use rand::prelude::{Rng, SliceRandom};
+use crate::audio::{AudioEngine, AudioSettings, SoundCategory};
+use crate::clock::{Clock, SystemClock};
+use crate::config;
+use crate::constants::{animation, audio as audio_const, defrag_type::DefragStyle, disk};
+use crate::engine::{DefragEngine, EngineState, SimEvent};
+use crate::export::{print::PrintFormat, snapshot, snapshot::Palette};
+use crate::fat::FatVolume;
+use crate::layout::DiskLayout;
+use crate::models::{DefragPhase, FileFragment, FileSort, OptimizationMethod, SortKey, SortOrder};
+use crate::scan;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::{
     io::Result,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::mpsc,
     time::{Duration, Instant},
 };
+use toml::Spanned;
 
 // -- CLI arguments ------------------------------------------------------------
 
@@ -25,25 +32,112 @@ pub struct Args {
     #[arg(long, default_value = "normal")]
     pub speed: String,
 
-    /// Grid size in format WxH (e.g., 85x20)
+    /// Grid size in format WxH (e.g., 85x20); the height only applies to
+    /// --scan, drives take as many rows as their clusters need
     #[arg(long, default_value = "78x16")]
     pub size: String,
 
-    /// Initial disk fill percentage
-    #[arg(long, default_value_t = 0.65)]
-    pub fill: f32,
+    /// Initial disk fill percentage (default: the drive's fill ratio)
+    #[arg(long)]
+    pub fill: Option<f32>,
 
     /// Enable HDD sounds
     #[arg(long, short = 's', default_value_t = true)]
     pub sound: bool,
 
-    /// Select disk drive (C, D, E, or F)
+    /// Sound volume, from 0 to 1
+    #[arg(long)]
+    pub volume: Option<f32>,
+
+    /// Select disk drive (C, D, E, F or one from the drives file), or "all"
+    /// to defragment every drive in turn
     #[arg(long, short = 'd', default_value = "C")]
-    pub drive: char,
+    pub drive: String,
+
+    /// Drives file adding drive profiles (default: drives.toml in the user
+    /// configuration directory, when present)
+    #[arg(long)]
+    pub drives: Option<String>,
 
     /// UI style: msdos, win95, or win98
     #[arg(long, short = 'u', default_value = "msdos")]
     pub ui: String,
+
+    /// Optimization method: full or unfragment (unfragment files only)
+    #[arg(long, short = 'm', default_value = "full")]
+    pub method: String,
+
+    /// File sort: none, name, extension, date or size, optionally followed by
+    /// :asc or :desc (e.g. date:desc)
+    #[arg(long, default_value = "none")]
+    pub sort: String,
+
+    /// Raw FAT12/FAT16/FAT32 disk image to analyze and defragment (read-only)
+    #[arg(long)]
+    pub image: Option<String>,
+
+    /// Directory whose files' real on-disk fragmentation is shown and
+    /// defragmented in simulation (read-only, Linux)
+    #[arg(long, conflicts_with = "image")]
+    pub scan: Option<String>,
+
+    /// Write a defragmented copy of the --image disk image to this file
+    #[arg(long, requires = "image")]
+    pub output: Option<String>,
+
+    /// Save the final disk map to this file when the run finishes; the
+    /// extension selects the format (.txt, .437, .ans or .html)
+    #[arg(long)]
+    pub save_map: Option<String>,
+
+    /// Directory "Print disk map" writes its printouts to
+    #[arg(long, default_value = ".")]
+    pub print_dir: String,
+
+    /// Printout format: ps (PostScript) or pdf
+    #[arg(long, default_value = "ps")]
+    pub print_format: String,
+
+    /// Save a PNG snapshot of the disk map every N ticks, for time-lapses
+    #[arg(long, value_name = "N")]
+    pub snapshot_every: Option<u32>,
+
+    /// Directory PNG snapshots (C key or --snapshot-every) are written to
+    #[arg(long, default_value = ".")]
+    pub snapshot_dir: String,
+
+    /// Snapshot and recording colors: msdos or win98 (default: those of the
+    /// UI style)
+    #[arg(long)]
+    pub snapshot_palette: Option<String>,
+
+    /// Run the whole simulation without a screen, as fast as possible, and
+    /// record it as an animated GIF (.gif) or APNG (.png)
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<String>,
+
+    /// Ticks between two recorded frames
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    pub record_every: u32,
+
+    /// Shrink recorded frames this many times, for large grids
+    #[arg(long, value_name = "FACTOR", default_value_t = 1)]
+    pub record_downscale: usize,
+
+    /// Run the whole simulation without a screen, as fast as possible, and
+    /// record the MS-DOS interface as an asciicast v2 file
+    #[arg(long, value_name = "PATH", conflicts_with = "record")]
+    pub cast: Option<String>,
+
+    /// Continue the run saved in this file, or start a new one if it does not
+    /// exist yet; Esc saves an unfinished run to it (default: state.json in
+    /// the configuration directory)
+    #[arg(long, value_name = "PATH")]
+    pub resume: Option<String>,
+
+    /// Random seed, the same seed and options always replay the same run
+    #[arg(long)]
+    pub seed: Option<u64>,
 }
 
 impl Args {
@@ -55,6 +149,60 @@ impl Args {
             _ => DefragStyle::MsDos,
         }
     }
+
+    /// Parse the optimization method from the command line argument
+    pub fn get_method(&self) -> OptimizationMethod {
+        match self.method.to_lowercase().as_str() {
+            "unfragment" | "unfragment-files-only" | "files" => {
+                OptimizationMethod::UnfragmentFilesOnly
+            }
+            _ => OptimizationMethod::FullOptimization,
+        }
+    }
+
+    /// Parse the file sort from the command line argument
+    pub fn get_sort(&self) -> FileSort {
+        let value = self.sort.to_lowercase();
+        let (key, order) = value.split_once(':').unwrap_or((&value, "asc"));
+        let key = match key {
+            "name" => SortKey::Name,
+            "extension" | "ext" => SortKey::Extension,
+            "date" | "time" => SortKey::Date,
+            "size" => SortKey::Size,
+            _ => SortKey::Unsorted,
+        };
+        let order = match order {
+            "desc" | "descending" => SortOrder::Descending,
+            _ => SortOrder::Ascending,
+        };
+        FileSort::new(key, order)
+    }
+
+    /// Parse the snapshot palette from the command line argument, falling
+    /// back to the one of the UI style
+    pub fn get_snapshot_palette(&self) -> Palette {
+        let style_palette = match self.get_ui_style() {
+            DefragStyle::MsDos => Palette::MsDos,
+            _ => Palette::Win98,
+        };
+        self.snapshot_palette
+            .as_deref()
+            .and_then(Palette::parse)
+            .unwrap_or(style_palette)
+    }
+
+    /// Parse the sound volume from the command line argument, clamped to
+    /// 0-1
+    pub fn get_volume(&self) -> f32 {
+        self.volume
+            .filter(|volume| volume.is_finite())
+            .map_or(audio_const::DEFAULT_VOLUME, |volume| volume.clamp(0.0, 1.0))
+    }
+
+    /// Parse the printout format from the command line argument
+    pub fn get_print_format(&self) -> PrintFormat {
+        PrintFormat::parse(&self.print_format).unwrap_or_default()
+    }
 }
 
 // -- Disk drive types ----------------------------------------------------------
@@ -64,22 +212,42 @@ impl Args {
 #[derive(Debug, Clone)]
 pub struct DiskDrive {
     pub config: disk::DriveConfig,
+    /// Volume label, or the media type when the drive has none
+    pub label: String,
+    /// Label with capacity and speed, e.g. "Hard Disk (2GB, 2 IOPS)"
     pub name: String,
 }
 
 impl DiskDrive {
     /// Creates a new disk drive instance from a DriveConfig
     pub fn from_config(config: disk::DriveConfig) -> Self {
-        let name = match config.letter {
-            'C' => "Hard Disk (2GB, 2 IOPS)",
-            'D' => "Hard Disk (1GB, 3 IOPS)",
-            'E' => "Floppy Disk (512MB, 1 IOPS)",
-            'F' => "SSHD (2GB, 8 IOPS)",
-            _ => "Unknown Drive",
-        };
+        Self::with_label(config, config.media.name())
+    }
+
+    /// Creates a disk drive with its own volume label
+    pub fn with_label(config: disk::DriveConfig, label: &str) -> Self {
         Self {
             config,
-            name: name.to_string(),
+            label: label.to_string(),
+            name: format!(
+                "{} ({}, {} IOPS)",
+                label,
+                Self::format_capacity(config.capacity_mb),
+                config.iops
+            ),
+        }
+    }
+
+    /// Capacity as shown to the user, e.g. "2GB" or "512MB"
+    pub fn capacity(&self) -> String {
+        Self::format_capacity(self.config.capacity_mb)
+    }
+
+    fn format_capacity(capacity_mb: u32) -> String {
+        if capacity_mb >= 1024 && capacity_mb.is_multiple_of(1024) {
+            format!("{}GB", capacity_mb / 1024)
+        } else {
+            format!("{}MB", capacity_mb)
         }
     }
 
@@ -93,10 +261,158 @@ impl DiskDrive {
         self.config.letter
     }
 
+    /// Rows of a `width` clusters wide grid that hold every cluster
+    pub fn grid_height(&self, width: usize) -> usize {
+        (self.config.cluster_count as usize).div_ceil(width.max(1))
+    }
+
     /// Gets the calculated playback rate for audio based on IOPS
     pub fn audio_playback_rate(&self) -> f32 {
         audio_const::calculate_playback_rate(self.config.iops)
     }
+
+    /// Reads a drive from a `[[drive]]` table of `text`, a drives file
+    ///
+    /// Only the letter is required, other settings default to those of
+    /// drive C. Errors name the line of the faulty setting.
+    fn from_entry(entry: DriveEntry, text: &str) -> Result<Self> {
+        let invalid = |span: Range<usize>, message: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "line {}: drive {}: {}",
+                    config::line(text, span.start),
+                    entry.letter.get_ref(),
+                    message
+                ),
+            )
+        };
+        let mut config = disk::DEFAULT_DRIVE;
+        let mut chars = entry.letter.get_ref().chars();
+        config.letter = match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphabetic() => c.to_ascii_uppercase(),
+            _ => {
+                return Err(invalid(
+                    entry.letter.span(),
+                    "`letter` must be a single letter".into(),
+                ))
+            }
+        };
+
+        // The media type picks the default mechanics, which the geometry
+        // settings below refine
+        if let Some(ref media) = entry.media {
+            config.media = disk::MediaType::parse(media.get_ref()).ok_or_else(|| {
+                invalid(media.span(), "`media` must be floppy, hdd or ssd".into())
+            })?;
+            config.geometry = disk::DiskGeometry::for_media(config.media);
+        }
+
+        let positive = |value: Option<Spanned<u32>>, key: &str| match value {
+            Some(value) if *value.get_ref() == 0 => {
+                Err(invalid(value.span(), format!("`{}` must be above 0", key)))
+            }
+            _ => Ok(value.map(Spanned::into_inner)),
+        };
+        let ratio = |value: Option<Spanned<f32>>, key: &str| match value {
+            Some(value) if !disk::is_ratio(*value.get_ref()) => Err(invalid(
+                value.span(),
+                format!("`{}` must be between 0 and 1", key),
+            )),
+            _ => Ok(value.map(Spanned::into_inner)),
+        };
+        let time = |value: Option<Spanned<f32>>, key: &str| match value {
+            Some(value) if !disk::is_time(*value.get_ref()) => Err(invalid(
+                value.span(),
+                format!("`{}` must be finite and not negative", key),
+            )),
+            _ => Ok(value.map(Spanned::into_inner)),
+        };
+
+        // Only solid-state drives have no spindle, the others need one
+        // turning for the rotational latency
+        let rpm = match entry.rpm {
+            Some(rpm) if config.media == disk::MediaType::Ssd && *rpm.get_ref() != 0 => {
+                return Err(invalid(rpm.span(), "`rpm` must be 0 for ssd media".into()))
+            }
+            Some(rpm) if config.media != disk::MediaType::Ssd && !disk::is_rpm(*rpm.get_ref()) => {
+                return Err(invalid(
+                    rpm.span(),
+                    format!(
+                        "`rpm` must be between {} and {}",
+                        disk::RPM_RANGE.0,
+                        disk::RPM_RANGE.1
+                    ),
+                ))
+            }
+            rpm => rpm.map(Spanned::into_inner),
+        };
+
+        let geometry = &mut config.geometry;
+        config.capacity_mb =
+            positive(entry.capacity_mb, "capacity_mb")?.unwrap_or(config.capacity_mb);
+        config.cluster_count = match entry.cluster_count {
+            Some(count)
+                if !(disk::CLUSTER_COUNT_RANGE.0..=disk::CLUSTER_COUNT_RANGE.1)
+                    .contains(count.get_ref()) =>
+            {
+                return Err(invalid(
+                    count.span(),
+                    format!(
+                        "`cluster_count` must be between {} and {}",
+                        disk::CLUSTER_COUNT_RANGE.0,
+                        disk::CLUSTER_COUNT_RANGE.1
+                    ),
+                ))
+            }
+            count => count.map_or(config.cluster_count, Spanned::into_inner),
+        };
+        config.iops = positive(entry.iops, "iops")?.unwrap_or(config.iops);
+        config.bad_ratio = ratio(entry.bad_ratio, "bad_ratio")?.unwrap_or(config.bad_ratio);
+        config.fill_ratio = ratio(entry.fill_ratio, "fill_ratio")?.unwrap_or(config.fill_ratio);
+        geometry.cylinders = positive(entry.cylinders, "cylinders")?.unwrap_or(geometry.cylinders);
+        geometry.heads = positive(entry.heads, "heads")?.unwrap_or(geometry.heads);
+        geometry.sectors_per_track =
+            positive(entry.sectors, "sectors")?.unwrap_or(geometry.sectors_per_track);
+        geometry.rpm = rpm.unwrap_or(geometry.rpm);
+        geometry.track_to_track_ms = time(entry.track_to_track_ms, "track_to_track_ms")?
+            .unwrap_or(geometry.track_to_track_ms);
+        geometry.full_stroke_ms =
+            time(entry.full_stroke_ms, "full_stroke_ms")?.unwrap_or(geometry.full_stroke_ms);
+
+        Ok(match entry.label {
+            Some(ref label) => Self::with_label(config, label),
+            None => Self::from_config(config),
+        })
+    }
+}
+
+/// A drives file: `[[drive]]` tables only
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DrivesFile {
+    #[serde(default)]
+    drive: Vec<DriveEntry>,
+}
+
+/// A `[[drive]]` table of a drives file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DriveEntry {
+    letter: Spanned<String>,
+    label: Option<String>,
+    media: Option<Spanned<String>>,
+    capacity_mb: Option<Spanned<u32>>,
+    cluster_count: Option<Spanned<u32>>,
+    iops: Option<Spanned<u32>>,
+    bad_ratio: Option<Spanned<f32>>,
+    fill_ratio: Option<Spanned<f32>>,
+    cylinders: Option<Spanned<u32>>,
+    heads: Option<Spanned<u32>>,
+    sectors: Option<Spanned<u32>>,
+    rpm: Option<Spanned<u32>>,
+    track_to_track_ms: Option<Spanned<f32>>,
+    full_stroke_ms: Option<Spanned<f32>>,
 }
 
 /// Collection of available disk drives for the simulation
@@ -115,6 +431,41 @@ impl DiskDriveCollection {
         }
     }
 
+    /// Creates the default drives plus those of a drives file
+    ///
+    /// Without `path`, the drives file of the user configuration directory is
+    /// read when it exists. A drive using the letter of a built-in one
+    /// replaces it, others are added in file order.
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let mut collection = Self::new();
+        let text = match path {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => match config::config_dir().map(|dir| dir.join(disk::DRIVES_FILE)) {
+                Some(path) if path.exists() => std::fs::read_to_string(path)?,
+                _ => return Ok(collection),
+            },
+        };
+        collection.add_from(&text)?;
+        Ok(collection)
+    }
+
+    /// Adds the `[[drive]]` tables of a drives file
+    fn add_from(&mut self, text: &str) -> Result<()> {
+        let file: DrivesFile = config::parse(text)?;
+        for entry in file.drive {
+            let drive = DiskDrive::from_entry(entry, text)?;
+            match self
+                .drives
+                .iter_mut()
+                .find(|existing| existing.letter() == drive.letter())
+            {
+                Some(existing) => *existing = drive,
+                None => self.drives.push(drive),
+            }
+        }
+        Ok(())
+    }
+
     /// Gets a reference to all available drives
     pub fn get_all(&self) -> &[DiskDrive] {
         &self.drives
@@ -125,6 +476,24 @@ impl DiskDriveCollection {
         self.drives.iter().find(|drive| drive.letter() == letter)
     }
 
+    /// Gets the drive for a `--drive` letter, failing with the list of known
+    /// letters when there is none
+    pub fn select(&self, letter: char) -> Result<&DiskDrive> {
+        self.get_by_letter(letter.to_ascii_uppercase())
+            .ok_or_else(|| {
+                let letters: Vec<String> =
+                    self.drives.iter().map(|d| d.letter().to_string()).collect();
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "unknown drive {} (available: {})",
+                        letter.to_ascii_uppercase(),
+                        letters.join(", ")
+                    ),
+                )
+            })
+    }
+
     /// Gets drive by index
     pub fn get_by_index(&self, index: usize) -> Option<&DiskDrive> {
         self.drives.get(index)
@@ -136,156 +505,228 @@ impl DiskDriveCollection {
     }
 }
 
-// -- File simulation --------------------------------------------------------
-
-/// Represents a logical file with multiple clusters
-/// This allows simulating files of different sizes during defragmentation
-#[derive(Debug, Clone)]
-pub struct FileFragment {
-    /// The cluster indices that belong to this file
-    pub clusters: Vec<usize>,
-    /// The size of the file in clusters
-    pub size: usize,
-    /// Whether this file is fragmented (clusters not contiguous)
-    pub is_fragmented: bool,
+impl Default for DiskDriveCollection {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl FileFragment {
-    /// Create a new file fragment
-    pub fn new(clusters: Vec<usize>) -> Self {
-        let size = clusters.len();
-        let is_fragmented = Self::check_fragmentation(&clusters);
-        Self {
-            clusters,
-            size,
-            is_fragmented,
-        }
-    }
+/// Outcome of one drive of an "All drives" run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveReport {
+    pub letter: char,
+    pub label: String,
+    /// Simulated time the drive took
+    pub elapsed: Duration,
+    /// Share of fragmented files before and after, from 0 to 100
+    pub fragmented_before: f32,
+    pub fragmented_after: f32,
+}
 
-    /// Check if clusters are contiguous (not fragmented)
-    fn check_fragmentation(clusters: &[usize]) -> bool {
-        if clusters.len() <= 1 {
-            return false;
-        }
-        for window in clusters.windows(2) {
-            if window[1] != window[0] + 1 {
-                return true; // Not contiguous = fragmented
-            }
-        }
-        false
-    }
+/// Progress of an "All drives" run, which defragments every drive of the
+/// collection in turn
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DriveSequence {
+    /// Index of the drive being defragmented
+    pub current: usize,
+    /// Share of fragmented files of the current drive before its run
+    fragmented_before: f32,
+    /// Drives done so far
+    pub reports: Vec<DriveReport>,
+    /// Whether every drive is done
+    pub finished: bool,
+}
 
-    /// Get the first cluster of this file
-    pub fn first_cluster(&self) -> Option<usize> {
-        self.clusters.first().copied()
-    }
+/// Column of the "File fragmentation..." list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileColumn {
+    Name,
+    Fragments,
+    Size,
+    FirstCluster,
+    Fragmented,
+}
 
-    /// Get the last cluster of this file
-    pub fn last_cluster(&self) -> Option<usize> {
-        self.clusters.last().copied()
+impl FileColumn {
+    pub const ALL: [FileColumn; 5] = [
+        FileColumn::Name,
+        FileColumn::Fragments,
+        FileColumn::Size,
+        FileColumn::FirstCluster,
+        FileColumn::Fragmented,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FileColumn::Name => "File",
+            FileColumn::Fragments => "Fragments",
+            FileColumn::Size => "Clusters",
+            FileColumn::FirstCluster => "First",
+            FileColumn::Fragmented => "Status",
+        }
     }
 }
 
-/// Represents the state of a file during defragmentation
+/// State of the "File fragmentation..." dialog
 #[derive(Debug, Clone)]
-pub enum FileDefragPhase {
-    /// The file is being read from its fragmented location
-    Reading { progress: usize },
-    /// The file is being written to its new contiguous location
-    Writing { progress: usize },
-    /// The file has been fully defragmented
-    Completed,
+pub struct FileListDialog {
+    /// File indices in display order
+    pub rows: Vec<usize>,
+    /// Highlighted row
+    pub selected: usize,
+    pub column: FileColumn,
+    pub descending: bool,
 }
 
-// -- Application state --------------------------------------------------------
-
-/// Cache for tracking free space regions (optimization)
-#[derive(Debug, Clone)]
-pub struct FreeSpaceCache {
-    /// List of (start_index, length) for contiguous free regions
-    regions: Vec<(usize, usize)>,
-    /// Whether the cache needs rebuilding
-    dirty: bool,
-}
+impl FileListDialog {
+    /// Lists `files`, most fragmented first
+    pub fn new(files: &[FileFragment]) -> Self {
+        let mut dialog = Self {
+            rows: (0..files.len()).collect(),
+            selected: 0,
+            column: FileColumn::Fragments,
+            descending: true,
+        };
+        dialog.sort(files);
+        dialog
+    }
 
-impl FreeSpaceCache {
-    pub fn new() -> Self {
-        Self {
-            regions: Vec::new(),
-            dirty: true,
+    /// Sorts the rows by the current column, keeping the highlighted file
+    pub fn sort(&mut self, files: &[FileFragment]) {
+        let highlighted = self.rows.get(self.selected).copied();
+        let column = self.column;
+        self.rows.sort_by(|&a, &b| {
+            let (a, b) = (&files[a], &files[b]);
+            let order = match column {
+                FileColumn::Name => a
+                    .display_name()
+                    .to_lowercase()
+                    .cmp(&b.display_name().to_lowercase()),
+                FileColumn::Fragments => a.fragment_count().cmp(&b.fragment_count()),
+                FileColumn::Size => a.size.cmp(&b.size),
+                FileColumn::FirstCluster => a.first_cluster().cmp(&b.first_cluster()),
+                FileColumn::Fragmented => a.is_fragmented.cmp(&b.is_fragmented),
+            };
+            order.then_with(|| a.first_cluster().cmp(&b.first_cluster()))
+        });
+        if self.descending {
+            self.rows.reverse();
+        }
+        if let Some(file) = highlighted {
+            self.selected = self.rows.iter().position(|&row| row == file).unwrap_or(0);
         }
     }
 
-    /// Mark cache as needing rebuild
-    pub fn invalidate(&mut self) {
-        self.dirty = true;
+    /// Moves the highlight by `delta` rows, stopping at both ends
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
     }
 
-    /// Rebuild the cache from cluster state
-    pub fn rebuild(&mut self, clusters: &[ClusterState]) {
-        self.regions.clear();
-        let mut start: Option<usize> = None;
-        let mut length = 0;
+    /// Sorts by the column `delta` places left or right of the current one
+    pub fn cycle_column(&mut self, delta: isize, files: &[FileFragment]) {
+        let count = FileColumn::ALL.len() as isize;
+        let index = FileColumn::ALL
+            .iter()
+            .position(|&column| column == self.column)
+            .unwrap_or(0) as isize;
+        self.column = FileColumn::ALL[(index + delta).rem_euclid(count) as usize];
+        self.sort(files);
+    }
 
-        for (i, &cluster) in clusters.iter().enumerate() {
-            if cluster == ClusterState::Unused {
-                if start.is_none() {
-                    start = Some(i);
-                }
-                length += 1;
-            } else if let Some(s) = start {
-                self.regions.push((s, length));
-                start = None;
-                length = 0;
-            }
-        }
+    /// File under the highlight
+    pub fn selected_file(&self) -> Option<usize> {
+        self.rows.get(self.selected).copied()
+    }
+}
 
-        if let Some(s) = start {
-            self.regions.push((s, length));
-        }
+// -- Application state --------------------------------------------------------
 
-        self.regions.sort_by(|a, b| b.1.cmp(&a.1));
-        self.dirty = false;
-    }
+/// Format version of saved sessions
+const SESSION_VERSION: u32 = 3;
+
+/// A run saved by [`App::save_session`]
+#[derive(Serialize, Deserialize)]
+struct Session {
+    version: u32,
+    /// Letter of the drive the run is on
+    drive: char,
+    engine: EngineState,
+    /// "All drives" run the drive is part of
+    drive_sequence: Option<DriveSequence>,
+    /// Disk image the run is on, which must be loaded again to resume it
+    image: Option<PathBuf>,
+}
 
-    /// Find a region with at least `size` contiguous clusters
-    pub fn find_region(&self, size: usize) -> Option<usize> {
-        self.regions
-            .iter()
-            .find(|(_, len)| *len >= size)
-            .map(|(start, _)| *start)
-    }
+/// What is read of a saved session before the rest, whatever its version
+#[derive(Deserialize)]
+struct SessionVersion {
+    version: u32,
 }
 
 pub struct App {
     pub running: bool,
     pub paused: bool,
     pub tick_rate: Duration,
-    pub width: usize,
-    pub height: usize,
-    pub clusters: Vec<ClusterState>,
-    pub stats: DefragStats,
-    pub phase: DefragPhase,
-    pub animation_step: u64,
-    pub read_pos: Option<usize>,
-    pub write_pos: Option<usize>,
-    pub current_file_read_progress: Option<FileDefragPhase>,
-    pub current_filename: Option<String>,
-    pub current_op_end_time: Option<Instant>,
-    status_message: String,
-    file_provider: DosFileProvider,
+    /// Disk and defragmentation state
+    pub engine: DefragEngine,
+    /// Time source driving the engine
+    clock: Box<dyn Clock>,
+    /// Simulation time when the clock showed `clock_origin`
+    time_base: Duration,
+    clock_origin: Duration,
     pub menu_open: bool,
     pub selected_menu: usize,
     pub selected_item: usize,
     pub show_about_box: bool,
+    /// Method highlighted in the "Optimization method..." dialog, when open
+    pub method_dialog: Option<OptimizationMethod>,
+    /// Drive highlighted in the "Drive..." dialog, when open; the entry
+    /// after the last drive is "All drives"
+    pub drive_dialog: Option<usize>,
+    /// "All drives" run in progress or done
+    pub drive_sequence: Option<DriveSequence>,
+    /// "File fragmentation..." dialog, when open
+    pub file_dialog: Option<FileListDialog>,
+    /// Analysis report dialog, when open: whether "Optimize" (rather than
+    /// "Cancel") is highlighted
+    pub analysis_dialog: Option<bool>,
+    /// File name typed in the "Save disk map..." dialog, when open
+    pub save_dialog: Option<String>,
+    /// Message box text, when one is shown
+    pub message: Option<String>,
+    /// Directory "Print disk map" writes its printouts to
+    pub print_dir: PathBuf,
+    pub print_format: PrintFormat,
+    /// Directory PNG snapshots are written to
+    pub snapshot_dir: PathBuf,
+    pub snapshot_palette: Palette,
+    /// Ticks between two time-lapse snapshots, if any
+    pub snapshot_every: Option<u32>,
+    /// Lowest number the next snapshot file may take
+    next_snapshot: u32,
+    /// Engine updates since the start
+    ticks: u64,
     pub audio: Option<AudioEngine>,
+    /// Sound volume, from 0 to 1
+    pub volume: f32,
+    /// Kinds of sounds the user muted
+    pub audio_settings: AudioSettings,
     pub current_drive: DiskDrive,
     pub drive_collection: DiskDriveCollection,
+    /// Fill ratio of `--fill`, which drives picked later use instead of
+    /// their own
+    pub fill_override: Option<f32>,
     pub ui_style: DefragStyle,
-    free_space_cache: FreeSpaceCache,
     pub demo_mode: bool,
-    pending_indices_cache: Vec<usize>,
-    pending_cache_dirty: bool,
+    /// Run without anyone at the keyboard: nothing waits for a key
+    pub unattended: bool,
+    /// Whether the run was left with Esc, to be saved and resumed later
+    pub interrupted: bool,
+    /// Disk image the engine's layout was read from
+    pub image: Option<FatVolume>,
+    /// Path of the disk image or scanned directory the layout comes from
+    pub layout_path: Option<PathBuf>,
 }
 
 impl App {
@@ -294,199 +735,393 @@ impl App {
         height: usize,
         fill_percent: f32,
         enable_sound: bool,
-        drive_letter: char,
+        current_drive: DiskDrive,
         ui_style: DefragStyle,
+        seed: Option<u64>,
     ) -> Self {
-        let total_clusters = width * height;
-        let mut rng = rand::thread_rng();
-
-        let num_pending = (total_clusters as f32 * fill_percent) as usize;
-        let num_bad = (total_clusters as f32 * ui_const::BAD_BLOCK_PERCENT) as usize;
-
-        let mut clusters: Vec<ClusterState> = Vec::with_capacity(total_clusters);
-
-        for _ in 0..(num_pending.saturating_sub(2)) {
-            clusters.push(ClusterState::Pending);
-        }
-
-        clusters.push(ClusterState::Writing);
-        clusters.push(ClusterState::Reading);
-
-        while clusters.len() < total_clusters - num_bad {
-            clusters.push(ClusterState::Unused);
-        }
-
-        clusters.shuffle(&mut rng);
-
-        let mut bad_positions: Vec<usize> = (0..clusters.len()).collect();
-        bad_positions.shuffle(&mut rng);
-        for &pos in bad_positions.iter().take(num_bad) {
-            clusters.insert(pos.min(clusters.len()), ClusterState::Bad);
-        }
-
-        clusters.truncate(total_clusters);
-
-        if !clusters.is_empty() {
-            clusters[0] = ClusterState::Unmovable;
-        }
-
-        let total_to_defrag = clusters
-            .iter()
-            .filter(|&&c| c == ClusterState::Pending)
-            .count()
-            + 2;
-
-        let drive_collection = DiskDriveCollection::new();
-        let current_drive = drive_collection
-            .get_by_letter(drive_letter.to_ascii_uppercase())
-            .unwrap_or_else(|| drive_collection.get_default())
-            .clone();
-
-        Self {
+        let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+        let mut engine = DefragEngine::new(
+            width,
+            height,
+            fill_percent,
+            current_drive.config.bad_ratio,
+            current_drive.iops(),
+            seed,
+        );
+        engine.geometry = current_drive.config.geometry;
+
+        let mut app = Self {
             running: true,
             paused: false,
             tick_rate: Duration::from_millis(animation::DEFAULT_TICK_RATE_MS),
-            width,
-            height,
-            clusters,
-            stats: DefragStats {
-                total_to_defrag,
-                clusters_defragged: 0,
-                start_time: Instant::now(),
-            },
-            phase: DefragPhase::Initializing,
-            animation_step: 0,
-            read_pos: None,
-            write_pos: None,
-            current_file_read_progress: None,
-            current_filename: None,
-            current_op_end_time: None,
-            status_message: "Initializing...".to_string(),
-            file_provider: DosFileProvider::new(),
+            engine,
+            clock: Box::new(SystemClock::new()),
+            time_base: Duration::ZERO,
+            clock_origin: Duration::ZERO,
             menu_open: false,
             selected_menu: 0,
             selected_item: 0,
             show_about_box: false,
+            method_dialog: None,
+            drive_dialog: None,
+            drive_sequence: None,
+            file_dialog: None,
+            analysis_dialog: None,
+            save_dialog: None,
+            message: None,
+            print_dir: PathBuf::from("."),
+            print_format: PrintFormat::default(),
+            snapshot_dir: PathBuf::from("."),
+            snapshot_palette: match ui_style {
+                DefragStyle::MsDos => Palette::MsDos,
+                _ => Palette::Win98,
+            },
+            snapshot_every: None,
+            next_snapshot: 1,
+            ticks: 0,
             audio: if enable_sound {
-                let mut audio = AudioEngine::new();
-                if let Some(ref mut audio_engine) = audio {
-                    audio_engine.set_iops(current_drive.iops());
-                }
-                audio
+                AudioEngine::new()
             } else {
                 None
             },
+            volume: audio_const::DEFAULT_VOLUME,
+            audio_settings: AudioSettings::default(),
             current_drive,
-            drive_collection,
+            drive_collection: DiskDriveCollection::new(),
+            fill_override: None,
             ui_style,
-            free_space_cache: FreeSpaceCache::new(),
             demo_mode: false,
-            pending_indices_cache: Vec::new(),
-            pending_cache_dirty: true,
+            unattended: false,
+            interrupted: false,
+            image: None,
+            layout_path: None,
+        };
+        app.tune_audio();
+        app
+    }
+
+    /// Replaces the time source, e.g. with a virtual clock to run faster
+    /// than real time
+    ///
+    /// Simulation time goes on from the engine's last update.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+        self.sync_clock();
+    }
+
+    /// Makes simulation time go on from the engine's last update
+    fn sync_clock(&mut self) {
+        self.time_base = self.engine.now();
+        self.clock_origin = self.clock.now();
+    }
+
+    /// Current simulation time
+    fn now(&self) -> Duration {
+        self.time_base + self.clock.now().saturating_sub(self.clock_origin)
+    }
+
+    /// Saves the run to `path`, to be continued with [`App::resume`]
+    pub fn save_session(&self, path: &Path) -> Result<()> {
+        let session = Session {
+            version: SESSION_VERSION,
+            drive: self.current_drive.letter(),
+            engine: self.engine.save_state(),
+            drive_sequence: self.drive_sequence.clone(),
+            image: self.image_path(),
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
         }
+        std::fs::write(path, format!("{}\n", serde_json::to_string(&session)?))
     }
 
-    pub fn toggle_pause(&mut self) {
-        if self.phase == DefragPhase::Defragmenting || self.phase == DefragPhase::Analyzing {
-            self.paused = !self.paused;
-            if self.paused {
-                if let Some(ref audio) = self.audio {
-                    audio.stop_all();
-                }
+    /// Continues the run saved in `path` by [`App::save_session`]
+    ///
+    /// The time the simulator was not running does not count: the elapsed
+    /// time and the operation in progress go on where they stopped, and an
+    /// "All drives" run goes on with the drives after the saved one.
+    pub fn resume(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        let SessionVersion { version } = serde_json::from_str(&text)?;
+        if version != SESSION_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unsupported saved state version",
+            ));
+        }
+        let session: Session = serde_json::from_str(&text)?;
+        let drive = self.drive_collection.select(session.drive)?.clone();
+        if let Some(ref sequence) = session.drive_sequence {
+            let current = self.drive_collection.get_by_index(sequence.current);
+            if current.is_none_or(|current| current.letter() != drive.letter()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "the saved \"All drives\" run was made with other drives",
+                ));
             }
         }
+        // Only the loaded image can be written back with the run's moves
+        if session.image != self.image_path() {
+            let message = match session.image {
+                Some(ref image) => format!(
+                    "the saved run was made on the disk image {}, load it with --image",
+                    image.display()
+                ),
+                None => "the saved run was not made on a disk image".to_string(),
+            };
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                message,
+            ));
+        }
+        self.engine.restore_state(session.engine)?;
+
+        self.current_drive = drive;
+        self.drive_sequence = session.drive_sequence;
+        self.tune_audio();
+        self.sync_clock();
+        Ok(())
     }
 
-    pub fn toggle_demo_mode(&mut self) {
-        self.demo_mode = !self.demo_mode;
+    /// Absolute path of the loaded disk image, if any
+    fn image_path(&self) -> Option<PathBuf> {
+        let path = self.layout_path.as_ref().filter(|_| self.image.is_some())?;
+        Some(std::fs::canonicalize(path).unwrap_or_else(|_| path.clone()))
     }
 
-    pub fn restart(&mut self) {
-        let mut rng = rand::thread_rng();
-        let total_clusters = self.width * self.height;
-        let fill_percent = ui_const::DEFAULT_FILL_PERCENT;
+    /// Replaces the simulated disk with the layout of a FAT disk image
+    pub fn load_image(&mut self, path: &str) -> Result<()> {
+        let volume = FatVolume::open(path)?;
+        self.use_layout(volume.layout());
+        self.image = Some(volume);
+        self.layout_path = Some(PathBuf::from(path));
+        Ok(())
+    }
+
+    /// Replaces the simulated disk with the real fragmentation of the files
+    /// below a directory, scaled to the grid
+    pub fn load_scan(&mut self, path: &str) -> Result<()> {
+        let cells = self.engine.width * self.engine.height;
+        let layout = scan::scan_layout(std::path::Path::new(path), cells)?;
+        self.use_layout(layout);
+        self.layout_path = Some(PathBuf::from(path));
+        Ok(())
+    }
+
+    fn use_layout(&mut self, layout: DiskLayout) {
+        let width = self.engine.width;
+        let height = layout.clusters.len().div_ceil(width.max(1));
+        let mut engine =
+            DefragEngine::with_layout(width, height, layout, self.engine.iops, self.engine.seed);
+        engine.method = self.engine.method;
+        engine.sort = self.engine.sort;
+        engine.geometry = self.engine.geometry;
+        self.engine = engine;
+    }
+
+    /// Writes a copy of the disk image with the files where the run left them
+    ///
+    /// Only moves that have completed are applied, so an interrupted run
+    /// still gives a consistent, partly defragmented image.
+    pub fn write_image(&self, path: &str) -> Result<()> {
+        let Some(ref volume) = self.image else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no disk image loaded",
+            ));
+        };
+        volume.write_defragmented(&self.engine.files, path)
+    }
+
+    /// Switches to drive `index` of the collection: a new disk sized for its
+    /// cluster count, its sound and mechanics, and a fresh analysis
+    pub fn select_drive(&mut self, index: usize) {
+        let Some(drive) = self.drive_collection.get_by_index(index).cloned() else {
+            return;
+        };
 
-        let num_pending = (total_clusters as f32 * fill_percent) as usize;
-        let num_bad = (total_clusters as f32 * ui_const::BAD_BLOCK_PERCENT) as usize;
+        let width = self.engine.width;
+        let mut engine = DefragEngine::new(
+            width,
+            drive.grid_height(width),
+            self.fill_override.unwrap_or(drive.config.fill_ratio),
+            drive.config.bad_ratio,
+            drive.iops(),
+            self.engine.seed,
+        );
+        engine.method = self.engine.method;
+        engine.sort = self.engine.sort;
+        engine.geometry = drive.config.geometry;
+        engine.stats.start_time = self.now();
+        engine.start_analysis();
+
+        self.engine = engine;
+        self.image = None;
+        self.layout_path = None;
+        self.current_drive = drive;
+        self.paused = false;
+        self.tune_audio();
+    }
 
-        self.clusters.clear();
-        for _ in 0..(num_pending.saturating_sub(2)) {
-            self.clusters.push(ClusterState::Pending);
+    /// Tunes the drive sounds to the current drive: clicks paced by its
+    /// IOPS, spindle pitched by its speed, silent while the run is paused,
+    /// at the volume and with the mutes of the user
+    fn tune_audio(&mut self) {
+        if let Some(ref mut audio) = self.audio {
+            audio.set_master_volume(self.volume);
+            audio.set_settings(self.audio_settings);
+            audio.set_iops(self.current_drive.iops());
+            audio.set_rpm(self.current_drive.config.geometry.rpm);
+            audio.set_paused(self.paused);
         }
-        self.clusters.push(ClusterState::Writing);
-        self.clusters.push(ClusterState::Reading);
-        while self.clusters.len() < total_clusters - num_bad {
-            self.clusters.push(ClusterState::Unused);
+    }
+
+    /// Starts defragmenting every drive of the collection, one after another
+    pub fn start_all_drives(&mut self) {
+        self.select_drive(0);
+        self.drive_sequence = Some(DriveSequence {
+            fragmented_before: self.engine.fragmented_files_percent(),
+            ..DriveSequence::default()
+        });
+    }
+
+    /// Records the finished drive of an "All drives" run and moves on to the
+    /// next one; returns whether a new drive was started
+    fn advance_drive_sequence(&mut self) -> bool {
+        let Some(mut sequence) = self.drive_sequence.take() else {
+            return false;
+        };
+        if sequence.finished {
+            self.drive_sequence = Some(sequence);
+            return false;
         }
-        self.clusters.shuffle(&mut rng);
 
-        let mut bad_positions: Vec<usize> = (0..self.clusters.len()).collect();
-        bad_positions.shuffle(&mut rng);
-        for &pos in bad_positions.iter().take(num_bad) {
-            self.clusters
-                .insert(pos.min(self.clusters.len()), ClusterState::Bad);
+        sequence.reports.push(DriveReport {
+            letter: self.current_drive.letter(),
+            label: self.current_drive.label.clone(),
+            elapsed: self.engine.elapsed(),
+            fragmented_before: sequence.fragmented_before,
+            fragmented_after: self.engine.fragmented_files_percent(),
+        });
+
+        let next = sequence.current + 1;
+        let started = next < self.drive_collection.get_all().len();
+        if started {
+            self.select_drive(next);
+            sequence.current = next;
+            sequence.fragmented_before = self.engine.fragmented_files_percent();
+        } else {
+            sequence.finished = true;
         }
-        self.clusters.truncate(total_clusters);
-        if !self.clusters.is_empty() {
-            self.clusters[0] = ClusterState::Unmovable;
+        self.drive_sequence = Some(sequence);
+        started
+    }
+
+    /// Shows an error in a message box, with a beep of the PC speaker in
+    /// the MS-DOS interface
+    pub fn show_error(&mut self, message: String) {
+        self.message = Some(message);
+        if self.ui_style == DefragStyle::MsDos {
+            if let Some(ref audio) = self.audio {
+                audio.play_error_beep();
+            }
         }
+    }
 
-        let total_to_defrag = self
-            .clusters
-            .iter()
-            .filter(|&&c| c == ClusterState::Pending)
-            .count()
-            + 2;
-        self.stats = DefragStats {
-            total_to_defrag,
-            clusters_defragged: 0,
-            start_time: Instant::now(),
-        };
+    /// Saves the disk map to `path` and reports the outcome in a message box
+    pub fn save_map(&mut self, path: &str) {
+        match crate::export::map::save_map(self, std::path::Path::new(path)) {
+            Ok(format) => {
+                self.message = Some(format!("Disk map saved to {} ({}).", path, format.name()))
+            }
+            Err(e) => self.show_error(format!("Cannot save {}: {}", path, e)),
+        }
+    }
 
-        self.phase = DefragPhase::Initializing;
-        self.animation_step = 0;
-        self.read_pos = None;
-        self.write_pos = None;
-        self.current_file_read_progress = None;
-        self.current_filename = None;
-        self.current_op_end_time = None;
-        self.status_message = "Initializing...".to_string();
-        self.paused = false;
-        self.file_provider = DosFileProvider::new();
+    /// Prints the disk map to a new file of the print directory and reports
+    /// the outcome in a message box
+    pub fn print_map(&mut self) {
+        match crate::export::print::print_map(self, &self.print_dir, self.print_format) {
+            Ok(path) => self.message = Some(format!("Disk map printed to {}.", path.display())),
+            Err(e) => self.show_error(format!(
+                "Cannot print to {}: {}",
+                self.print_dir.display(),
+                e
+            )),
+        }
+    }
 
-        self.free_space_cache.invalidate();
-        self.pending_cache_dirty = true;
+    /// Saves a PNG snapshot of the disk map to a new file of the snapshot
+    /// directory
+    pub fn save_snapshot(&mut self) -> Result<PathBuf> {
+        std::fs::create_dir_all(&self.snapshot_dir)?;
+        let (path, number) = snapshot::free_path(
+            &self.snapshot_dir,
+            self.current_drive.letter(),
+            self.next_snapshot,
+        )?;
+        snapshot::save_png(self, &path, self.snapshot_palette)?;
+        self.next_snapshot = number.saturating_add(1);
+        Ok(path)
     }
 
-    pub fn estimated_time_remaining(&self) -> Option<Duration> {
-        if self.stats.clusters_defragged == 0 || self.phase != DefragPhase::Defragmenting {
-            return None;
+    /// Sets the sound volume, from 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+        if let Some(ref mut audio) = self.audio {
+            audio.set_master_volume(self.volume);
         }
+    }
 
-        let elapsed = self.stats.start_time.elapsed();
-        let remaining = self
-            .stats
-            .total_to_defrag
-            .saturating_sub(self.stats.clusters_defragged);
+    /// Turns the volume up or down by `steps` presses of the volume keys
+    pub fn step_volume(&mut self, steps: i32) {
+        let step = audio_const::VOLUME_STEP;
+        let level = (self.volume / step).round() + steps as f32;
+        self.set_volume(level * step);
+    }
 
-        if remaining == 0 {
-            return Some(Duration::ZERO);
+    /// Mutes the kinds of sounds `settings` says are muted
+    pub fn set_audio_settings(&mut self, settings: AudioSettings) {
+        self.audio_settings = settings;
+        if let Some(ref mut audio) = self.audio {
+            audio.set_settings(settings);
         }
+    }
+
+    /// Mutes or unmutes a kind of sound and saves the choice to the user
+    /// configuration; the sound stays muted or not for this run when it
+    /// cannot be saved
+    pub fn set_sound_muted(&mut self, category: SoundCategory, muted: bool) -> Result<()> {
+        let mut settings = self.audio_settings;
+        settings.set_muted(category, muted);
+        self.set_audio_settings(settings);
+        let path = AudioSettings::path().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no configuration directory")
+        })?;
+        settings.save(&path)
+    }
 
-        let rate = self.stats.clusters_defragged as f64 / elapsed.as_secs_f64();
-        if rate <= 0.0 {
-            return None;
+    pub fn toggle_pause(&mut self) {
+        if matches!(
+            self.engine.phase,
+            DefragPhase::Defragmenting | DefragPhase::Analyzing
+        ) {
+            self.paused = !self.paused;
+            if let Some(ref mut audio) = self.audio {
+                audio.set_paused(self.paused);
+            }
         }
+    }
 
-        let remaining_secs = remaining as f64 / rate;
-        Some(Duration::from_secs_f64(remaining_secs))
+    pub fn toggle_demo_mode(&mut self) {
+        self.demo_mode = !self.demo_mode;
     }
 
-    pub fn progress_percent(&self) -> f32 {
-        if self.stats.total_to_defrag == 0 {
-            return 100.0;
-        }
-        (self.stats.clusters_defragged as f32 / self.stats.total_to_defrag as f32) * 100.0
+    pub fn restart(&mut self) {
+        self.engine.restart();
+        self.paused = false;
+        self.analysis_dialog = None;
+        self.tune_audio();
     }
 
     pub fn run(&mut self, term: &mut crate::ui::TuiWrapper, rx: mpsc::Receiver<()>) -> Result<()> {
@@ -495,7 +1130,7 @@ impl App {
         let mut last_tick = Instant::now();
         while self.running {
             // Only MS-DOS UI is allowed in terminal mode
-            term.draw(|frame| crate::ui::render_app(&self, frame))?;
+            term.draw(|frame| crate::ui::render_app(self, frame))?;
 
             if rx.try_recv().is_ok() {
                 self.running = false;
@@ -514,11 +1149,142 @@ impl App {
                             continue;
                         }
 
+                        if self.message.is_some() {
+                            if matches!(
+                                key.code,
+                                KeyCode::Enter | KeyCode::Esc | KeyCode::Char(' ')
+                            ) {
+                                self.message = None;
+                            }
+                            continue;
+                        }
+
+                        if let Some(ref mut name) = self.save_dialog {
+                            match key.code {
+                                KeyCode::Char(c) if name.chars().count() < 64 => name.push(c),
+                                KeyCode::Backspace => {
+                                    name.pop();
+                                }
+                                KeyCode::Enter if !name.trim().is_empty() => {
+                                    let path = name.trim().to_string();
+                                    self.save_dialog = None;
+                                    self.save_map(&path);
+                                }
+                                KeyCode::Esc => self.save_dialog = None,
+                                _ => {}
+                            }
+                            continue;
+                        }
+
+                        if let Some(selected) = self.method_dialog {
+                            match key.code {
+                                KeyCode::Up | KeyCode::Down | KeyCode::Tab => {
+                                    self.method_dialog = Some(match selected {
+                                        OptimizationMethod::FullOptimization => {
+                                            OptimizationMethod::UnfragmentFilesOnly
+                                        }
+                                        OptimizationMethod::UnfragmentFilesOnly => {
+                                            OptimizationMethod::FullOptimization
+                                        }
+                                    });
+                                }
+                                KeyCode::Enter | KeyCode::Char(' ') => {
+                                    self.engine.method = selected;
+                                    self.method_dialog = None;
+                                }
+                                KeyCode::Esc => {
+                                    self.method_dialog = None;
+                                }
+                                _ => {}
+                            }
+                            continue;
+                        }
+
+                        if self
+                            .drive_sequence
+                            .as_ref()
+                            .is_some_and(|sequence| sequence.finished)
+                        {
+                            if matches!(
+                                key.code,
+                                KeyCode::Enter | KeyCode::Esc | KeyCode::Char(' ')
+                            ) {
+                                self.running = false;
+                            }
+                            continue;
+                        }
+
+                        if let Some(optimize) = self.analysis_dialog {
+                            match key.code {
+                                KeyCode::Left | KeyCode::Right | KeyCode::Tab => {
+                                    self.analysis_dialog = Some(!optimize);
+                                }
+                                KeyCode::Enter | KeyCode::Char(' ') => {
+                                    self.analysis_dialog = None;
+                                    if optimize {
+                                        self.engine.proceed();
+                                    }
+                                }
+                                KeyCode::Esc => self.analysis_dialog = None,
+                                _ => {}
+                            }
+                            continue;
+                        }
+
+                        if let Some(ref mut dialog) = self.file_dialog {
+                            let files = &self.engine.files;
+                            match key.code {
+                                KeyCode::Up => dialog.move_selection(-1),
+                                KeyCode::Down => dialog.move_selection(1),
+                                KeyCode::PageUp => dialog.move_selection(-10),
+                                KeyCode::PageDown => dialog.move_selection(10),
+                                KeyCode::Home => dialog.selected = 0,
+                                KeyCode::End => dialog.move_selection(isize::MAX),
+                                KeyCode::Left => dialog.cycle_column(-1, files),
+                                KeyCode::Right | KeyCode::Tab => dialog.cycle_column(1, files),
+                                KeyCode::Char(' ') => {
+                                    dialog.descending = !dialog.descending;
+                                    dialog.sort(files);
+                                }
+                                KeyCode::Enter | KeyCode::Esc => self.file_dialog = None,
+                                _ => {}
+                            }
+                            continue;
+                        }
+
+                        if let Some(selected) = self.drive_dialog {
+                            // The drives, then "All drives"
+                            let count = self.drive_collection.get_all().len() + 1;
+                            match key.code {
+                                KeyCode::Up => {
+                                    self.drive_dialog = Some((selected + count - 1) % count);
+                                }
+                                KeyCode::Down | KeyCode::Tab => {
+                                    self.drive_dialog = Some((selected + 1) % count);
+                                }
+                                KeyCode::Enter | KeyCode::Char(' ') => {
+                                    self.drive_dialog = None;
+                                    if selected == count - 1 {
+                                        self.start_all_drives();
+                                    } else {
+                                        self.drive_sequence = None;
+                                        self.select_drive(selected);
+                                    }
+                                }
+                                KeyCode::Esc => {
+                                    self.drive_dialog = None;
+                                }
+                                _ => {}
+                            }
+                            continue;
+                        }
+
                         match key.code {
                             KeyCode::Char('q') | KeyCode::Esc => {
                                 if self.menu_open {
                                     self.menu_open = false;
                                 } else {
+                                    self.interrupted = key.code == KeyCode::Esc;
                                     self.running = false;
                                 }
                             }
@@ -530,67 +1296,65 @@ impl App {
                                     audio.toggle();
                                 } else {
                                     self.audio = AudioEngine::new();
+                                    self.tune_audio();
                                 }
                             }
+                            KeyCode::Char('+') | KeyCode::Char('=') => self.step_volume(1),
+                            KeyCode::Char('-') | KeyCode::Char('_') => self.step_volume(-1),
                             KeyCode::F(10) | KeyCode::Tab => {
                                 self.menu_open = !self.menu_open;
                                 if self.menu_open {
                                     self.selected_item = 0;
                                 }
                             }
-                            KeyCode::Left => {
-                                if self.menu_open {
-                                    self.selected_menu = if self.selected_menu == 0 {
-                                        4
-                                    } else {
-                                        self.selected_menu - 1
-                                    };
-                                    self.selected_item = 0;
-                                }
+                            KeyCode::Left if self.menu_open => {
+                                self.selected_menu = if self.selected_menu == 0 {
+                                    4
+                                } else {
+                                    self.selected_menu - 1
+                                };
+                                self.selected_item = 0;
                             }
-                            KeyCode::Right => {
-                                if self.menu_open {
-                                    self.selected_menu = (self.selected_menu + 1) % 5;
-                                    self.selected_item = 0;
-                                }
+                            KeyCode::Right if self.menu_open => {
+                                self.selected_menu = (self.selected_menu + 1) % 5;
+                                self.selected_item = 0;
                             }
-                            KeyCode::Up => {
-                                if self.menu_open {
-                                    let max_items =
-                                        crate::ui::get_menu_items(self.selected_menu).len();
-                                    self.selected_item = if self.selected_item == 0 {
-                                        max_items.saturating_sub(1)
-                                    } else {
-                                        self.selected_item - 1
-                                    };
-                                }
+                            KeyCode::Up if self.menu_open => {
+                                let max_items = crate::ui::get_menu_items(self.selected_menu).len();
+                                self.selected_item = if self.selected_item == 0 {
+                                    max_items.saturating_sub(1)
+                                } else {
+                                    self.selected_item - 1
+                                };
                             }
-                            KeyCode::Down => {
-                                if self.menu_open {
-                                    let max_items =
-                                        crate::ui::get_menu_items(self.selected_menu).len();
-                                    self.selected_item = (self.selected_item + 1) % max_items;
-                                }
+                            KeyCode::Down if self.menu_open => {
+                                let max_items = crate::ui::get_menu_items(self.selected_menu).len();
+                                self.selected_item = (self.selected_item + 1) % max_items;
                             }
-                            KeyCode::Enter => {
-                                if self.menu_open {
-                                    self.handle_menu_action();
-                                    self.menu_open = false;
-                                }
+                            KeyCode::Enter if self.menu_open => {
+                                self.handle_menu_action();
+                                self.menu_open = false;
                             }
-                            KeyCode::Char('p') | KeyCode::Char('P') | KeyCode::Char(' ') => {
-                                if !self.menu_open {
-                                    self.toggle_pause();
-                                }
+                            KeyCode::Char('p') | KeyCode::Char('P') | KeyCode::Char(' ')
+                                if !self.menu_open =>
+                            {
+                                self.toggle_pause();
                             }
-                            KeyCode::Char('r') | KeyCode::Char('R') => {
-                                if !self.menu_open {
-                                    self.restart();
-                                }
+                            KeyCode::Char('r') | KeyCode::Char('R') if !self.menu_open => {
+                                self.restart();
                             }
-                            KeyCode::Char('d') | KeyCode::Char('D') => {
-                                if !self.menu_open {
-                                    self.toggle_demo_mode();
+                            KeyCode::Char('d') | KeyCode::Char('D') if !self.menu_open => {
+                                self.toggle_demo_mode();
+                            }
+                            KeyCode::Char('c') | KeyCode::Char('C') if !self.menu_open => {
+                                match self.save_snapshot() {
+                                    Ok(path) => {
+                                        self.message =
+                                            Some(format!("Snapshot saved to {}.", path.display()))
+                                    }
+                                    Err(e) => {
+                                        self.show_error(format!("Cannot save snapshot: {}", e))
+                                    }
                                 }
                             }
                             _ => {}
@@ -608,167 +1372,69 @@ impl App {
     }
 
     pub fn update(&mut self) {
-        self.animation_step += 1;
         self.tick_rate = Duration::from_millis(animation::DEFAULT_TICK_RATE_MS);
 
-        if self.phase != DefragPhase::Defragmenting {
-            self.status_message = self.get_phase_status().to_string();
-        }
-
-        match self.phase {
-            DefragPhase::Initializing => {
-                if self.animation_step > 20 {
-                    self.phase = DefragPhase::Analyzing;
-                    self.animation_step = 0;
+        // Unattended runs go on without asking
+        self.engine.confirm_analysis =
+            !self.demo_mode && !self.unattended && self.drive_sequence.is_none();
+        let phase = self.engine.phase;
+        self.engine.update(self.now());
+        self.play_events();
+        self.ticks += 1;
+        if let Some(every) = self.snapshot_every {
+            if self.ticks.is_multiple_of(u64::from(every.max(1))) {
+                if let Err(e) = self.save_snapshot() {
+                    // Stop the time-lapse rather than failing on every tick
+                    self.show_error(format!("Cannot save snapshot: {}", e));
+                    self.snapshot_every = None;
                 }
             }
-            DefragPhase::Analyzing => {
-                let total_clusters = self.width * self.height;
-                let scan_pos = (self.animation_step as usize * 5).min(total_clusters - 1);
-                self.read_pos = Some(scan_pos);
-
-                if self.animation_step % 3 == 0 {
-                    if let Some(ref audio) = self.audio {
-                        audio.play_seek();
-                    }
-                }
-
-                if self.animation_step > (total_clusters as u64 / 5) + 10 {
-                    self.read_pos = None;
-                    self.phase = DefragPhase::Defragmenting;
-                    self.animation_step = 0;
-                    self.current_op_end_time = Some(Instant::now());
-                }
+        }
+        if phase != DefragPhase::Analyzed && self.engine.phase == DefragPhase::Analyzed {
+            self.analysis_dialog = Some(true);
+        }
+        if phase != DefragPhase::Finished
+            && self.engine.phase == DefragPhase::Finished
+            && self.ui_style == DefragStyle::MsDos
+        {
+            if let Some(ref audio) = self.audio {
+                audio.play_completion_beep();
             }
-            DefragPhase::Defragmenting => {
-                if self
-                    .current_op_end_time
-                    .map_or(true, |t| Instant::now() >= t)
-                {
-                    let mut rng = rand::thread_rng();
-                    let clusters_per_operation = (self.current_drive.iops() as usize).max(1);
-
-                    if self.current_file_read_progress.is_none() {
-                        let pending_indices: Vec<usize> = self
-                            .clusters
-                            .iter()
-                            .enumerate()
-                            .filter(|&(_, c)| *c == ClusterState::Pending)
-                            .map(|(i, _)| i)
-                            .collect();
-
-                        if let Some(pending_idx) = pending_indices.choose(&mut rng).copied() {
-                            self.current_filename = self.file_provider.get_random_filename();
-                            let file_size = rng.gen_range(1..=5);
-
-                            let base_duration_ms = rng.gen_range(1000..=3000);
-                            let iops_factor = self.current_drive.iops().max(1) as f64;
-                            let final_duration = Duration::from_millis(
-                                (base_duration_ms as f64 / iops_factor) as u64,
-                            );
-                            self.current_op_end_time = Some(Instant::now() + final_duration);
-
-                            self.clusters[pending_idx] = ClusterState::Reading;
-                            self.read_pos = Some(pending_idx);
-                            if let Some(ref audio) = self.audio {
-                                audio.play_seek();
-                            }
+        }
 
-                            if let Some(unused_start_idx) =
-                                self.find_contiguous_unused_clusters(file_size)
-                            {
-                                for i in 0..file_size.min(clusters_per_operation) {
-                                    if unused_start_idx + i < self.clusters.len() {
-                                        self.clusters[unused_start_idx + i] = ClusterState::Writing;
-                                    }
-                                }
-                                self.write_pos = Some(unused_start_idx);
-                                self.current_file_read_progress =
-                                    Some(FileDefragPhase::Reading { progress: 0 });
-                                self.status_message = format!(
-                                    "Reading {}...",
-                                    self.current_filename.as_deref().unwrap_or("file")
-                                );
-                            } else {
-                                self.clusters[pending_idx] = ClusterState::Used;
-                                self.stats.clusters_defragged += 1;
-                                self.read_pos = None;
-                                self.current_filename = None;
-                                if let Some(ref audio) = self.audio {
-                                    audio.play_write();
-                                }
-                                self.current_op_end_time = Some(Instant::now());
-                            }
-                        } else {
-                            self.phase = DefragPhase::Finished;
-                            self.current_filename = None;
-                            self.read_pos = None;
-                            self.write_pos = None;
-                        }
-                    } else {
-                        match &mut self.current_file_read_progress {
-                            Some(FileDefragPhase::Reading { .. }) => {
-                                if let Some(reading_idx) = self.read_pos {
-                                    if self.clusters[reading_idx] == ClusterState::Reading {
-                                        self.clusters[reading_idx] = ClusterState::Unused;
-                                        if let Some(ref audio) = self.audio {
-                                            audio.play_read();
-                                        }
-                                    }
-                                }
-                                self.current_file_read_progress =
-                                    Some(FileDefragPhase::Writing { progress: 0 });
-                                self.status_message = format!(
-                                    "Writing {}...",
-                                    self.current_filename.as_deref().unwrap_or("file")
-                                );
-                            }
-                            Some(FileDefragPhase::Writing { .. }) => {
-                                if let Some(write_idx) = self.write_pos {
-                                    if self.clusters[write_idx] == ClusterState::Writing {
-                                        self.clusters[write_idx] = ClusterState::Used;
-                                        self.stats.clusters_defragged += 1;
-                                        if let Some(ref audio) = self.audio {
-                                            audio.play_write();
-                                        }
-                                    }
-                                }
-                                self.current_file_read_progress = Some(FileDefragPhase::Completed);
-                                self.status_message = format!(
-                                    "Finishing {}...",
-                                    self.current_filename.as_deref().unwrap_or("file")
-                                );
-                            }
-                            Some(FileDefragPhase::Completed) => {
-                                self.current_file_read_progress = None;
-                                self.current_filename = None;
-                                self.current_op_end_time = Some(Instant::now());
-                                self.status_message = "Looking for next file...".to_string();
-                            }
-                            None => {}
-                        }
-                    }
-                } else {
-                    let dots = ".".repeat((self.animation_step % 4) as usize);
-                    let base_message = match self.current_file_read_progress {
-                        Some(FileDefragPhase::Reading { .. }) => "Reading",
-                        Some(FileDefragPhase::Writing { .. }) => "Writing",
-                        _ => "Processing",
-                    };
-                    self.status_message = format!(
-                        "{} {}{}",
-                        base_message,
-                        self.current_filename.as_deref().unwrap_or("file"),
-                        dots
-                    );
-                }
+        if self.engine.phase == DefragPhase::Finished {
+            if self.engine.animation_step > animation::FINISH_WAIT_TICKS / 2
+                && self.advance_drive_sequence()
+            {
+                return;
             }
-            DefragPhase::Finished => {
-                if self.demo_mode && self.animation_step > animation::FINISH_WAIT_TICKS / 2 {
-                    self.restart();
-                } else if !self.demo_mode && self.animation_step > animation::FINISH_WAIT_TICKS {
-                    self.running = false;
-                }
+            // The MS-DOS interface keeps the summary of an "All drives" run
+            // on screen until it is dismissed
+            if self.ui_style == DefragStyle::MsDos
+                && !self.unattended
+                && self.drive_sequence.as_ref().is_some_and(|s| s.finished)
+            {
+                return;
+            }
+            if self.demo_mode && self.engine.animation_step > animation::FINISH_WAIT_TICKS / 2 {
+                self.restart();
+            } else if !self.demo_mode && self.engine.animation_step > animation::FINISH_WAIT_TICKS {
+                self.running = false;
+            }
+        }
+    }
+
+    /// Plays the disk sounds matching the engine's latest activity
+    fn play_events(&mut self) {
+        let events = self.engine.take_events();
+        let Some(ref audio) = self.audio else {
+            return;
+        };
+        for event in events {
+            match event {
+                SimEvent::Seek { distance } => audio.play_seek(distance),
+                SimEvent::Read { clusters } => audio.play_read(clusters),
+                SimEvent::Write { clusters } => audio.play_write(clusters),
             }
         }
     }
@@ -776,15 +1442,55 @@ impl App {
     fn handle_menu_action(&mut self) {
         match (self.selected_menu, self.selected_item) {
             (0, 0) => {
-                self.restart();
+                if self.engine.phase == DefragPhase::Analyzed {
+                    self.engine.proceed();
+                } else {
+                    self.restart();
+                }
+            }
+            (0, 1) if self.layout_path.is_some() => {
+                // Another drive would replace the disk read from the image
+                self.show_error("Drive... is not available for a disk image or a scan.".into());
+            }
+            (0, 1) => {
+                let current = self.current_drive.letter();
+                let drives = self.drive_collection.get_all();
+                self.drive_dialog = Some(
+                    drives
+                        .iter()
+                        .position(|d| d.letter() == current)
+                        .unwrap_or(0),
+                );
+            }
+            (0, 2) => {
+                self.method_dialog = Some(self.engine.method);
             }
             (0, 4) => {
                 self.running = false;
             }
             (1, 0) => {
-                if self.phase != DefragPhase::Analyzing {
-                    self.phase = DefragPhase::Analyzing;
-                    self.animation_step = 0;
+                self.engine.start_analysis();
+            }
+            (1, 1) => {
+                self.file_dialog = Some(FileListDialog::new(&self.engine.files));
+            }
+            (2, 0) => {
+                self.print_map();
+            }
+            (2, 1) => {
+                self.save_dialog = Some(format!("DEFRAG-{}.TXT", self.current_drive.letter()));
+            }
+            (3, item) => {
+                let sort = &mut self.engine.sort;
+                match item {
+                    0 => sort.key = SortKey::Unsorted,
+                    1 => sort.key = SortKey::Name,
+                    2 => sort.key = SortKey::Extension,
+                    3 => sort.key = SortKey::Date,
+                    4 => sort.key = SortKey::Size,
+                    6 => sort.order = SortOrder::Ascending,
+                    7 => sort.order = SortOrder::Descending,
+                    _ => {}
                 }
             }
             (4, 0) | (4, 1) => {
@@ -796,80 +1502,16 @@ impl App {
 }
 
 impl App {
-    fn find_contiguous_unused_clusters(&mut self, size: usize) -> Option<usize> {
-        if size == 0 {
-            return None;
-        }
-
-        let mut current_run = 0;
-        let mut start_pos: Option<usize> = None;
-
-        for (i, &cluster) in self.clusters.iter().enumerate() {
-            if cluster == ClusterState::Unused {
-                if current_run == 0 {
-                    start_pos = Some(i);
-                }
-                current_run += 1;
-
-                if current_run >= size {
-                    return start_pos;
-                }
-            } else {
-                current_run = 0;
-                start_pos = None;
-            }
-        }
-
-        None
-    }
-
-    fn invalidate_caches(&mut self) {
-        self.free_space_cache.invalidate();
-        self.pending_cache_dirty = true;
-    }
-
-    fn get_pending_indices(&mut self) -> &[usize] {
-        if self.pending_cache_dirty {
-            self.pending_indices_cache = self
-                .clusters
-                .iter()
-                .enumerate()
-                .filter(|&(_, c)| *c == ClusterState::Pending)
-                .map(|(i, _)| i)
-                .collect();
-            self.pending_cache_dirty = false;
-        }
-        &self.pending_indices_cache
-    }
-
-    fn find_next_cluster_in_file(&self, start_pos: usize, state: ClusterState) -> Option<usize> {
-        for i in (start_pos + 1)..self.clusters.len() {
-            if self.clusters[i] == state {
-                return Some(i);
-            }
-        }
-        None
-    }
-
-    pub fn count_clusters(&self, state: ClusterState) -> usize {
-        self.clusters.iter().filter(|&&c| c == state).count()
-    }
-
-    pub fn fragmentation_percent(&self) -> f32 {
-        let pending = self.count_clusters(ClusterState::Pending);
-        let total_data = pending + self.count_clusters(ClusterState::Used);
-        if total_data == 0 {
-            return 0.0;
-        }
-        pending as f32 / total_data as f32
-    }
-
-    fn get_phase_status(&self) -> &'static str {
-        match self.phase {
-            DefragPhase::Initializing => "Initializing...",
-            DefragPhase::Analyzing => "Analyzing disk...",
-            DefragPhase::Defragmenting => "Defragmenting...",
-            DefragPhase::Finished => "Complete",
+    /// Clusters of the file highlighted in the "File fragmentation..."
+    /// dialog, while they are flashed on; empty otherwise
+    pub fn flashing_clusters(&self) -> &[usize] {
+        let flash_on = (self.now().as_millis() / animation::FLASH_PERIOD_MS).is_multiple_of(2);
+        match self.file_dialog {
+            Some(ref dialog) if flash_on => dialog
+                .selected_file()
+                .and_then(|file| self.engine.files.get(file))
+                .map_or(&[], |file| &file.clusters),
+            _ => &[],
         }
     }
 
@@ -877,7 +1519,7 @@ impl App {
         if self.paused {
             return "Paused";
         }
-        &self.status_message
+        self.engine.status_message()
     }
 }
 
@@ -897,3 +1539,346 @@ pub fn parse_size(size_str: &str) -> Result<(usize, usize)> {
         .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid height"))?;
     Ok((width, height))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SharedClock;
+
+    #[test]
+    fn test_drives_file_adds_and_replaces_drives() {
+        let mut drives = DiskDriveCollection::new();
+        drives
+            .add_from(
+                r#"
+                [[drive]]
+                letter = "g"
+                label = "Zip 100"
+                media = "floppy"
+                capacity_mb = 100
+                iops = 1
+                fill_ratio = 0.4
+                cylinders = 96
+
+                [[drive]]
+                letter = "C"
+                iops = 5
+                "#,
+            )
+            .unwrap();
+
+        let zip = drives.select('G').unwrap();
+        assert_eq!(zip.name, "Zip 100 (100MB, 1 IOPS)");
+        assert_eq!(zip.config.media, disk::MediaType::Floppy);
+        assert_eq!(zip.config.fill_ratio, 0.4);
+        assert_eq!(zip.config.geometry.cylinders, 96);
+        assert_eq!(zip.config.geometry.rpm, disk::FLOPPY_GEOMETRY.rpm);
+        assert_eq!(drives.get_all().len(), 5);
+        assert_eq!(drives.get_default().iops(), 5);
+
+        let error = drives.select('Z').unwrap_err().to_string();
+        assert!(error.contains("unknown drive Z"), "{}", error);
+        assert!(error.contains("Z (available: C, D, E, F, G)"), "{}", error);
+
+        assert!(drives
+            .add_from("[[drive]]\nletter = \"H\"\nturbo = true\n")
+            .is_err());
+        assert!(drives.add_from("[[drive]]\nfill_ratio = 1.5\n").is_err());
+    }
+
+    #[test]
+    fn test_drives_file_errors_name_the_line() {
+        let mut drives = DiskDriveCollection::new();
+        let error = |drives: &mut DiskDriveCollection, text: &str| {
+            drives.add_from(text).unwrap_err().to_string()
+        };
+
+        let text = "[[drive]]\nletter = \"H\"\ncapacity_mb = 0\n";
+        assert_eq!(
+            error(&mut drives, text),
+            "line 3: drive H: `capacity_mb` must be above 0"
+        );
+        let text = "[[drive]]\nletter = \"H\"\ncluster_count = 5000000\n";
+        assert_eq!(
+            error(&mut drives, text),
+            "line 3: drive H: `cluster_count` must be between 1 and 1000000"
+        );
+        let text = "[[drive]]\nletter = \"H\"\n\nrpm = 0\n";
+        assert_eq!(
+            error(&mut drives, text),
+            "line 4: drive H: `rpm` must be between 60 and 20000"
+        );
+        let text = "[[drive]]\nletter = \"H\"\nmedia = \"ssd\"\nrpm = 7200\n";
+        assert_eq!(
+            error(&mut drives, text),
+            "line 4: drive H: `rpm` must be 0 for ssd media"
+        );
+
+        // Solid-state drives have no spindle
+        drives
+            .add_from("[[drive]]\nletter = \"H\"\nmedia = \"ssd\"\nrpm = 0\n")
+            .unwrap();
+        assert_eq!(drives.select('H').unwrap().config.geometry.rpm, 0);
+    }
+
+    #[test]
+    fn test_file_list_sorts_by_column() {
+        let files = vec![
+            FileFragment::new("B.TXT", vec![10, 11, 20, 30]),
+            FileFragment::new("A.TXT", vec![1, 2, 3]),
+            FileFragment::new("C.TXT", vec![5, 7]),
+        ];
+        assert_eq!(files[0].fragment_count(), 3);
+
+        let mut dialog = FileListDialog::new(&files);
+        assert_eq!(dialog.rows, [0, 2, 1]);
+
+        // The highlight follows its file when the order changes
+        dialog.move_selection(1);
+        dialog.cycle_column(-1, &files);
+        assert_eq!(dialog.column, FileColumn::Name);
+        assert_eq!(dialog.rows, [2, 0, 1]);
+        assert_eq!(dialog.selected_file(), Some(2));
+
+        dialog.descending = false;
+        dialog.sort(&files);
+        assert_eq!(dialog.rows, [1, 0, 2]);
+        dialog.move_selection(10);
+        assert_eq!(dialog.selected, 2);
+    }
+
+    #[test]
+    fn test_all_drives_run_reports_every_drive() {
+        let drives = DiskDriveCollection::new();
+        let mut app = App::new(
+            40,
+            10,
+            0.5,
+            false,
+            drives.get_default().clone(),
+            DefragStyle::MsDos,
+            Some(3),
+        );
+        let clock = SharedClock::new();
+        app.set_clock(Box::new(clock.clone()));
+
+        app.start_all_drives();
+        while !app.drive_sequence.as_ref().unwrap().finished {
+            clock.advance(Duration::from_millis(500));
+            app.update();
+            assert!(app.running);
+        }
+
+        let reports = &app.drive_sequence.as_ref().unwrap().reports;
+        let letters: Vec<char> = reports.iter().map(|r| r.letter).collect();
+        assert_eq!(letters, ['C', 'D', 'E', 'F']);
+        for report in reports {
+            assert!(report.fragmented_before > 0.0);
+            assert_eq!(report.fragmented_after, 0.0);
+            assert!(report.elapsed > Duration::ZERO);
+        }
+        // Drive F has 4096 clusters, rounded up to whole 40-cluster rows
+        assert_eq!(app.engine.clusters.len(), 4120);
+    }
+
+    #[test]
+    fn test_fill_override_applies_to_every_drive() {
+        let drives = DiskDriveCollection::new();
+        let mut app = App::new(
+            40,
+            10,
+            0.5,
+            false,
+            drives.get_default().clone(),
+            DefragStyle::MsDos,
+            Some(3),
+        );
+        app.fill_override = Some(0.2);
+        app.start_all_drives();
+        assert_eq!(app.engine.fill_percent, 0.2);
+        app.select_drive(1);
+        assert_eq!(app.engine.fill_percent, 0.2);
+    }
+
+    #[test]
+    fn test_windows_styles_wait_for_the_analysis_to_be_confirmed() {
+        let drives = DiskDriveCollection::new();
+        let mut app = App::new(
+            40,
+            10,
+            0.5,
+            false,
+            drives.get_default().clone(),
+            DefragStyle::Windows98,
+            Some(3),
+        );
+        let clock = SharedClock::new();
+        app.set_clock(Box::new(clock.clone()));
+        app.engine.start_analysis();
+        for _ in 0..200 {
+            clock.advance(Duration::from_millis(500));
+            app.update();
+        }
+        assert_eq!(app.engine.phase, DefragPhase::Analyzed);
+    }
+
+    #[test]
+    fn test_drive_dialog_keeps_a_loaded_image() {
+        let drives = DiskDriveCollection::new();
+        let mut app = App::new(
+            40,
+            10,
+            0.5,
+            false,
+            drives.get_default().clone(),
+            DefragStyle::MsDos,
+            Some(3),
+        );
+        app.layout_path = Some(PathBuf::from("floppy.img"));
+        let clusters = app.engine.clusters.clone();
+
+        (app.selected_menu, app.selected_item) = (0, 1);
+        app.handle_menu_action();
+        assert_eq!(app.drive_dialog, None);
+        assert!(app.message.is_some());
+        assert_eq!(app.engine.clusters, clusters);
+    }
+
+    #[test]
+    fn test_resumed_image_run_needs_its_image() {
+        let drives = DiskDriveCollection::new();
+        let new_app = || {
+            App::new(
+                40,
+                10,
+                0.5,
+                false,
+                drives.get_default().clone(),
+                DefragStyle::MsDos,
+                Some(3),
+            )
+        };
+        let dir = std::env::temp_dir();
+        let image = dir.join(format!("defrag-resume-{}.img", std::process::id()));
+        std::fs::write(&image, crate::fat::tests::sample_floppy()).unwrap();
+        let path = dir.join(format!("defrag-resume-{}.json", std::process::id()));
+        let mut app = new_app();
+        app.load_image(image.to_str().unwrap()).unwrap();
+        app.save_session(&path).unwrap();
+
+        let mut resumed = new_app();
+        let error = resumed.resume(&path).unwrap_err();
+        assert!(error.to_string().contains("--image"), "{}", error);
+        resumed.load_image(image.to_str().unwrap()).unwrap();
+        resumed.resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&image).unwrap();
+        assert_eq!(resumed.engine.clusters, app.engine.clusters);
+    }
+
+    #[test]
+    fn test_resumed_run_goes_on_where_it_stopped() {
+        let drives = DiskDriveCollection::new();
+        let new_app = |seed| {
+            let mut app = App::new(
+                30,
+                6,
+                0.5,
+                false,
+                drives.select('D').unwrap().clone(),
+                DefragStyle::MsDos,
+                Some(seed),
+            );
+            app.unattended = true;
+            app
+        };
+        let step = Duration::from_millis(50);
+        let mut app = new_app(8);
+        app.engine.method = OptimizationMethod::UnfragmentFilesOnly;
+        app.engine.sort = FileSort::new(SortKey::Size, SortOrder::Descending);
+        let clock = SharedClock::new();
+        app.set_clock(Box::new(clock.clone()));
+        while app.engine.stats.clusters_defragged == 0 {
+            clock.advance(step);
+            app.update();
+        }
+        let path = std::env::temp_dir().join(format!("defrag-state-{}.json", std::process::id()));
+        app.save_session(&path).unwrap();
+
+        // A later session, whose clock starts over, on another disk
+        let mut resumed = new_app(9);
+        clock.advance(Duration::from_secs(3600));
+        resumed.set_clock(Box::new(SharedClock::new()));
+        resumed.resume(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resumed.current_drive.letter(), 'D');
+        assert_eq!(resumed.engine.elapsed(), app.engine.elapsed());
+        assert_eq!(resumed.engine.method, app.engine.method);
+        assert_eq!(resumed.engine.sort, app.engine.sort);
+
+        let resumed_clock = SharedClock::new();
+        resumed.set_clock(Box::new(resumed_clock.clone()));
+        clock.advance(Duration::from_secs(1));
+        app.set_clock(Box::new(clock.clone()));
+        while app.running {
+            clock.advance(step);
+            resumed_clock.advance(step);
+            app.update();
+            resumed.update();
+            assert_eq!(resumed.engine.clusters, app.engine.clusters);
+            assert_eq!(resumed.engine.current_filename, app.engine.current_filename);
+        }
+        assert_eq!(resumed.engine.phase, DefragPhase::Finished);
+        assert_eq!(resumed.engine.elapsed(), app.engine.elapsed());
+    }
+
+    #[test]
+    fn test_resumed_all_drives_run_goes_on() {
+        let drives = DiskDriveCollection::new();
+        let new_app = || {
+            App::new(
+                40,
+                10,
+                0.5,
+                false,
+                drives.get_default().clone(),
+                DefragStyle::MsDos,
+                Some(3),
+            )
+        };
+        let mut app = new_app();
+        let clock = SharedClock::new();
+        app.set_clock(Box::new(clock.clone()));
+        app.start_all_drives();
+        while app.current_drive.letter() != 'D' {
+            clock.advance(Duration::from_millis(500));
+            app.update();
+        }
+        let path = std::env::temp_dir().join(format!("defrag-all-{}.json", std::process::id()));
+        app.save_session(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+
+        let mut resumed = new_app();
+        let clock = SharedClock::new();
+        resumed.set_clock(Box::new(clock.clone()));
+        resumed.resume(&path).unwrap();
+        assert_eq!(resumed.current_drive.letter(), 'D');
+        while !resumed.drive_sequence.as_ref().unwrap().finished {
+            clock.advance(Duration::from_millis(500));
+            resumed.update();
+        }
+        let reports = &resumed.drive_sequence.as_ref().unwrap().reports;
+        let letters: Vec<char> = reports.iter().map(|r| r.letter).collect();
+        assert_eq!(letters, ['C', 'D', 'E', 'F']);
+
+        // A drive the collection does not have
+        std::fs::write(&path, text.replace("\"drive\":\"D\"", "\"drive\":\"Z\"")).unwrap();
+        let error = new_app().resume(&path).unwrap_err();
+        assert!(
+            error.to_string().starts_with("unknown drive Z"),
+            "{}",
+            error
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,340 @@
+use crate::constants::audio;
+use rodio::dynamic_mixer::{self, DynamicMixerController};
+use rodio::source::Zero;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, PlayError, Sink, Source};
+use std::io::Cursor;
+use std::sync::Arc;
+use synth::{HeadSound, SpeakerBeep, SpindleWhine, SAMPLE_RATE};
+
+pub use settings::{AudioSettings, SoundCategory};
+
+pub mod settings;
+pub mod synth;
+
+// Embedded resources module for audio files
+mod resources {
+    use std::io::Cursor;
+
+    /// Embedded mouse down sound file (mousedown.mp3)
+    pub const MOUSE_DOWN_SOUND: &[u8] = include_bytes!("../../static/audio/mousedown.mp3");
+
+    /// Embedded mouse up sound file (mouseup.mp3)
+    pub const MOUSE_UP_SOUND: &[u8] = include_bytes!("../../static/audio/mouseup.mp3");
+
+    /// Embedded chimes sound file (chimes.mp3)
+    pub const CHIMES_SOUND: &[u8] = include_bytes!("../../static/audio/chimes.mp3");
+
+    /// Embedded loop sound file (loop.mp3)
+    pub const LOOP_SOUND: &[u8] = include_bytes!("../../static/audio/loop.mp3");
+
+    /// A structure to hold all embedded audio resources
+    pub struct EmbeddedAudioResources;
+
+    impl EmbeddedAudioResources {
+        /// Returns a cursor for the mouse down sound file
+        pub fn mouse_down_sound() -> Cursor<&'static [u8]> {
+            Cursor::new(MOUSE_DOWN_SOUND)
+        }
+
+        /// Returns a cursor for the mouse up sound file
+        pub fn mouse_up_sound() -> Cursor<&'static [u8]> {
+            Cursor::new(MOUSE_UP_SOUND)
+        }
+
+        /// Returns a cursor for the chimes sound file
+        pub fn chimes_sound() -> Cursor<&'static [u8]> {
+            Cursor::new(CHIMES_SOUND)
+        }
+
+        /// Returns a cursor for the loop sound file
+        pub fn loop_sound() -> Cursor<&'static [u8]> {
+            Cursor::new(LOOP_SOUND)
+        }
+    }
+}
+
+use resources::EmbeddedAudioResources;
+
+/// Mixing channels of the audio engine, each with its own volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Looping drive ambience and spindle whine
+    Ambience,
+    /// Clicks and chatter of the heads
+    Head,
+    /// Button clicks, chimes and beeps of the interface
+    Interface,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Ambience, Channel::Head, Channel::Interface];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Channel of one-shot sounds, which start at once and play over each other
+struct Mixer {
+    sink: Sink,
+    input: Arc<DynamicMixerController<f32>>,
+}
+
+impl Mixer {
+    fn new(handle: &OutputStreamHandle) -> Result<Self, PlayError> {
+        let sink = Sink::try_new(handle)?;
+        let (input, output) = dynamic_mixer::mixer(2, SAMPLE_RATE);
+        // Silence keeps the mixer going between sounds
+        input.add(Zero::<f32>::new(2, SAMPLE_RATE));
+        sink.append(output);
+        Ok(Self { sink, input })
+    }
+
+    fn play<S>(&self, sound: S)
+    where
+        S: Source<Item = f32> + Send + 'static,
+    {
+        self.input.add(sound);
+    }
+}
+
+/// Audio engine synthesizing the drive sounds and playing the embedded
+/// interface sounds
+pub struct AudioEngine {
+    _stream: OutputStream,
+    handle: OutputStreamHandle,
+    /// Looping ambience, replaced when the drive changes
+    ambience: Sink,
+    head: Mixer,
+    interface: Mixer,
+    /// Volume of each channel, from 0 to 1
+    volumes: [f32; 3],
+    /// Volume of the whole mix, from 0 to 1
+    master: f32,
+    settings: AudioSettings,
+    enabled: bool,
+    /// Whether the simulation is paused, which silences the drive
+    paused: bool,
+    /// Speed of the head sounds, following the disk IOPS (higher IOPS =
+    /// faster clicks)
+    playback_rate: f32,
+    /// Spindle speed of the drive, 0 for drives without moving parts
+    rpm: u32,
+}
+
+impl AudioEngine {
+    /// Creates a new audio engine with default playback rate of 1.0
+    pub fn new() -> Option<Self> {
+        let (stream, handle) = OutputStream::try_default().ok()?;
+        let audio = Self {
+            ambience: Sink::try_new(&handle).ok()?,
+            head: Mixer::new(&handle).ok()?,
+            interface: Mixer::new(&handle).ok()?,
+            _stream: stream,
+            handle,
+            volumes: [
+                audio::AMBIENCE_VOLUME,
+                audio::HEAD_VOLUME,
+                audio::INTERFACE_VOLUME,
+            ],
+            master: audio::DEFAULT_VOLUME,
+            settings: AudioSettings::default(),
+            enabled: true,
+            paused: false,
+            playback_rate: 1.0, // Default playback rate
+            rpm: 0,
+        };
+        audio.apply_state();
+        Some(audio)
+    }
+
+    /// Updates the playback rate based on the disk IOPS (Input/Output Operations Per Second)
+    /// Higher IOPS means faster head sounds, simulating faster disk performance
+    pub fn set_iops(&mut self, iops: u32) {
+        self.playback_rate = audio::calculate_playback_rate(iops);
+    }
+
+    /// Sets the spindle speed of the drive, which pitches its whine; drives
+    /// without moving parts (0 rpm) are silent
+    pub fn set_rpm(&mut self, rpm: u32) {
+        if rpm != self.rpm {
+            self.rpm = rpm;
+            self.play_loop_sound();
+        }
+    }
+
+    pub fn volume(&self, channel: Channel) -> f32 {
+        self.volumes[channel.index()]
+    }
+
+    /// Sets the volume of `channel`, from 0 to 1
+    pub fn set_volume(&mut self, channel: Channel, volume: f32) {
+        self.volumes[channel.index()] = volume.clamp(0.0, 1.0);
+        self.apply_state();
+    }
+
+    pub fn master_volume(&self) -> f32 {
+        self.master
+    }
+
+    /// Sets the volume of the whole mix, from 0 to 1
+    pub fn set_master_volume(&mut self, volume: f32) {
+        self.master = volume.clamp(0.0, 1.0);
+        self.apply_state();
+    }
+
+    pub fn settings(&self) -> AudioSettings {
+        self.settings
+    }
+
+    /// Mutes the kinds of sounds `settings` says are muted
+    pub fn set_settings(&mut self, settings: AudioSettings) {
+        self.settings = settings;
+        self.apply_state();
+    }
+
+    /// Pauses or resumes the drive sounds along with the simulation; the
+    /// interface keeps its sounds
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.apply_state();
+    }
+
+    /// Passes volumes, pause and on/off to the channels
+    fn apply_state(&self) {
+        let drive_playing = self.enabled && !self.paused;
+        let ambience_playing = drive_playing && !self.settings.is_muted(SoundCategory::Ambience);
+        for (sink, playing, channel) in [
+            (&self.ambience, ambience_playing, Channel::Ambience),
+            (&self.head.sink, drive_playing, Channel::Head),
+            (&self.interface.sink, self.enabled, Channel::Interface),
+        ] {
+            sink.set_volume(self.master * self.volume(channel));
+            if playing {
+                sink.play();
+            } else {
+                sink.pause();
+            }
+        }
+    }
+
+    /// Plays an embedded sound of `category` from memory on `mixer`
+    fn play_embedded_sound(
+        &self,
+        mixer: &Mixer,
+        category: SoundCategory,
+        sound_data: Cursor<&'static [u8]>,
+    ) {
+        if !self.enabled || self.settings.is_muted(category) {
+            return;
+        }
+
+        // Create a decoder from the embedded sound data
+        if let Ok(source) = Decoder::new(sound_data) {
+            mixer.play(source.convert_samples());
+        }
+    }
+
+    /// Plays a sound of the heads, which drives without moving parts do not
+    /// make
+    fn play_head_sound(&self, sound: HeadSound) {
+        if self.enabled
+            && !self.paused
+            && self.rpm > 0
+            && !self.settings.is_muted(SoundCategory::Head)
+        {
+            self.head.play(sound);
+        }
+    }
+
+    /// Plays mouse down sound
+    pub fn play_mouse_down(&self) {
+        self.play_embedded_sound(
+            &self.interface,
+            SoundCategory::Clicks,
+            EmbeddedAudioResources::mouse_down_sound(),
+        );
+    }
+
+    /// Plays mouse up sound
+    pub fn play_mouse_up(&self) {
+        self.play_embedded_sound(
+            &self.interface,
+            SoundCategory::Clicks,
+            EmbeddedAudioResources::mouse_up_sound(),
+        );
+    }
+
+    /// Plays the chimes of a finished run
+    pub fn play_chimes(&self) {
+        self.play_embedded_sound(
+            &self.interface,
+            SoundCategory::Chime,
+            EmbeddedAudioResources::chimes_sound(),
+        );
+    }
+
+    /// Beeps the PC speaker for an error
+    pub fn play_error_beep(&self) {
+        self.play_beep(SpeakerBeep::error());
+    }
+
+    /// Beeps the PC speaker for a finished run
+    pub fn play_completion_beep(&self) {
+        self.play_beep(SpeakerBeep::completion());
+    }
+
+    fn play_beep(&self, beep: SpeakerBeep) {
+        if self.enabled && !self.settings.is_muted(SoundCategory::Beep) {
+            self.interface.play(beep);
+        }
+    }
+
+    /// Toggles audio on/off
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        self.apply_state();
+    }
+
+    /// Checks if audio is enabled
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts the looping ambience of the drive: the recorded drive loop
+    /// with the whine of its spindle, both endless
+    ///
+    /// The ambience of the previous drive, if any, stops; drives without
+    /// moving parts have none.
+    pub fn play_loop_sound(&mut self) {
+        let Ok(ambience) = Sink::try_new(&self.handle) else {
+            return;
+        };
+        if self.rpm > 0 {
+            if let Ok(source) = Decoder::new(EmbeddedAudioResources::loop_sound()) {
+                let drive_loop = source.convert_samples::<f32>().repeat_infinite();
+                ambience.append(drive_loop.mix(SpindleWhine::new(self.rpm)));
+            }
+        }
+        self.ambience = ambience;
+        self.apply_state();
+    }
+
+    /// Plays the chatter of a seek over `distance` of the full stroke, from
+    /// 0 to 1
+    pub fn play_seek(&self, distance: f32) {
+        self.play_head_sound(HeadSound::seek(distance, self.playback_rate));
+    }
+
+    /// Plays the heads reading `clusters` clusters
+    pub fn play_read(&self, clusters: usize) {
+        self.play_head_sound(HeadSound::transfer(clusters, self.playback_rate));
+    }
+
+    /// Plays the heads writing `clusters` clusters, ending with the click of
+    /// the write gate
+    pub fn play_write(&self, clusters: usize) {
+        self.play_head_sound(HeadSound::transfer(clusters, self.playback_rate));
+        self.play_head_sound(HeadSound::click());
+    }
+}
@@ -0,0 +1,162 @@
+//! Sounds muted by the user
+//!
+//! Each kind of sound can be muted on its own, and the choice is kept in
+//! `audio.toml` of the user configuration directory:
+//!
+//! ```toml
+//! [mute]
+//! head = true
+//! chime = false
+//! ```
+
+use crate::config;
+use crate::constants::audio;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Kinds of sounds that can be muted one by one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundCategory {
+    /// Clicks and chatter of the heads
+    Head,
+    /// Looping drive ambience and spindle whine
+    Ambience,
+    /// Button clicks of the interface
+    Clicks,
+    /// Chime of a finished run
+    Chime,
+    /// Beeps of the PC speaker in the MS-DOS interface
+    Beep,
+}
+
+impl SoundCategory {
+    pub const ALL: [SoundCategory; 5] = [
+        SoundCategory::Head,
+        SoundCategory::Ambience,
+        SoundCategory::Clicks,
+        SoundCategory::Chime,
+        SoundCategory::Beep,
+    ];
+
+    /// Key of the category in the `[mute]` table
+    pub fn key(self) -> &'static str {
+        match self {
+            SoundCategory::Head => "head",
+            SoundCategory::Ambience => "ambience",
+            SoundCategory::Clicks => "clicks",
+            SoundCategory::Chime => "chime",
+            SoundCategory::Beep => "beep",
+        }
+    }
+
+    /// Name of the category in the settings dialog
+    pub fn label(self) -> &'static str {
+        match self {
+            SoundCategory::Head => "head noise",
+            SoundCategory::Ambience => "drive ambience",
+            SoundCategory::Clicks => "button clicks",
+            SoundCategory::Chime => "completion chime",
+            SoundCategory::Beep => "PC speaker beeps",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// An audio settings file: whether each kind of sound is muted, by key
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SettingsFile {
+    #[serde(default)]
+    mute: BTreeMap<String, bool>,
+}
+
+/// Which kinds of sounds are muted; none are by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioSettings {
+    muted: [bool; 5],
+}
+
+impl AudioSettings {
+    pub fn is_muted(&self, category: SoundCategory) -> bool {
+        self.muted[category.index()]
+    }
+
+    pub fn set_muted(&mut self, category: SoundCategory, muted: bool) {
+        self.muted[category.index()] = muted;
+    }
+
+    /// The settings file of the user configuration directory
+    pub fn path() -> Option<PathBuf> {
+        config::config_dir().map(|dir| dir.join(audio::SETTINGS_FILE))
+    }
+
+    /// Reads the settings file, if there is one
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::parse(&std::fs::read_to_string(path)?),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Reads the `[mute]` table of a settings file
+    pub fn parse(text: &str) -> Result<Self> {
+        let file: SettingsFile = config::parse(text)?;
+        let mut settings = Self::default();
+        for (key, muted) in file.mute {
+            let category = SoundCategory::ALL
+                .into_iter()
+                .find(|category| category.key() == key)
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::InvalidData, format!("unknown sound `{}`", key))
+                })?;
+            settings.set_muted(category, muted);
+        }
+        Ok(settings)
+    }
+
+    /// Writes the settings the way [`AudioSettings::parse`] reads them back
+    pub fn to_text(&self) -> Result<String> {
+        let mute = SoundCategory::ALL
+            .into_iter()
+            .map(|category| (category.key().to_string(), self.is_muted(category)))
+            .collect();
+        config::format(&SettingsFile { mute })
+    }
+
+    /// Writes the settings to `path`, creating its directory
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_text()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip() {
+        let mut settings = AudioSettings::default();
+        settings.set_muted(SoundCategory::Head, true);
+        settings.set_muted(SoundCategory::Chime, true);
+        assert_eq!(
+            AudioSettings::parse(&settings.to_text().unwrap()).unwrap(),
+            settings
+        );
+
+        let partial = AudioSettings::parse("[mute]\nclicks = true\n").unwrap();
+        assert!(partial.is_muted(SoundCategory::Clicks));
+        assert!(!partial.is_muted(SoundCategory::Ambience));
+
+        assert!(AudioSettings::parse("[mute]\nbeeper = true\n").is_err());
+        assert!(AudioSettings::parse("[mute]\nhead = 1\n").is_err());
+        assert!(AudioSettings::parse("[volume]\n").is_err());
+    }
+}
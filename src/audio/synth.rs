@@ -0,0 +1,386 @@
+//! Procedural drive sounds
+//!
+//! The sounds of the heads and the spindle are computed sample by sample
+//! rather than played from recordings, so they follow the simulated drive: a
+//! long seek chatters longer than a short one, a fast drive clicks faster and
+//! a 7200 rpm spindle whines higher than a 3600 rpm one. The beeps of the
+//! PC speaker are square waves, the only sound the speaker could make.
+
+use rodio::Source;
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+pub const SAMPLE_RATE: u32 = 44_100;
+
+/// Length of one actuator click
+const CLICK_MS: f32 = 6.0;
+/// Time between two clicks of a seek at playback rate 1
+const SEEK_CLICK_MS: f32 = 9.0;
+/// Clicks added to a seek over the full stroke
+const MAX_SEEK_CLICKS: f32 = 8.0;
+/// Sound of a transfer per cluster at playback rate 1, and its longest
+const TRANSFER_MS_PER_CLUSTER: f32 = 4.0;
+const MAX_TRANSFER_MS: f32 = 400.0;
+/// Time between two ticks of a transfer at playback rate 1
+const TRANSFER_TICK_MS: f32 = 15.0;
+/// Clock of the timer driving the PC speaker, in Hz
+const PIT_CLOCK: f32 = 1_193_182.0;
+/// Loudness of the PC speaker
+const BEEP_LEVEL: f32 = 0.2;
+
+fn samples(milliseconds: f32) -> u32 {
+    (milliseconds * SAMPLE_RATE as f32 / 1000.0) as u32
+}
+
+/// White noise from a xorshift generator, cheap enough for the audio thread
+#[derive(Debug, Clone)]
+struct Noise(u32);
+
+impl Noise {
+    fn new() -> Self {
+        Noise(0x2545_f491)
+    }
+
+    /// Next sample, from -1 to 1
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+/// Clicks of the head actuator over a rustle of the voice coil
+#[derive(Debug, Clone)]
+pub struct HeadSound {
+    /// Start of each click, in samples, with its loudness
+    clicks: Vec<(u32, f32)>,
+    /// Level of the rustle, and how long it lasts in samples
+    rustle: f32,
+    rustle_length: u32,
+    position: u32,
+    length: u32,
+    noise: Noise,
+    /// Low-passed noise the rustle is made of
+    filtered: f32,
+}
+
+impl HeadSound {
+    fn new(clicks: Vec<(u32, f32)>, rustle: f32, rustle_length: u32) -> Self {
+        let last_click = clicks
+            .last()
+            .map_or(0, |&(start, _)| start + samples(CLICK_MS));
+        Self {
+            clicks,
+            rustle,
+            rustle_length,
+            position: 0,
+            length: last_click.max(rustle_length),
+            noise: Noise::new(),
+            filtered: 0.0,
+        }
+    }
+
+    /// A single click of the actuator
+    pub fn click() -> Self {
+        Self::new(vec![(0, 1.0)], 0.0, 0)
+    }
+
+    /// Chatter of a seek over `distance` of the full stroke, from 0 to 1
+    ///
+    /// The heads click as they leave, cross and settle on the tracks: the
+    /// longer the seek, the more clicks. `rate` makes them closer together
+    /// for faster drives.
+    pub fn seek(distance: f32, rate: f32) -> Self {
+        let count = 1 + (distance.clamp(0.0, 1.0) * MAX_SEEK_CLICKS).round() as u32;
+        let interval = samples(SEEK_CLICK_MS / rate.max(0.1));
+        let clicks = (0..count)
+            .map(|index| {
+                // Loud start and settle, softer clicks while coasting
+                let gain = if index == 0 || index + 1 == count {
+                    1.0
+                } else {
+                    0.5
+                };
+                (index * interval, gain)
+            })
+            .collect();
+        Self::new(clicks, 0.1 * distance.clamp(0.0, 1.0), count * interval)
+    }
+
+    /// The heads reading or writing `clusters` clusters: soft ticks over a
+    /// rustle lasting as long as the transfer
+    pub fn transfer(clusters: usize, rate: f32) -> Self {
+        let rate = rate.max(0.1);
+        let length =
+            samples((clusters as f32 * TRANSFER_MS_PER_CLUSTER / rate).min(MAX_TRANSFER_MS));
+        let interval = samples(TRANSFER_TICK_MS / rate).max(1);
+        let clicks = (0..length.max(1))
+            .step_by(interval as usize)
+            .map(|start| (start, 0.2))
+            .collect();
+        Self::new(clicks, 0.04, length)
+    }
+}
+
+impl Iterator for HeadSound {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position >= self.length {
+            return None;
+        }
+        let noise = self.noise.next();
+        let click_length = samples(CLICK_MS);
+        let mut sample = 0.0;
+        for &(start, gain) in &self.clicks {
+            if start > self.position {
+                break;
+            }
+            let offset = self.position - start;
+            if offset < click_length {
+                sample += gain * click(offset, noise);
+            }
+        }
+        if self.position < self.rustle_length {
+            self.filtered += (noise - self.filtered) * 0.2;
+            sample += self.rustle * self.filtered;
+        }
+        self.position += 1;
+        Some(sample.clamp(-1.0, 1.0))
+    }
+}
+
+/// Sample `offset` of a click: a sharp metallic tick over the thump of the
+/// actuator arm
+fn click(offset: u32, noise: f32) -> f32 {
+    let t = offset as f32 / SAMPLE_RATE as f32;
+    let tick = (-t / 0.0012).exp();
+    let thump = (TAU * 140.0 * t).sin() * (-t / 0.004).exp();
+    0.4 * noise * tick + 0.5 * (TAU * 2400.0 * t).sin() * tick + 0.5 * thump
+}
+
+impl Source for HeadSound {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some((self.length - self.position) as usize)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(
+            self.length as f32 / SAMPLE_RATE as f32,
+        ))
+    }
+}
+
+/// Endless whine of a spindle turning at `rpm`
+///
+/// The hum of the rotation, the whine of the motor poles and the hiss of
+/// the bearings all rise with the spindle speed.
+#[derive(Debug, Clone)]
+pub struct SpindleWhine {
+    /// Frequency, loudness and phase (0 to 1) of each partial
+    partials: [(f32, f32, f32); 3],
+    /// Loudness of the bearing hiss
+    hiss: f32,
+    noise: Noise,
+    filtered: f32,
+}
+
+impl SpindleWhine {
+    pub fn new(rpm: u32) -> Self {
+        let turn = rpm as f32 / 60.0;
+        Self {
+            partials: [
+                (turn, 0.05, 0.0),
+                (turn * 8.0, 0.03, 0.0),
+                (turn * 40.0, 0.006, 0.0),
+            ],
+            hiss: 0.02,
+            noise: Noise::new(),
+            filtered: 0.0,
+        }
+    }
+}
+
+impl Iterator for SpindleWhine {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let mut sample = 0.0;
+        for (frequency, gain, phase) in &mut self.partials {
+            sample += *gain * (TAU * *phase).sin();
+            *phase = (*phase + *frequency / SAMPLE_RATE as f32).fract();
+        }
+        self.filtered += (self.noise.next() - self.filtered) * 0.05;
+        Some(sample + self.hiss * self.filtered)
+    }
+}
+
+impl Source for SpindleWhine {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Beep of the PC speaker: a square wave, or silence, for each tone
+#[derive(Debug, Clone)]
+pub struct SpeakerBeep {
+    /// Frequency (0 for silence) and length in samples of each tone
+    tones: Vec<(f32, u32)>,
+    tone: usize,
+    /// Sample of the current tone
+    position: u32,
+    /// Phase of the wave, from 0 to 1
+    phase: f32,
+}
+
+impl SpeakerBeep {
+    /// Plays `tones`, given as frequency in Hz and length in milliseconds
+    ///
+    /// Frequencies are rounded to those the timer can divide its clock
+    /// into, as on the real speaker.
+    pub fn new(tones: &[(f32, f32)]) -> Self {
+        let tones = tones
+            .iter()
+            .map(|&(frequency, milliseconds)| {
+                let frequency = if frequency > 0.0 {
+                    PIT_CLOCK / (PIT_CLOCK / frequency).round().max(1.0)
+                } else {
+                    0.0
+                };
+                (frequency, samples(milliseconds))
+            })
+            .collect();
+        Self {
+            tones,
+            tone: 0,
+            position: 0,
+            phase: 0.0,
+        }
+    }
+
+    /// The beep of an error: a single low tone
+    pub fn error() -> Self {
+        Self::new(&[(440.0, 300.0)])
+    }
+
+    /// The beeps of a finished run: two short rising tones
+    pub fn completion() -> Self {
+        Self::new(&[(880.0, 120.0), (0.0, 60.0), (1320.0, 200.0)])
+    }
+
+    fn length(&self) -> u32 {
+        self.tones.iter().map(|&(_, length)| length).sum()
+    }
+}
+
+impl Iterator for SpeakerBeep {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            let &(frequency, length) = self.tones.get(self.tone)?;
+            if self.position < length {
+                self.position += 1;
+                if frequency == 0.0 {
+                    return Some(0.0);
+                }
+                let high = self.phase < 0.5;
+                self.phase = (self.phase + frequency / SAMPLE_RATE as f32).fract();
+                return Some(if high { BEEP_LEVEL } else { -BEEP_LEVEL });
+            }
+            self.tone += 1;
+            self.position = 0;
+        }
+    }
+}
+
+impl Source for SpeakerBeep {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(
+            self.length() as f32 / SAMPLE_RATE as f32,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_seeks_chatter_longer() {
+        let short = HeadSound::seek(0.05, 1.0);
+        let long = HeadSound::seek(1.0, 1.0);
+        assert!(long.total_duration() > short.total_duration());
+        assert!(HeadSound::seek(1.0, 4.0).total_duration() < long.total_duration());
+
+        let samples: Vec<f32> = long.collect();
+        assert!(samples.iter().all(|sample| (-1.0..=1.0).contains(sample)));
+        assert!(samples.iter().any(|sample| sample.abs() > 0.3));
+    }
+
+    #[test]
+    fn test_spindle_pitch_follows_rpm() {
+        // Zero crossings of the motor whine over one second
+        let crossings = |rpm| {
+            let mut whine = SpindleWhine::new(rpm);
+            whine.partials[0].1 = 0.0;
+            whine.partials[2].1 = 0.0;
+            whine.hiss = 0.0;
+            let samples: Vec<f32> = whine.take(SAMPLE_RATE as usize).collect();
+            samples
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+                .count()
+        };
+        assert!(crossings(7200) > crossings(3600) * 3 / 2);
+    }
+
+    #[test]
+    fn test_speaker_beeps_square_waves() {
+        let beep = SpeakerBeep::completion();
+        let length = beep.length() as usize;
+        let wave: Vec<f32> = beep.collect();
+        assert_eq!(wave.len(), length);
+        assert!(wave
+            .iter()
+            .all(|&sample| sample == 0.0 || sample.abs() == BEEP_LEVEL));
+
+        // Silence between the two tones
+        let pause = samples(120.0) as usize..samples(180.0) as usize;
+        assert!(wave[pause].iter().all(|&sample| sample == 0.0));
+        assert!(wave[..samples(120.0) as usize].contains(&-BEEP_LEVEL));
+    }
+}
@@ -0,0 +1,88 @@
+//! Simulation time sources
+//!
+//! The engine only ever sees a [`Duration`] since the start of the simulation,
+//! so it can follow the wall clock in the interactive front-ends or run on
+//! virtual time in tests and export modes.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Source of simulation time
+pub trait Clock {
+    /// Time elapsed since the clock was started
+    fn now(&self) -> Duration;
+}
+
+/// Clock following real (wall-clock) time
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+/// Clock that only moves when told to
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VirtualClock {
+    now: Duration,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward by `step`
+    pub fn advance(&mut self, step: Duration) {
+        self.now += step;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        self.now
+    }
+}
+
+/// Virtual clock whose clones all show the same time, so it can be moved
+/// while an app owns one of them
+#[derive(Debug, Clone, Default)]
+pub struct SharedClock {
+    now: Rc<Cell<Duration>>,
+}
+
+impl SharedClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves every clone of the clock forward by `step`
+    pub fn advance(&self, step: Duration) {
+        self.now.set(self.now.get() + step);
+    }
+}
+
+impl Clock for SharedClock {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+}
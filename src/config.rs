@@ -0,0 +1,105 @@
+//! User configuration files
+//!
+//! Configuration lives in `$XDG_CONFIG_HOME/defrag-simulator` (or
+//! `~/.config/defrag-simulator`, or `%APPDATA%\defrag-simulator` on Windows)
+//! and is written in TOML.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::io::{Error, ErrorKind, Result};
+use std::path::PathBuf;
+
+/// Directory holding the user configuration, when a home can be found
+pub fn config_dir() -> Option<PathBuf> {
+    config_dir_from(|name| std::env::var_os(name))
+}
+
+/// Configuration directory named by the environment variables `var` reads
+fn config_dir_from(var: impl Fn(&str) -> Option<OsString>) -> Option<PathBuf> {
+    let set = |name| var(name).filter(|value| !value.is_empty());
+    let base = if let Some(dir) = set("XDG_CONFIG_HOME") {
+        PathBuf::from(dir)
+    } else if let Some(home) = set("HOME") {
+        PathBuf::from(home).join(".config")
+    } else {
+        PathBuf::from(set("APPDATA")?)
+    };
+    Some(base.join("defrag-simulator"))
+}
+
+/// Reads a configuration file into `T`
+///
+/// Syntax errors and settings of the wrong type or unknown to `T` are
+/// reported with their line.
+pub fn parse<T: DeserializeOwned>(text: &str) -> Result<T> {
+    toml::from_str(text).map_err(|e| {
+        let message = match e.span() {
+            Some(span) => format!("line {}: {}", line(text, span.start), e.message()),
+            None => e.message().to_string(),
+        };
+        Error::new(ErrorKind::InvalidData, message)
+    })
+}
+
+/// Line of `text` holding the byte at `offset`, counted from 1
+pub fn line(text: &str, offset: usize) -> usize {
+    text[..offset].matches('\n').count() + 1
+}
+
+/// Writes `value` as a configuration file
+pub fn format<T: Serialize>(value: &T) -> Result<String> {
+    toml::to_string(value).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize, PartialEq)]
+    #[serde(deny_unknown_fields)]
+    struct Sample {
+        name: String,
+        fast: Option<bool>,
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_line() {
+        let sample: Sample = parse("name = \"Zip # 100\"  # comment\n").unwrap();
+        assert_eq!(sample.name, "Zip # 100");
+
+        let error = parse::<Sample>("name = \"G\"\nfast = 1\n").unwrap_err();
+        assert!(error.to_string().starts_with("line 2:"), "{}", error);
+        assert!(parse::<Sample>("name = \"G\"\nturbo = true\n").is_err());
+    }
+
+    #[test]
+    fn test_config_dir_falls_back_to_appdata() {
+        let dir = |vars: &[(&str, &str)]| {
+            let vars: Vec<(String, OsString)> = vars
+                .iter()
+                .map(|&(name, value)| (name.to_string(), value.into()))
+                .collect();
+            config_dir_from(|name| {
+                vars.iter()
+                    .find(|(var, _)| var == name)
+                    .map(|(_, value)| value.clone())
+            })
+        };
+
+        assert_eq!(dir(&[]), None);
+        assert_eq!(dir(&[("HOME", "")]), None);
+        assert_eq!(
+            dir(&[("APPDATA", "C:\\Users\\Me\\AppData\\Roaming")]),
+            Some(PathBuf::from("C:\\Users\\Me\\AppData\\Roaming").join("defrag-simulator"))
+        );
+        assert_eq!(
+            dir(&[("HOME", "/home/me"), ("APPDATA", "C:\\AppData")]),
+            Some(PathBuf::from("/home/me/.config/defrag-simulator"))
+        );
+        assert_eq!(
+            dir(&[("XDG_CONFIG_HOME", "/etc/xdg"), ("HOME", "/home/me")]),
+            Some(PathBuf::from("/etc/xdg/defrag-simulator"))
+        );
+    }
+}
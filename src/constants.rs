@@ -5,6 +5,187 @@
 
 /// Disk drive configuration constants
 pub mod disk {
+    use super::ui::{BAD_BLOCK_PERCENT, DEFAULT_FILL_PERCENT};
+    use serde::{Deserialize, Serialize};
+
+    /// Kind of medium a drive stores its clusters on
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub enum MediaType {
+        Floppy,
+        #[default]
+        Hdd,
+        Ssd,
+    }
+
+    impl MediaType {
+        /// Parses a media type as written in a drives file
+        pub fn parse(name: &str) -> Option<Self> {
+            match name.to_ascii_lowercase().as_str() {
+                "floppy" => Some(Self::Floppy),
+                "hdd" | "hard disk" => Some(Self::Hdd),
+                "ssd" | "sshd" => Some(Self::Ssd),
+                _ => None,
+            }
+        }
+
+        pub fn name(self) -> &'static str {
+            match self {
+                Self::Floppy => "Floppy Disk",
+                Self::Hdd => "Hard Disk",
+                Self::Ssd => "SSD",
+            }
+        }
+    }
+
+    /// Physical layout and mechanics of a drive, used to time head movements
+    #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+    pub struct DiskGeometry {
+        pub cylinders: u32,
+        pub heads: u32,
+        pub sectors_per_track: u32,
+        /// Spindle speed, 0 for drives without moving parts
+        pub rpm: u32,
+        /// Seek time to the neighbouring cylinder
+        pub track_to_track_ms: f32,
+        /// Seek time from the first to the last cylinder
+        pub full_stroke_ms: f32,
+    }
+
+    impl DiskGeometry {
+        /// Cylinder holding `cluster` out of `total` clusters laid out from the
+        /// outer edge inwards
+        pub fn cylinder_of(&self, cluster: usize, total: usize) -> u32 {
+            if total == 0 {
+                return 0;
+            }
+            let cylinder = cluster.min(total - 1) as u64 * self.cylinders as u64 / total as u64;
+            cylinder as u32
+        }
+
+        /// Time to move the heads over `distance` cylinders
+        ///
+        /// The heads accelerate then coast, so the time grows with the square
+        /// root of the distance between the track-to-track and full-stroke
+        /// times.
+        pub fn seek_ms(&self, distance: u32) -> f32 {
+            if distance == 0 {
+                return 0.0;
+            }
+            let span = self.cylinders.saturating_sub(1).max(1) as f32;
+            let ratio = ((distance - 1) as f32 / span).min(1.0).sqrt();
+            self.track_to_track_ms + (self.full_stroke_ms - self.track_to_track_ms) * ratio
+        }
+
+        /// Time of one spindle revolution
+        pub fn revolution_ms(&self) -> f32 {
+            if self.rpm == 0 {
+                0.0
+            } else {
+                60_000.0 / self.rpm as f32
+            }
+        }
+
+        /// Time for one sector to pass under the head
+        pub fn sector_ms(&self) -> f32 {
+            self.revolution_ms() / self.sectors_per_track.max(1) as f32
+        }
+
+        /// Mean access time: a seek over a third of the cylinders plus half a
+        /// revolution
+        pub fn average_access_ms(&self) -> f32 {
+            self.seek_ms(self.cylinders / 3) + self.revolution_ms() / 2.0
+        }
+
+        /// Typical mechanics of a media type
+        pub const fn for_media(media: MediaType) -> Self {
+            match media {
+                MediaType::Floppy => FLOPPY_GEOMETRY,
+                MediaType::Hdd => HDD_GEOMETRY,
+                MediaType::Ssd => SSD_GEOMETRY,
+            }
+        }
+
+        /// Checks every setting the timings rely on, naming the first faulty
+        /// one
+        pub fn check(&self) -> Result<(), String> {
+            let counts = [
+                (self.cylinders, "cylinders"),
+                (self.heads, "heads"),
+                (self.sectors_per_track, "sectors"),
+            ];
+            if let Some((_, key)) = counts.iter().find(|(count, _)| *count == 0) {
+                return Err(format!("`{}` must be above 0", key));
+            }
+            if self.rpm != 0 && !is_rpm(self.rpm) {
+                return Err(format!(
+                    "`rpm` must be 0 or between {} and {}",
+                    RPM_RANGE.0, RPM_RANGE.1
+                ));
+            }
+            let times = [
+                (self.track_to_track_ms, "track_to_track_ms"),
+                (self.full_stroke_ms, "full_stroke_ms"),
+            ];
+            match times.iter().find(|(time, _)| !is_time(*time)) {
+                Some((_, key)) => Err(format!("`{}` must be finite and not negative", key)),
+                None => Ok(()),
+            }
+        }
+    }
+
+    /// Whether `value` is a share between 0 and 1
+    pub fn is_ratio(value: f32) -> bool {
+        (0.0..=1.0).contains(&value)
+    }
+
+    /// Whether `value` is a usable time in milliseconds: finite and not
+    /// negative
+    pub fn is_time(value: f32) -> bool {
+        value.is_finite() && value >= 0.0
+    }
+
+    /// Whether `rpm` is within [`RPM_RANGE`], for drives with moving parts
+    pub fn is_rpm(rpm: u32) -> bool {
+        (RPM_RANGE.0..=RPM_RANGE.1).contains(&rpm)
+    }
+
+    /// 3.5" floppy drive: 80 cylinders, slow stepper motor
+    pub const FLOPPY_GEOMETRY: DiskGeometry = DiskGeometry {
+        cylinders: 80,
+        heads: 2,
+        sectors_per_track: 18,
+        rpm: 300,
+        track_to_track_ms: 6.0,
+        full_stroke_ms: 160.0,
+    };
+
+    /// Mid-90s IDE hard disk of about 2 GB
+    pub const HDD_GEOMETRY: DiskGeometry = DiskGeometry {
+        cylinders: 4161,
+        heads: 16,
+        sectors_per_track: 63,
+        rpm: 5400,
+        track_to_track_ms: 2.5,
+        full_stroke_ms: 22.0,
+    };
+
+    /// Solid-state storage: no heads to move, no platter to wait for
+    pub const SSD_GEOMETRY: DiskGeometry = DiskGeometry {
+        cylinders: 1,
+        heads: 1,
+        sectors_per_track: 1,
+        rpm: 0,
+        track_to_track_ms: 0.0,
+        full_stroke_ms: 0.0,
+    };
+
+    /// Spindle speeds accepted for drives with moving parts (min, max)
+    pub const RPM_RANGE: (u32, u32) = (60, 20_000);
+
+    /// Cluster counts accepted for a drive (min, max): one cell each on
+    /// screen, so the grid stays within what the planner handles quickly
+    pub const CLUSTER_COUNT_RANGE: (u32, u32) = (1, 1_000_000);
+
     /// Represents a disk drive configuration with IOPS-based audio speed
     #[derive(Debug, Clone, Copy, PartialEq)]
     pub struct DriveConfig {
@@ -12,6 +193,12 @@ pub mod disk {
         pub capacity_mb: u32,
         pub cluster_count: u32,
         pub iops: u32,
+        /// Share of the clusters that are bad blocks (0.0 to 1.0)
+        pub bad_ratio: f32,
+        /// Share of the clusters used by files (0.0 to 1.0)
+        pub fill_ratio: f32,
+        pub media: MediaType,
+        pub geometry: DiskGeometry,
     }
 
     /// Drive C: Standard hard disk (slower, 2 IOPS)
@@ -20,6 +207,10 @@ pub mod disk {
         capacity_mb: 2048,
         cluster_count: 4096,
         iops: 2,
+        bad_ratio: BAD_BLOCK_PERCENT,
+        fill_ratio: DEFAULT_FILL_PERCENT,
+        media: MediaType::Hdd,
+        geometry: HDD_GEOMETRY,
     };
 
     /// Drive D: Medium hard disk (3 IOPS)
@@ -28,6 +219,16 @@ pub mod disk {
         capacity_mb: 1024,
         cluster_count: 2048,
         iops: 3,
+        bad_ratio: BAD_BLOCK_PERCENT,
+        fill_ratio: DEFAULT_FILL_PERCENT,
+        media: MediaType::Hdd,
+        geometry: DiskGeometry {
+            cylinders: 2080,
+            rpm: 4500,
+            track_to_track_ms: 3.0,
+            full_stroke_ms: 25.0,
+            ..HDD_GEOMETRY
+        },
     };
 
     /// Drive E: Slow disk/Floppy (1 IOPS - slowest)
@@ -36,6 +237,10 @@ pub mod disk {
         capacity_mb: 512,
         cluster_count: 1024,
         iops: 1,
+        bad_ratio: BAD_BLOCK_PERCENT,
+        fill_ratio: DEFAULT_FILL_PERCENT,
+        media: MediaType::Floppy,
+        geometry: FLOPPY_GEOMETRY,
     };
 
     /// Drive F: Fast SSHD/SSD hybrid (8 IOPS - fastest)
@@ -44,6 +249,10 @@ pub mod disk {
         capacity_mb: 2048,
         cluster_count: 4096,
         iops: 8,
+        bad_ratio: BAD_BLOCK_PERCENT,
+        fill_ratio: DEFAULT_FILL_PERCENT,
+        media: MediaType::Ssd,
+        geometry: SSD_GEOMETRY,
     };
 
     /// All available drive configurations
@@ -52,6 +261,13 @@ pub mod disk {
     /// Default drive (Drive C)
     pub const DEFAULT_DRIVE: DriveConfig = DRIVE_C;
 
+    /// Name of the drives file in the user configuration directory
+    pub const DRIVES_FILE: &str = "drives.toml";
+
+    /// Name of the file an interrupted run is saved to in the user
+    /// configuration directory, unless `--resume` names one
+    pub const SESSION_FILE: &str = "state.json";
+
     /// Get drive by letter
     pub fn get_drive_by_letter(letter: char) -> Option<DriveConfig> {
         ALL_DRIVES.iter().find(|d| d.letter == letter).copied()
@@ -80,6 +296,21 @@ pub mod audio {
     /// Default audio volume (0.0 to 1.0)
     pub const DEFAULT_VOLUME: f32 = 0.5;
 
+    /// Volume change of one press of the volume keys
+    pub const VOLUME_STEP: f32 = 0.1;
+
+    /// Audio settings file in the user configuration directory
+    pub const SETTINGS_FILE: &str = "audio.toml";
+
+    /// Volume of the looping drive ambience (0.0 to 1.0)
+    pub const AMBIENCE_VOLUME: f32 = 0.3;
+
+    /// Volume of the head clicks and seeks (0.0 to 1.0)
+    pub const HEAD_VOLUME: f32 = 0.5;
+
+    /// Volume of the interface clicks and chimes (0.0 to 1.0)
+    pub const INTERFACE_VOLUME: f32 = 0.5;
+
     /// Calculates playback rate based on IOPS using linear mapping
     ///
     /// This maps IOPS values from [0, 16] to playback rates [0.5, 4.0]
@@ -93,7 +324,7 @@ pub mod audio {
     ///
     /// # Example
     /// ```
-    /// use defrag_rs::constants::audio::calculate_playback_rate;
+    /// use defrag_simulator_rs::constants::audio::calculate_playback_rate;
     ///
     /// // Slow disk (1 IOPS) -> slow playback
     /// let rate = calculate_playback_rate(1);
@@ -115,7 +346,7 @@ pub mod audio {
             (iops_f - iops_min_f) * (rate_max - rate_min) / (iops_max_f - iops_min_f) + rate_min;
 
         // Clamp to valid range
-        rate.max(MIN_PLAYBACK_RATE).min(MAX_PLAYBACK_RATE)
+        rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE)
     }
 
     /// Alternative calculation based on simple IOPS timing (1000ms / iops)
@@ -137,7 +368,7 @@ pub mod audio {
         // Scale to our range
         let rate = normalized / 4.0; // Scale factor to get reasonable rates
 
-        rate.max(MIN_PLAYBACK_RATE).min(MAX_PLAYBACK_RATE)
+        rate.clamp(MIN_PLAYBACK_RATE, MAX_PLAYBACK_RATE)
     }
 }
 
@@ -157,6 +388,16 @@ pub mod animation {
 
     /// Finished phase wait time (in ticks) before auto-exit
     pub const FINISH_WAIT_TICKS: u64 = 50;
+
+    /// Time the clusters of a highlighted file stay lit, then dark
+    pub const FLASH_PERIOD_MS: u128 = 300;
+}
+
+/// Analysis pass constants
+pub mod analysis {
+    /// Fragmentation (share of the files stored in several fragments) from
+    /// which defragmenting is recommended
+    pub const RECOMMEND_DEFRAG_PERCENT: f32 = 10.0;
 }
 
 /// UI dimensions and layout constants
@@ -183,6 +424,30 @@ pub mod ui {
     pub const ABOUT_BOX_HEIGHT: u16 = 18;
 }
 
+/// Simulated file system constants
+pub mod files {
+    /// Largest generated file, in clusters
+    pub const MAX_FILE_CLUSTERS: usize = 24;
+
+    /// Largest "small" file, in clusters (most generated files are small)
+    pub const SMALL_FILE_CLUSTERS: usize = 4;
+
+    /// Probability that a generated file is small
+    pub const SMALL_FILE_RATIO: f64 = 0.7;
+
+    /// Probability that a generated file is split into several fragments
+    pub const FRAGMENTED_FILE_RATIO: f64 = 0.6;
+
+    /// Maximum number of fragments a generated file is split into
+    pub const MAX_FRAGMENTS: usize = 6;
+
+    /// Average number of file fragments between two free space gaps
+    pub const FRAGMENTS_PER_GAP: usize = 3;
+
+    /// Range of years generated files were last modified in
+    pub const MODIFIED_YEARS: (u16, u16) = (1990, 1996);
+}
+
 /// Defrag simulation types
 pub mod defrag_type {
     /// Different defrag visual styles
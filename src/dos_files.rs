@@ -1,5 +1,5 @@
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{thread_rng, Rng};
 
 // --- MS-DOS 6.22 file categories ---
 
@@ -234,8 +234,8 @@ pub struct DosFileProvider {
 
 impl DosFileProvider {
     /// Creates a new provider, populates it with all file lists,
-    /// and shuffles the list for unique random distribution.
-    pub fn new() -> Self {
+    /// and shuffles the list with `rng` for unique random distribution.
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         let mut remaining_files = Vec::new();
 
         // Add all file categories
@@ -255,7 +255,7 @@ impl DosFileProvider {
         remaining_files.extend_from_slice(WINDOWS_31_FILES);
 
         // Shuffle the list to get a random order
-        remaining_files.shuffle(&mut thread_rng());
+        remaining_files.shuffle(rng);
 
         Self { remaining_files }
     }
@@ -266,3 +266,9 @@ impl DosFileProvider {
         self.remaining_files.pop().map(|s| s.to_string())
     }
 }
+
+impl Default for DosFileProvider {
+    fn default() -> Self {
+        Self::new(&mut thread_rng())
+    }
+}
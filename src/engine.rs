@@ -0,0 +1,1033 @@
+//! Headless defragmentation engine
+//!
+//! Holds the simulated disk and steps the defragmentation forward. The engine
+//! never reads the wall clock: every update receives the current simulation
+//! time, so the front-ends drive it in real time while tests and exports can
+//! run a whole defrag on virtual time in a few milliseconds.
+
+use crate::clock::{Clock, VirtualClock};
+use crate::constants::disk::{self, DiskGeometry};
+use crate::constants::ui as ui_const;
+use crate::layout::DiskLayout;
+use crate::models::{
+    cluster_codes, AnalysisReport, ClusterState, DefragPhase, DefragStats, FileFragment, FileSort,
+    OptimizationMethod, SimRng,
+};
+use crate::planner::{self, ClusterMove};
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+
+/// Number of ticks spent in the Initializing phase
+const INIT_TICKS: u64 = 20;
+
+/// Clusters scanned per tick while analyzing
+const ANALYZE_STEP: usize = 5;
+
+/// Represents the state of a file during defragmentation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "step", rename_all = "lowercase")]
+pub enum FileDefragPhase {
+    /// The file is being read from its fragmented location
+    Reading { progress: usize },
+    /// The file is being written to its new contiguous location
+    Writing { progress: usize },
+    /// The file has been fully defragmented
+    Completed,
+}
+
+/// Disk activity produced by the engine, for the front-ends to play or show
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimEvent {
+    /// The head moved to a new position, over `distance` of the full stroke
+    /// (0 to 1)
+    Seek { distance: f32 },
+    /// Clusters were read
+    Read { clusters: usize },
+    /// Clusters were written
+    Write { clusters: usize },
+}
+
+/// Cache for tracking free space regions (optimization)
+#[derive(Debug, Clone)]
+pub struct FreeSpaceCache {
+    /// List of (start_index, length) for contiguous free regions
+    regions: Vec<(usize, usize)>,
+    /// Whether the cache needs rebuilding
+    dirty: bool,
+}
+
+impl FreeSpaceCache {
+    pub fn new() -> Self {
+        Self {
+            regions: Vec::new(),
+            dirty: true,
+        }
+    }
+
+    /// Mark cache as needing rebuild
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Rebuild the cache from cluster state
+    pub fn rebuild(&mut self, clusters: &[ClusterState]) {
+        self.regions.clear();
+        let mut start: Option<usize> = None;
+        let mut length = 0;
+
+        for (i, &cluster) in clusters.iter().enumerate() {
+            if cluster == ClusterState::Unused {
+                if start.is_none() {
+                    start = Some(i);
+                }
+                length += 1;
+            } else if let Some(s) = start {
+                self.regions.push((s, length));
+                start = None;
+                length = 0;
+            }
+        }
+
+        if let Some(s) = start {
+            self.regions.push((s, length));
+        }
+
+        self.regions.sort_by_key(|&(_, len)| std::cmp::Reverse(len));
+        self.dirty = false;
+    }
+
+    /// Free regions as (start, length), largest first
+    pub fn regions(&self) -> &[(usize, usize)] {
+        &self.regions
+    }
+
+    /// Find a region with at least `size` contiguous clusters
+    pub fn find_region(&self, size: usize) -> Option<usize> {
+        self.regions
+            .iter()
+            .find(|(_, len)| *len >= size)
+            .map(|(start, _)| *start)
+    }
+}
+
+impl Default for FreeSpaceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The simulated disk and the state of the defragmentation running on it
+pub struct DefragEngine {
+    pub width: usize,
+    pub height: usize,
+    pub clusters: Vec<ClusterState>,
+    /// Files stored on the simulated disk
+    pub files: Vec<FileFragment>,
+    pub stats: DefragStats,
+    pub phase: DefragPhase,
+    pub animation_step: u64,
+    pub read_pos: Option<usize>,
+    pub write_pos: Option<usize>,
+    pub current_file_read_progress: Option<FileDefragPhase>,
+    pub current_filename: Option<String>,
+    /// Simulation time at which the current read or write completes
+    pub current_op_end_time: Option<Duration>,
+    /// Strategy used for the next defragmentation pass
+    pub method: OptimizationMethod,
+    /// Order files are visited in and laid out by
+    pub sort: FileSort,
+    status_message: String,
+    /// Moves left to perform in the current defragmentation pass
+    plan: VecDeque<ClusterMove>,
+    /// Move being read or written
+    current_move: Option<ClusterMove>,
+    free_space_cache: FreeSpaceCache,
+    /// Operations per second of the simulated drive
+    pub iops: u32,
+    /// Mechanics of the simulated drive, timing each read and write
+    pub geometry: DiskGeometry,
+    /// Cluster the heads are over
+    head_pos: usize,
+    /// Seed the simulation was started with
+    pub seed: u64,
+    /// Report of the last analysis pass
+    pub analysis: Option<AnalysisReport>,
+    /// Wait in the Analyzed phase for [`DefragEngine::proceed`] once the
+    /// analysis is done, instead of defragmenting right away
+    pub confirm_analysis: bool,
+    /// Share of the disk used by files in generated layouts
+    pub fill_percent: f32,
+    /// Share of the disk made of bad blocks in generated layouts
+    pub bad_ratio: f32,
+    /// Layout restored on restart instead of generating a new one
+    fixed_layout: Option<DiskLayout>,
+    rng: SimRng,
+    /// Simulation time of the last update
+    now: Duration,
+    events: Vec<SimEvent>,
+}
+
+impl DefragEngine {
+    /// Creates an engine with a freshly generated `width` x `height` disk,
+    /// `fill_percent` of it used by files and `bad_ratio` of it bad blocks
+    pub fn new(
+        width: usize,
+        height: usize,
+        fill_percent: f32,
+        bad_ratio: f32,
+        iops: u32,
+        seed: u64,
+    ) -> Self {
+        let mut rng = SimRng::seed_from_u64(seed);
+        let layout = DiskLayout::generate(width * height, fill_percent, bad_ratio, &mut rng);
+        let mut engine = Self::build(width, height, layout, iops, seed, rng);
+        engine.fill_percent = fill_percent;
+        engine.bad_ratio = bad_ratio;
+        engine
+    }
+
+    /// Creates an engine for an existing disk layout (e.g. read from a disk
+    /// image), which restarts always go back to
+    pub fn with_layout(
+        width: usize,
+        height: usize,
+        layout: DiskLayout,
+        iops: u32,
+        seed: u64,
+    ) -> Self {
+        let rng = SimRng::seed_from_u64(seed);
+        let mut engine = Self::build(width, height, layout.clone(), iops, seed, rng);
+        engine.fixed_layout = Some(layout);
+        engine
+    }
+
+    fn build(
+        width: usize,
+        height: usize,
+        layout: DiskLayout,
+        iops: u32,
+        seed: u64,
+        rng: SimRng,
+    ) -> Self {
+        Self {
+            width,
+            height,
+            stats: DefragStats {
+                total_to_defrag: layout.file_clusters(),
+                clusters_defragged: 0,
+                start_time: Duration::ZERO,
+            },
+            clusters: layout.clusters,
+            files: layout.files,
+            phase: DefragPhase::Initializing,
+            animation_step: 0,
+            read_pos: None,
+            write_pos: None,
+            current_file_read_progress: None,
+            current_filename: None,
+            current_op_end_time: None,
+            method: OptimizationMethod::default(),
+            sort: FileSort::default(),
+            status_message: "Initializing...".to_string(),
+            plan: VecDeque::new(),
+            current_move: None,
+            free_space_cache: FreeSpaceCache::new(),
+            iops,
+            geometry: disk::DEFAULT_DRIVE.geometry,
+            head_pos: 0,
+            seed,
+            analysis: None,
+            confirm_analysis: false,
+            fill_percent: ui_const::DEFAULT_FILL_PERCENT,
+            bad_ratio: ui_const::BAD_BLOCK_PERCENT,
+            fixed_layout: None,
+            rng,
+            now: Duration::ZERO,
+            events: Vec::new(),
+        }
+    }
+
+    /// Generates a new disk (or reloads the fixed one) and starts over from
+    /// the Initializing phase
+    pub fn restart(&mut self) {
+        let layout = match self.fixed_layout {
+            Some(ref layout) => layout.clone(),
+            None => DiskLayout::generate(
+                self.width * self.height,
+                self.fill_percent,
+                self.bad_ratio,
+                &mut self.rng,
+            ),
+        };
+        self.stats = DefragStats {
+            total_to_defrag: layout.file_clusters(),
+            clusters_defragged: 0,
+            start_time: self.now,
+        };
+        self.clusters = layout.clusters;
+        self.files = layout.files;
+
+        self.phase = DefragPhase::Initializing;
+        self.animation_step = 0;
+        self.read_pos = None;
+        self.write_pos = None;
+        self.head_pos = 0;
+        self.current_file_read_progress = None;
+        self.current_filename = None;
+        self.current_op_end_time = None;
+        self.status_message = "Initializing...".to_string();
+        self.plan.clear();
+        self.current_move = None;
+        self.events.clear();
+
+        self.free_space_cache.invalidate();
+    }
+
+    /// Starts (or restarts) the analysis pass, unless a defrag is running
+    pub fn start_analysis(&mut self) {
+        if matches!(
+            self.phase,
+            DefragPhase::Initializing | DefragPhase::Analyzed | DefragPhase::Finished
+        ) {
+            self.phase = DefragPhase::Analyzing;
+            self.animation_step = 0;
+        }
+    }
+
+    /// Starts defragmenting once the analysis report has been confirmed
+    pub fn proceed(&mut self) {
+        if self.phase == DefragPhase::Analyzed {
+            self.start_defragmenting();
+        }
+    }
+
+    /// Stops the run where it is
+    pub fn stop(&mut self) {
+        self.phase = DefragPhase::Finished;
+        self.animation_step = 0;
+    }
+
+    /// Advances the simulation to time `now`
+    pub fn update(&mut self, now: Duration) {
+        self.now = now;
+        self.animation_step += 1;
+
+        if self.phase != DefragPhase::Defragmenting {
+            self.status_message = self.get_phase_status().to_string();
+        }
+
+        match self.phase {
+            DefragPhase::Initializing => {
+                if self.animation_step > INIT_TICKS {
+                    self.phase = DefragPhase::Analyzing;
+                    self.animation_step = 0;
+                }
+            }
+            DefragPhase::Analyzing => {
+                let total_clusters = self.clusters.len();
+                let scan_pos = (self.animation_step as usize * ANALYZE_STEP)
+                    .min(total_clusters.saturating_sub(1));
+                self.read_pos = Some(scan_pos);
+
+                if self.animation_step.is_multiple_of(3) {
+                    let distance = self.stroke(scan_pos.saturating_sub(3 * ANALYZE_STEP), scan_pos);
+                    self.events.push(SimEvent::Seek { distance });
+                }
+
+                if self.animation_step > (total_clusters / ANALYZE_STEP) as u64 + 10 {
+                    self.read_pos = None;
+                    self.analysis = Some(self.analyze());
+                    if self.confirm_analysis {
+                        self.phase = DefragPhase::Analyzed;
+                        self.animation_step = 0;
+                    } else {
+                        self.start_defragmenting();
+                    }
+                }
+            }
+            DefragPhase::Analyzed => {}
+            DefragPhase::Defragmenting => {
+                if self.current_op_end_time.is_none_or(|t| now >= t) {
+                    match self.current_file_read_progress {
+                        None => self.start_next_move(),
+                        Some(FileDefragPhase::Reading { .. }) => self.finish_read(),
+                        Some(FileDefragPhase::Writing { .. }) => self.finish_write(),
+                        Some(FileDefragPhase::Completed) => {
+                            self.current_file_read_progress = None;
+                            self.current_move = None;
+                            self.current_filename = None;
+                            self.current_op_end_time = Some(now);
+                            self.status_message = "Looking for next file...".to_string();
+                        }
+                    }
+                } else {
+                    let dots = ".".repeat((self.animation_step % 4) as usize);
+                    let base_message = match self.current_file_read_progress {
+                        Some(FileDefragPhase::Reading { .. }) => "Reading",
+                        Some(FileDefragPhase::Writing { .. }) => "Writing",
+                        _ => "Processing",
+                    };
+                    self.status_message = format!(
+                        "{} {}{}",
+                        base_message,
+                        self.current_filename.as_deref().unwrap_or("file"),
+                        dots
+                    );
+                }
+            }
+            DefragPhase::Finished => {}
+        }
+    }
+
+    /// Runs the simulation on virtual time until it finishes
+    ///
+    /// Each update advances time by `step`. Returns the simulated duration of
+    /// the run.
+    pub fn run_to_completion(&mut self, step: Duration) -> Duration {
+        let mut clock = VirtualClock::new();
+        clock.advance(self.now);
+        while self.phase != DefragPhase::Finished {
+            clock.advance(step);
+            self.update(clock.now());
+        }
+        self.elapsed()
+    }
+
+    /// Drains the disk activity produced since the last call
+    pub fn take_events(&mut self) -> Vec<SimEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Simulation time elapsed since the run started
+    pub fn elapsed(&self) -> Duration {
+        self.now.saturating_sub(self.stats.start_time)
+    }
+
+    pub fn estimated_time_remaining(&self) -> Option<Duration> {
+        if self.stats.clusters_defragged == 0 || self.phase != DefragPhase::Defragmenting {
+            return None;
+        }
+
+        let elapsed = self.elapsed();
+        let remaining = self
+            .stats
+            .total_to_defrag
+            .saturating_sub(self.stats.clusters_defragged);
+
+        if remaining == 0 {
+            return Some(Duration::ZERO);
+        }
+
+        let rate = self.stats.clusters_defragged as f64 / elapsed.as_secs_f64();
+        if rate <= 0.0 || !rate.is_finite() {
+            return None;
+        }
+
+        let remaining_secs = remaining as f64 / rate;
+        Some(Duration::from_secs_f64(remaining_secs))
+    }
+
+    pub fn progress_percent(&self) -> f32 {
+        if self.stats.total_to_defrag == 0 {
+            return 100.0;
+        }
+        (self.stats.clusters_defragged as f32 / self.stats.total_to_defrag as f32) * 100.0
+    }
+
+    pub fn count_clusters(&self, state: ClusterState) -> usize {
+        self.clusters.iter().filter(|&&c| c == state).count()
+    }
+
+    /// Share of the files stored in several fragments, from 0 to 100
+    pub fn fragmented_files_percent(&self) -> f32 {
+        if self.files.is_empty() {
+            return 0.0;
+        }
+        let fragmented = self.files.iter().filter(|file| file.is_fragmented).count();
+        fragmented as f32 * 100.0 / self.files.len() as f32
+    }
+
+    pub fn fragmentation_percent(&self) -> f32 {
+        let pending = self.count_clusters(ClusterState::Pending);
+        let total_data = pending + self.count_clusters(ClusterState::Used);
+        if total_data == 0 {
+            return 0.0;
+        }
+        pending as f32 / total_data as f32
+    }
+
+    /// File being read or written
+    pub fn current_file(&self) -> Option<&FileFragment> {
+        self.current_move
+            .as_ref()
+            .and_then(|current| self.files.get(current.file))
+    }
+
+    pub fn status_message(&self) -> &str {
+        &self.status_message
+    }
+
+    fn get_phase_status(&self) -> &'static str {
+        match self.phase {
+            DefragPhase::Initializing => "Initializing...",
+            DefragPhase::Analyzing => "Analyzing disk...",
+            DefragPhase::Analyzed => "Analysis complete",
+            DefragPhase::Defragmenting => "Defragmenting...",
+            DefragPhase::Finished => "Complete",
+        }
+    }
+}
+
+impl DefragEngine {
+    /// Measures file and free-space fragmentation
+    fn analyze(&mut self) -> AnalysisReport {
+        self.free_space_cache.rebuild(&self.clusters);
+        let regions = self.free_space_cache.regions();
+
+        AnalysisReport {
+            total_files: self.files.len(),
+            fragmented_files: self.files.iter().filter(|file| file.is_fragmented).count(),
+            free_clusters: regions.iter().map(|&(_, length)| length).sum(),
+            free_runs: regions.len(),
+            largest_free_run: regions.first().map_or(0, |&(_, length)| length),
+            bad_clusters: self.count_clusters(ClusterState::Bad),
+        }
+    }
+
+    /// Plans the defragmentation pass and switches to the Defragmenting phase
+    fn start_defragmenting(&mut self) {
+        self.plan = planner::plan(self.method, self.sort, &self.clusters, &self.files).into();
+        // Files the plan cannot place stay where they are and do not count
+        self.stats.total_to_defrag = self
+            .plan
+            .iter()
+            .filter(|step| step.completes_file)
+            .map(|step| self.files[step.file].size)
+            .sum();
+        self.stats.clusters_defragged = 0;
+        self.phase = DefragPhase::Defragmenting;
+        self.animation_step = 0;
+        self.current_op_end_time = Some(self.now);
+    }
+
+    /// Duration of a read or write of `length` clusters at `target`, moving
+    /// the heads there
+    ///
+    /// The access is timed from the drive geometry (seek over the cylinders
+    /// crossed, rotational latency, transfer), then scaled so that an average
+    /// access takes 2000 / IOPS milliseconds: the animation keeps the pace of
+    /// the drive profile, and long seeks take longer than short ones.
+    fn operation_duration(&mut self, target: usize, length: usize) -> Duration {
+        let average_ms = 2000.0 / self.iops.max(1) as f64;
+        let geometry = self.geometry;
+        let total = self.clusters.len();
+        let distance = geometry
+            .cylinder_of(self.head_pos, total)
+            .abs_diff(geometry.cylinder_of(target, total));
+        self.events.push(SimEvent::Seek {
+            distance: self.stroke(self.head_pos, target),
+        });
+        self.head_pos = target + length.saturating_sub(1);
+
+        let access_ms = geometry.average_access_ms() as f64;
+        if access_ms <= 0.0 {
+            return Duration::from_secs_f64(average_ms / 1000.0);
+        }
+        let revolution_ms = geometry.revolution_ms() as f64;
+        let latency_ms = if revolution_ms > 0.0 {
+            self.rng.gen_range(0.0..revolution_ms)
+        } else {
+            0.0
+        };
+        let operation_ms = geometry.seek_ms(distance) as f64
+            + latency_ms
+            + length as f64 * geometry.sector_ms() as f64;
+        Duration::from_secs_f64(average_ms * operation_ms / access_ms / 1000.0)
+    }
+
+    /// Share of the full stroke the heads cross between clusters `from` and
+    /// `to`, from 0 to 1
+    fn stroke(&self, from: usize, to: usize) -> f32 {
+        let total = self.clusters.len();
+        let cylinders = self
+            .geometry
+            .cylinder_of(from, total)
+            .abs_diff(self.geometry.cylinder_of(to, total));
+        cylinders as f32 / self.geometry.cylinders.saturating_sub(1).max(1) as f32
+    }
+
+    /// Takes the next move of the plan and starts reading its source clusters
+    fn start_next_move(&mut self) {
+        let Some(next_move) = self.plan.pop_front() else {
+            self.phase = DefragPhase::Finished;
+            self.animation_step = 0;
+            self.current_filename = None;
+            self.read_pos = None;
+            self.write_pos = None;
+            return;
+        };
+
+        let filename = self.files[next_move.file].name.clone();
+        if next_move.from.is_empty() {
+            // File already in place: nothing to read, only mark it optimized
+            self.status_message = format!("Checking {}...", filename);
+            self.current_file_read_progress = Some(FileDefragPhase::Writing { progress: 0 });
+            self.current_op_end_time = Some(self.now);
+        } else {
+            for &cluster in &next_move.from {
+                self.clusters[cluster] = ClusterState::Reading;
+            }
+            self.read_pos = next_move.from.first().copied();
+            self.status_message = format!("Reading {}...", filename);
+            self.current_file_read_progress = Some(FileDefragPhase::Reading { progress: 0 });
+            let duration = self.operation_duration(next_move.from[0], next_move.from.len());
+            self.current_op_end_time = Some(self.now + duration);
+        }
+
+        self.current_filename = Some(filename);
+        self.current_move = Some(next_move);
+    }
+
+    /// Frees the source clusters of the current move and starts writing
+    fn finish_read(&mut self) {
+        let Some(ref current) = self.current_move else {
+            return;
+        };
+
+        for &cluster in &current.from {
+            self.clusters[cluster] = ClusterState::Unused;
+        }
+        for &cluster in &current.to {
+            self.clusters[cluster] = ClusterState::Writing;
+        }
+        self.write_pos = current.to.first().copied();
+        let write = self.write_pos.map(|target| (target, current.to.len()));
+        self.events.push(SimEvent::Read {
+            clusters: current.from.len(),
+        });
+
+        self.current_file_read_progress = Some(FileDefragPhase::Writing { progress: 0 });
+        self.status_message = format!(
+            "Writing {}...",
+            self.current_filename.as_deref().unwrap_or("file")
+        );
+        let duration = write.map_or(Duration::ZERO, |(target, length)| {
+            self.operation_duration(target, length)
+        });
+        self.current_op_end_time = Some(self.now + duration);
+        self.free_space_cache.invalidate();
+    }
+
+    /// Commits the current move to the file and marks it optimized when done
+    fn finish_write(&mut self) {
+        let Some(ref current) = self.current_move else {
+            return;
+        };
+
+        let file = &mut self.files[current.file];
+        let indices: Vec<Option<usize>> = current
+            .from
+            .iter()
+            .map(|cluster| file.clusters.iter().position(|c| c == cluster))
+            .collect();
+        for (index, &cluster) in indices.into_iter().zip(&current.to) {
+            if let Some(index) = index {
+                file.relocate(index, cluster);
+            }
+        }
+
+        if current.completes_file {
+            for &cluster in &file.clusters {
+                self.clusters[cluster] = ClusterState::Used;
+            }
+            self.stats.clusters_defragged += file.size;
+        } else {
+            for &cluster in &current.to {
+                self.clusters[cluster] = ClusterState::Pending;
+            }
+        }
+
+        if !current.to.is_empty() {
+            self.events.push(SimEvent::Write {
+                clusters: current.to.len(),
+            });
+        }
+
+        self.current_file_read_progress = Some(FileDefragPhase::Completed);
+        self.status_message = format!(
+            "Finishing {}...",
+            self.current_filename.as_deref().unwrap_or("file")
+        );
+        self.current_op_end_time = Some(self.now);
+    }
+}
+
+impl DefragEngine {
+    /// Simulation time of the last update
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Everything needed to carry on the run later
+    ///
+    /// The file names drawn from the `DosFileProvider` are saved with the
+    /// files; the queue itself only lives while a layout is generated, and
+    /// the next one is shuffled by the saved random generator.
+    pub fn save_state(&self) -> EngineState {
+        EngineState {
+            width: self.width,
+            height: self.height,
+            clusters: self.clusters.clone(),
+            files: self.files.clone(),
+            stats: self.stats.clone(),
+            phase: self.phase,
+            animation_step: self.animation_step,
+            read_pos: self.read_pos,
+            write_pos: self.write_pos,
+            operation: self.current_file_read_progress.clone(),
+            current_filename: self.current_filename.clone(),
+            current_op_end_time: self.current_op_end_time,
+            method: self.method,
+            sort: self.sort,
+            current_move: self.current_move.clone(),
+            plan: self.plan.clone(),
+            status_message: self.status_message.clone(),
+            iops: self.iops,
+            geometry: self.geometry,
+            head_pos: self.head_pos,
+            seed: self.seed,
+            fill_percent: self.fill_percent,
+            bad_ratio: self.bad_ratio,
+            analysis: self.analysis,
+            confirm_analysis: self.confirm_analysis,
+            fixed_layout: self.fixed_layout.clone(),
+            rng: self.rng.clone(),
+            now: self.now,
+        }
+    }
+
+    /// Puts the engine back in a state saved by [`DefragEngine::save_state`]
+    ///
+    /// The state is checked before anything changes, so the engine is left
+    /// untouched when it is invalid. Times are restored as they were: the
+    /// caller's clock has to go on from [`DefragEngine::now`].
+    pub fn restore_state(&mut self, state: EngineState) -> Result<()> {
+        let total = state
+            .width
+            .checked_mul(state.height)
+            .ok_or_else(|| invalid("disk too large"))?;
+        check_layout(&state.clusters, &state.files, total, state.width)?;
+        // Restarts go back to the fixed layout, which must fit the disk too
+        if let Some(ref layout) = state.fixed_layout {
+            check_layout(&layout.clusters, &layout.files, total, state.width)?;
+        }
+        state
+            .geometry
+            .check()
+            .map_err(|message| invalid(&message))?;
+        if !(disk::is_ratio(state.fill_percent) && disk::is_ratio(state.bad_ratio)) {
+            return Err(invalid(
+                "`fill_percent` and `bad_ratio` must be between 0 and 1",
+            ));
+        }
+        let in_disk = |index: &usize| *index < state.clusters.len();
+        let valid_move = |step: &ClusterMove| {
+            step.file < state.files.len()
+                && step.from.len() == step.to.len()
+                && step.from.iter().chain(&step.to).all(in_disk)
+        };
+        if !state.plan.iter().chain(&state.current_move).all(valid_move) {
+            return Err(invalid("move out of the disk"));
+        }
+        let heads = state.read_pos.iter().chain(&state.write_pos);
+        if !(state.head_pos < state.clusters.len().max(1) && heads.clone().all(in_disk)) {
+            return Err(invalid("head position out of the disk"));
+        }
+
+        self.width = state.width;
+        self.height = state.height;
+        self.clusters = state.clusters;
+        self.files = state.files;
+        // The flags of a stale or edited file are not trusted
+        for file in &mut self.files {
+            file.update_fragmentation();
+        }
+        self.stats = state.stats;
+        self.phase = state.phase;
+        self.animation_step = state.animation_step;
+        self.read_pos = state.read_pos;
+        self.write_pos = state.write_pos;
+        self.current_file_read_progress = state.operation;
+        self.current_filename = state.current_filename;
+        self.current_op_end_time = state.current_op_end_time;
+        self.method = state.method;
+        self.sort = state.sort;
+        self.current_move = state.current_move;
+        self.plan = state.plan;
+        self.status_message = state.status_message;
+        self.iops = state.iops;
+        self.geometry = state.geometry;
+        self.head_pos = state.head_pos;
+        self.seed = state.seed;
+        self.fill_percent = state.fill_percent;
+        self.bad_ratio = state.bad_ratio;
+        self.analysis = state.analysis;
+        self.confirm_analysis = state.confirm_analysis;
+        self.fixed_layout = state.fixed_layout;
+        self.rng = state.rng;
+        self.now = state.now;
+        self.events.clear();
+        self.free_space_cache.invalidate();
+        Ok(())
+    }
+}
+
+/// A run saved by [`DefragEngine::save_state`]
+#[derive(Serialize, Deserialize)]
+pub struct EngineState {
+    width: usize,
+    height: usize,
+    #[serde(with = "cluster_codes")]
+    clusters: Vec<ClusterState>,
+    files: Vec<FileFragment>,
+    stats: DefragStats,
+    phase: DefragPhase,
+    animation_step: u64,
+    read_pos: Option<usize>,
+    write_pos: Option<usize>,
+    operation: Option<FileDefragPhase>,
+    current_filename: Option<String>,
+    current_op_end_time: Option<Duration>,
+    method: OptimizationMethod,
+    sort: FileSort,
+    current_move: Option<ClusterMove>,
+    plan: VecDeque<ClusterMove>,
+    status_message: String,
+    iops: u32,
+    geometry: DiskGeometry,
+    head_pos: usize,
+    seed: u64,
+    fill_percent: f32,
+    bad_ratio: f32,
+    analysis: Option<AnalysisReport>,
+    confirm_analysis: bool,
+    fixed_layout: Option<DiskLayout>,
+    rng: SimRng,
+    now: Duration,
+}
+
+/// Checks that a saved layout fills a grid of `total` cells, `width` of them
+/// per row, and that its files lie on it
+///
+/// The last row of a disk image or scan may be partly used.
+fn check_layout(
+    clusters: &[ClusterState],
+    files: &[FileFragment],
+    total: usize,
+    width: usize,
+) -> Result<()> {
+    if clusters.len() > total || total - clusters.len() >= width.max(1) {
+        return Err(invalid("invalid `clusters`"));
+    }
+    if !files
+        .iter()
+        .all(|file| file.clusters.iter().all(|&index| index < clusters.len()))
+    {
+        return Err(invalid("file cluster out of the disk"));
+    }
+    if files.iter().any(|file| file.size != file.clusters.len()) {
+        return Err(invalid("file size does not match its clusters"));
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid saved state: {}", message),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_full_run_on_virtual_time() {
+        let mut engine = DefragEngine::new(78, 16, 0.65, 0.02, 2, 7);
+        let elapsed = engine.run_to_completion(Duration::from_millis(80));
+
+        assert!(elapsed > Duration::ZERO);
+        assert_eq!(
+            engine.stats.clusters_defragged,
+            engine.stats.total_to_defrag
+        );
+        assert_eq!(engine.count_clusters(ClusterState::Pending), 0);
+        assert_eq!(engine.count_clusters(ClusterState::Reading), 0);
+        assert_eq!(engine.count_clusters(ClusterState::Writing), 0);
+        assert!(engine.files.iter().all(|file| !file.is_fragmented));
+    }
+
+    #[test]
+    fn test_full_disk_run_ends_at_full_progress() {
+        for method in OptimizationMethod::ALL {
+            let mut engine = DefragEngine::new(40, 10, 0.95, 0.02, 50, 4);
+            engine.method = method;
+            engine.run_to_completion(Duration::from_millis(80));
+
+            // Some files find no room and are left as they are
+            let file_clusters: usize = engine.files.iter().map(|file| file.size).sum();
+            assert!((1..file_clusters).contains(&engine.stats.total_to_defrag));
+            assert_eq!(
+                engine.stats.clusters_defragged,
+                engine.stats.total_to_defrag
+            );
+            assert_eq!(engine.progress_percent(), 100.0);
+        }
+    }
+
+    #[test]
+    fn test_same_seed_replays_same_run() {
+        let mut first = DefragEngine::new(40, 10, 0.65, 0.02, 3, 1234);
+        let mut second = DefragEngine::new(40, 10, 0.65, 0.02, 3, 1234);
+        let step = Duration::from_millis(80);
+
+        let mut clock = VirtualClock::new();
+        while first.phase != DefragPhase::Finished {
+            clock.advance(step);
+            first.update(clock.now());
+            second.update(clock.now());
+            assert_eq!(first.clusters, second.clusters);
+            assert_eq!(first.current_filename, second.current_filename);
+        }
+        assert_eq!(second.phase, DefragPhase::Finished);
+    }
+
+    #[test]
+    fn test_restore_recomputes_fragmentation_and_checks_sizes() {
+        let mut engine = DefragEngine::new(40, 10, 0.65, 0.02, 2, 21);
+        let fragmented: Vec<bool> = engine.files.iter().map(|f| f.is_fragmented).collect();
+
+        let mut state = engine.save_state();
+        for file in &mut state.files {
+            file.is_fragmented = !file.is_fragmented;
+        }
+        engine.restore_state(state).unwrap();
+        let restored: Vec<bool> = engine.files.iter().map(|f| f.is_fragmented).collect();
+        assert_eq!(restored, fragmented);
+
+        let mut state = engine.save_state();
+        state.files[0].size += 1;
+        let error = engine.restore_state(state).unwrap_err();
+        assert!(error.to_string().contains("size"), "{}", error);
+        assert_eq!(engine.files[0].size, engine.files[0].clusters.len());
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_settings_and_fixed_layouts() {
+        let mut engine = DefragEngine::new(40, 10, 0.65, 0.02, 2, 21);
+        let clusters = engine.clusters.clone();
+
+        let mut state = engine.save_state();
+        state.width = usize::MAX;
+        state.height = 2;
+        assert!(engine.restore_state(state).is_err());
+
+        let mut state = engine.save_state();
+        state.geometry.full_stroke_ms = -1.0;
+        let error = engine.restore_state(state).unwrap_err();
+        assert!(error.to_string().contains("full_stroke_ms"), "{}", error);
+
+        let mut state = engine.save_state();
+        state.bad_ratio = f32::NAN;
+        assert!(engine.restore_state(state).is_err());
+
+        let mut state = engine.save_state();
+        let mut layout = DiskLayout {
+            clusters: clusters.clone(),
+            files: engine.files.clone(),
+        };
+        layout.clusters.push(ClusterState::Unused);
+        state.fixed_layout = Some(layout);
+        assert!(engine.restore_state(state).is_err());
+
+        // Nothing was written by the rejected states
+        assert_eq!((engine.width, engine.height), (40, 10));
+        assert_eq!(engine.clusters, clusters);
+        assert!(engine.fixed_layout.is_none());
+    }
+
+    #[test]
+    fn test_long_seeks_take_longer() {
+        let mut engine = DefragEngine::new(78, 16, 0.65, 0.02, 2, 99);
+        let last = engine.clusters.len() - 1;
+        let mut short = Duration::ZERO;
+        let mut long = Duration::ZERO;
+        for _ in 0..100 {
+            engine.head_pos = 0;
+            short += engine.operation_duration(1, 1);
+            engine.head_pos = 0;
+            long += engine.operation_duration(last, 1);
+        }
+        assert!(long > short * 2, "long {:?}, short {:?}", long, short);
+
+        // Without moving parts every access takes the same time
+        engine.geometry = disk::SSD_GEOMETRY;
+        assert_eq!(
+            engine.operation_duration(0, 1),
+            engine.operation_duration(last, 1)
+        );
+    }
+
+    #[test]
+    fn test_analysis_waits_for_confirmation() {
+        let mut engine = DefragEngine::new(40, 10, 0.65, 0.02, 2, 5);
+        engine.confirm_analysis = true;
+        let mut clock = VirtualClock::new();
+        while engine.phase != DefragPhase::Analyzed {
+            clock.advance(Duration::from_millis(80));
+            engine.update(clock.now());
+            assert_ne!(engine.phase, DefragPhase::Defragmenting);
+        }
+
+        let report = engine.analysis.unwrap();
+        assert_eq!(report.total_files, engine.files.len());
+        assert_eq!(
+            report.bad_clusters,
+            engine.count_clusters(ClusterState::Bad)
+        );
+        assert_eq!(
+            report.free_clusters,
+            engine.count_clusters(ClusterState::Unused)
+        );
+        assert!(report.largest_free_run <= report.free_clusters);
+        assert!(report.is_defrag_recommended());
+        assert!(report
+            .verdict()
+            .ends_with("Defragmentation is recommended."));
+
+        engine.update(clock.now());
+        assert_eq!(engine.phase, DefragPhase::Analyzed);
+        engine.proceed();
+        assert_eq!(engine.phase, DefragPhase::Defragmenting);
+    }
+
+    #[test]
+    fn test_verdict_counts_fragmented_files() {
+        // One big fragmented file among many small whole ones
+        let report = AnalysisReport {
+            total_files: 20,
+            fragmented_files: 1,
+            ..AnalysisReport::default()
+        };
+        assert_eq!(report.fragmented_files_percent(), 5.0);
+        assert!(!report.is_defrag_recommended());
+        assert_eq!(
+            report.verdict(),
+            "This drive is 5% fragmented. Defragmentation is not recommended."
+        );
+    }
+}
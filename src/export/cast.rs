@@ -0,0 +1,153 @@
+//! Asciinema recordings of the MS-DOS interface
+//!
+//! The run is driven on a virtual clock and every tick `ui::render_app` draws
+//! into a terminal of fixed size whose crossterm backend writes to memory, so
+//! no TTY is needed. The escape sequences ratatui sends for each frame become
+//! the output events of an asciicast v2 file, timed by the virtual clock.
+
+use crate::app::App;
+use crate::clock::{Clock, SharedClock};
+use crate::ui;
+use ratatui::backend::CrosstermBackend;
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::{Terminal, TerminalOptions, Viewport};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{BufWriter, Result, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Memory the terminal writes to, still readable while the backend owns it
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl SharedOutput {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for SharedOutput {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize> {
+        self.0.borrow_mut().extend_from_slice(bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// First line of an asciicast v2 file
+#[derive(Serialize)]
+struct Header {
+    version: u32,
+    width: u16,
+    height: u16,
+    title: String,
+    env: serde_json::Value,
+}
+
+/// Rows of the interface around the grid: menu bar, window borders and the
+/// status and legend boxes
+const CHROME_ROWS: usize = 10;
+
+/// Terminal size fitting the grid of `app` cell for cell, and never smaller
+/// than the 80x25 text screen of MS-DOS
+pub fn terminal_size(app: &App) -> (u16, u16) {
+    let width = (app.engine.width + 2).max(80);
+    let height = (app.engine.height + CHROME_ROWS).max(25);
+    (
+        u16::try_from(width).unwrap_or(u16::MAX),
+        u16::try_from(height).unwrap_or(u16::MAX),
+    )
+}
+
+/// Runs `app` to the end on virtual time and writes what the terminal shows
+/// to `path` as an asciicast v2 recording; returns the number of frames
+pub fn cast(app: &mut App, path: &Path) -> Result<usize> {
+    let clock = SharedClock::new();
+    app.set_clock(Box::new(clock.clone()));
+    app.unattended = true;
+
+    let (width, height) = terminal_size(app);
+    let output = SharedOutput::default();
+    let mut terminal = Terminal::with_options(
+        CrosstermBackend::new(output.clone()),
+        TerminalOptions {
+            viewport: Viewport::Fixed(Rect::new(0, 0, width, height)),
+        },
+    )?;
+
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = Header {
+        version: 2,
+        width,
+        height,
+        title: format!("MS-DOS Defrag - Drive {}:", app.current_drive.letter()),
+        env: serde_json::json!({"TERM": "xterm-256color"}),
+    };
+    writeln!(out, "{}", serde_json::to_string(&header)?)?;
+
+    let mut previous: Option<Buffer> = None;
+    let mut frames = 0;
+    loop {
+        let frame = terminal.draw(|frame| ui::render_app(app, frame))?;
+        // Unchanged screens still send cursor escapes: leave them out
+        let changed = previous.as_ref() != Some(frame.buffer);
+        if changed {
+            previous = Some(frame.buffer.clone());
+        }
+        let bytes = output.take();
+        if changed {
+            let event = (
+                clock.now().as_secs_f64(),
+                "o",
+                String::from_utf8_lossy(&bytes),
+            );
+            writeln!(out, "{}", serde_json::to_string(&event)?)?;
+            frames += 1;
+        }
+
+        if !app.running {
+            break;
+        }
+        app.update();
+        clock.advance(app.tick_rate);
+    }
+    out.flush()?;
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+
+    #[test]
+    fn test_cast_is_timed_asciicast() {
+        let mut app = test_app(30, 4, 6);
+        let path = std::env::temp_dir().join(format!("defrag-cast-{}.cast", std::process::id()));
+        let frames = cast(&mut app, &path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = text.lines();
+        let header = lines.next().unwrap();
+        assert!(header.starts_with("{\"version\":2,\"width\":80,\"height\":25,"));
+        let events: Vec<&str> = lines.collect();
+        assert_eq!(events.len(), frames);
+        assert!(events[0].contains("\\u001b["));
+
+        let mut last = -1.0;
+        for event in events {
+            let (time, kind, _): (f64, String, String) = serde_json::from_str(event).unwrap();
+            assert_eq!(kind, "o");
+            assert!(time >= last);
+            last = time;
+        }
+        assert!(last > 0.0);
+    }
+}
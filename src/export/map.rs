@@ -0,0 +1,308 @@
+//! Text, ANSI and HTML disk maps
+//!
+//! Every format draws one character per cluster, `engine.width` clusters per
+//! row, framed like the MS-DOS window and followed by the legend and the
+//! statistics. The ANSI and HTML maps use the colors of the terminal grid.
+
+use super::{stats_lines, title, vga_rgb, LEGEND};
+use crate::app::App;
+use crate::models::ClusterState;
+use crate::ui::cluster_glyph;
+use std::io::Result;
+use std::path::Path;
+
+/// File format of a saved disk map
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    /// Plain UTF-8 text
+    Text,
+    /// Plain text in code page 437, as MS-DOS would print it
+    Cp437,
+    /// UTF-8 text with ANSI color escapes, for `cat` in a terminal
+    Ansi,
+    /// Standalone HTML page
+    Html,
+}
+
+impl MapFormat {
+    /// Picks the format from a file extension: `.ans`, `.html`/`.htm`,
+    /// `.437`/`.cp437`, anything else being UTF-8 text
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("")
+            .to_ascii_lowercase();
+        match extension.as_str() {
+            "ans" | "ansi" => MapFormat::Ansi,
+            "html" | "htm" => MapFormat::Html,
+            "437" | "cp437" => MapFormat::Cp437,
+            _ => MapFormat::Text,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MapFormat::Text => "text",
+            MapFormat::Cp437 => "CP437 text",
+            MapFormat::Ansi => "ANSI",
+            MapFormat::Html => "HTML",
+        }
+    }
+}
+
+/// Symbol of a cluster in plain text, where colors cannot tell optimized and
+/// fragmented clusters apart
+fn plain_symbol(cluster: ClusterState) -> char {
+    match cluster {
+        ClusterState::Used => '█',
+        ClusterState::Pending => '▓',
+        ClusterState::Unused => '░',
+        ClusterState::Bad => 'B',
+        ClusterState::Unmovable => 'X',
+        ClusterState::Reading => 'r',
+        ClusterState::Writing => 'W',
+    }
+}
+
+/// Writes the disk map of `app` to `path`, in the format its extension names
+pub fn save_map(app: &App, path: &Path) -> Result<MapFormat> {
+    let format = MapFormat::from_path(path);
+    std::fs::write(path, render_map(app, format))?;
+    Ok(format)
+}
+
+/// Renders the disk map of `app` in `format`
+pub fn render_map(app: &App, format: MapFormat) -> Vec<u8> {
+    match format {
+        MapFormat::Text => render_text(app).into_bytes(),
+        MapFormat::Cp437 => to_cp437(&render_text(app)),
+        MapFormat::Ansi => render_ansi(app).into_bytes(),
+        MapFormat::Html => render_html(app).into_bytes(),
+    }
+}
+
+fn rows(app: &App) -> std::slice::Chunks<'_, ClusterState> {
+    app.engine.clusters.chunks(app.engine.width.max(1))
+}
+
+fn render_text(app: &App) -> String {
+    let width = app.engine.width.max(1);
+    let mut out = format!("{}\n╔{}╗\n", title(app), "═".repeat(width));
+    for row in rows(app) {
+        let cells: String = row.iter().map(|&c| plain_symbol(c)).collect();
+        out += &format!("║{:<width$}║\n", cells, width = width);
+    }
+    out += &format!("╚{}╝\n", "═".repeat(width));
+
+    let legend: Vec<String> = LEGEND
+        .iter()
+        .map(|&(state, text)| format!("{} {}", plain_symbol(state), text))
+        .collect();
+    out += &format!("Legend: {}\n", legend.join("  "));
+    for line in stats_lines(app) {
+        out += &line;
+        out.push('\n');
+    }
+    out
+}
+
+/// Maps the characters of a text map to code page 437, `?` standing for
+/// those it lacks
+fn to_cp437(text: &str) -> Vec<u8> {
+    text.chars()
+        .flat_map(|c| match c {
+            '\n' => vec![b'\r', b'\n'],
+            c if c.is_ascii() => vec![c as u8],
+            '░' => vec![0xB0],
+            '▒' => vec![0xB1],
+            '▓' => vec![0xB2],
+            '█' => vec![0xDB],
+            '║' => vec![0xBA],
+            '═' => vec![0xCD],
+            '╔' => vec![0xC9],
+            '╗' => vec![0xBB],
+            '╚' => vec![0xC8],
+            '╝' => vec![0xBC],
+            _ => vec![b'?'],
+        })
+        .collect()
+}
+
+/// SGR escape selecting the colors of a cluster
+fn ansi_colors(cluster: ClusterState) -> String {
+    let (_, style) = cluster_glyph(cluster);
+    let [fr, fg, fb] = vga_rgb(style.fg.unwrap_or_default());
+    let [br, bg, bb] = vga_rgb(style.bg.unwrap_or_default());
+    format!("\x1b[38;2;{fr};{fg};{fb};48;2;{br};{bg};{bb}m")
+}
+
+fn render_ansi(app: &App) -> String {
+    const FRAME: &str = "\x1b[0;97;44m";
+    const RESET: &str = "\x1b[0m";
+
+    let width = app.engine.width.max(1);
+    let mut out = format!(
+        "{FRAME}{:<w$}{RESET}\n{FRAME}╔{}╗{RESET}\n",
+        title(app),
+        "═".repeat(width),
+        w = width + 2
+    );
+    for row in rows(app) {
+        out += FRAME;
+        out += "║";
+        let mut previous = None;
+        for &cluster in row {
+            if previous != Some(cluster) {
+                out += &ansi_colors(cluster);
+                previous = Some(cluster);
+            }
+            out += cluster_glyph(cluster).0;
+        }
+        out += FRAME;
+        out += &" ".repeat(width - row.len());
+        out += "║";
+        out += RESET;
+        out.push('\n');
+    }
+    out += &format!("{FRAME}╚{}╝{RESET}\n", "═".repeat(width));
+
+    out += "Legend:";
+    for (state, text) in LEGEND {
+        out += &format!(
+            " {}{}{RESET} {} ",
+            ansi_colors(state),
+            cluster_glyph(state).0,
+            text
+        );
+    }
+    out.push('\n');
+    for line in stats_lines(app) {
+        out += &line;
+        out.push('\n');
+    }
+    out
+}
+
+fn css_color(rgb: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2])
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn render_html(app: &App) -> String {
+    let mut css = String::new();
+    for (index, (state, _)) in LEGEND.iter().enumerate() {
+        let (_, style) = cluster_glyph(*state);
+        css += &format!(
+            "  .c{} {{ color: {}; background: {}; }}\n",
+            index,
+            css_color(vga_rgb(style.fg.unwrap_or_default())),
+            css_color(vga_rgb(style.bg.unwrap_or_default()))
+        );
+    }
+    let class = |cluster: ClusterState| {
+        LEGEND
+            .iter()
+            .position(|&(state, _)| state == cluster)
+            .unwrap_or(0)
+    };
+
+    let mut grid = String::new();
+    for row in rows(app) {
+        // Runs of one state share a span
+        let mut cells = row.iter().peekable();
+        while let Some(&cluster) = cells.next() {
+            let mut run = String::from(cluster_glyph(cluster).0);
+            while cells.peek() == Some(&&cluster) {
+                run += cluster_glyph(cluster).0;
+                cells.next();
+            }
+            grid += &format!("<span class=\"c{}\">{}</span>", class(cluster), run);
+        }
+        grid.push('\n');
+    }
+
+    let legend: String = LEGEND
+        .iter()
+        .enumerate()
+        .map(|(index, &(state, text))| {
+            format!(
+                "<span class=\"c{}\">{}</span> {}  ",
+                index,
+                cluster_glyph(state).0,
+                text
+            )
+        })
+        .collect();
+    let stats = html_escape(&stats_lines(app).join("\n"));
+    let title = html_escape(&title(app));
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+  body {{ background: #0000aa; color: #ffffff; font-family: \"Perfect DOS VGA 437\", \"Lucida Console\", monospace; }}
+  pre {{ line-height: 1.1; }}
+  .grid {{ display: inline-block; border: 3px double #ffffff; padding: 2px; }}
+{css}</style>
+</head>
+<body>
+<h1>{title}</h1>
+<pre class=\"grid\">{grid}</pre>
+<pre>Legend: {legend}
+
+{stats}</pre>
+</body>
+</html>
+"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+
+    #[test]
+    fn test_formats_draw_every_cluster() {
+        let app = test_app(20, 3, 11);
+        let text = String::from_utf8(render_map(&app, MapFormat::Text)).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[1].chars().count(), 22);
+        assert_eq!(lines[2].chars().next(), Some('║'));
+        assert_eq!(lines[2].chars().nth(1), Some('X'));
+        assert!(text.contains("Drive C:"));
+
+        let cp437 = render_map(&app, MapFormat::Cp437);
+        assert!(!cp437.contains(&b'?'));
+        assert!(cp437.contains(&0xC9));
+
+        let ansi = String::from_utf8(render_map(&app, MapFormat::Ansi)).unwrap();
+        assert_eq!(ansi.matches("║").count(), 6);
+
+        let html = String::from_utf8(render_map(&app, MapFormat::Html)).unwrap();
+        let grid = html
+            .split("<pre class=\"grid\">")
+            .nth(1)
+            .and_then(|rest| rest.split("</pre>").next())
+            .unwrap();
+        assert_eq!(grid.lines().count(), 3);
+        assert!(!grid.contains("c0\"></span>"));
+    }
+
+    #[test]
+    fn test_format_from_extension() {
+        assert_eq!(MapFormat::from_path(Path::new("C.ANS")), MapFormat::Ansi);
+        assert_eq!(MapFormat::from_path(Path::new("map.htm")), MapFormat::Html);
+        assert_eq!(MapFormat::from_path(Path::new("map.437")), MapFormat::Cp437);
+        assert_eq!(MapFormat::from_path(Path::new("map")), MapFormat::Text);
+    }
+}
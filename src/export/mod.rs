@@ -0,0 +1,101 @@
+//! Exports of the disk map
+//!
+//! The cluster grid, its legend and the run statistics can be written to
+//! files: text, ANSI and HTML maps ([`map`]), PostScript or PDF printouts
+//! ([`print`]), PNG snapshots ([`snapshot`]), and recordings of a whole run
+//! as animated images ([`record`]) or asciinema casts ([`cast`]).
+
+pub mod cast;
+pub mod map;
+pub mod print;
+pub mod record;
+pub mod snapshot;
+
+use crate::app::App;
+use crate::models::ClusterState;
+use crate::ui;
+use ratatui::style::Color;
+
+/// Cluster states in legend order, with their legend text
+pub(crate) const LEGEND: [(ClusterState, &str); 7] = [
+    (ClusterState::Used, "Optimized"),
+    (ClusterState::Pending, "Fragmented"),
+    (ClusterState::Reading, "Reading"),
+    (ClusterState::Writing, "Writing"),
+    (ClusterState::Bad, "Bad block"),
+    (ClusterState::Unmovable, "Unmovable"),
+    (ClusterState::Unused, "Unused space"),
+];
+
+/// RGB value of a terminal color, named colors taking their VGA value
+pub(crate) fn vga_rgb(color: Color) -> [u8; 3] {
+    match color {
+        Color::Rgb(r, g, b) => [r, g, b],
+        Color::Black => [0, 0, 0],
+        Color::Red => [170, 0, 0],
+        Color::Green => [0, 170, 0],
+        Color::Yellow => [255, 255, 85],
+        Color::Blue => [0, 0, 170],
+        Color::Magenta => [170, 0, 170],
+        Color::Cyan => [0, 170, 170],
+        Color::Gray => [170, 170, 170],
+        Color::DarkGray => [85, 85, 85],
+        Color::LightRed => [255, 85, 85],
+        Color::LightGreen => [85, 255, 85],
+        Color::LightYellow => [255, 255, 85],
+        Color::LightBlue => [85, 85, 255],
+        Color::LightMagenta => [255, 85, 255],
+        Color::LightCyan => [85, 255, 255],
+        _ => [255, 255, 255],
+    }
+}
+
+/// Title line naming the drive
+pub(crate) fn title(app: &App) -> String {
+    format!(
+        "MS-DOS Defrag - Drive {}: {}",
+        app.current_drive.letter(),
+        app.current_drive.name
+    )
+}
+
+/// Statistics printed under the map
+pub(crate) fn stats_lines(app: &App) -> Vec<String> {
+    let engine = &app.engine;
+    let fragmented = engine
+        .files
+        .iter()
+        .filter(|file| file.is_fragmented)
+        .count();
+    vec![
+        format!(
+            "Status: {:<28} Method: {}",
+            app.status_text(),
+            engine.method.name()
+        ),
+        format!(
+            "Clusters: {}   Files: {} ({} fragmented)   Elapsed: {}   Progress: {:.0}%",
+            engine.clusters.len(),
+            engine.files.len(),
+            fragmented,
+            ui::format_time(engine.elapsed()),
+            engine.progress_percent().min(100.0)
+        ),
+    ]
+}
+
+/// App of the default drive with a `width` × `height` grid laid out from
+/// `seed`, for the tests of the exports
+#[cfg(test)]
+pub(crate) fn test_app(width: usize, height: usize, seed: u64) -> App {
+    let drives = crate::app::DiskDriveCollection::new();
+    App::new(
+        width,
+        height,
+        0.5,
+        false,
+        drives.get_default().clone(),
+        crate::constants::defrag_type::DefragStyle::MsDos,
+        Some(seed),
+    )
+}
@@ -0,0 +1,676 @@
+//! "Print disk map": PostScript and PDF printouts
+//!
+//! The page is laid out like a printout of the DOS days: a Courier header
+//! with the drive details, the cluster map drawn cell by cell in the colors
+//! of the terminal grid, the legend and the statistics. Both formats draw the
+//! same lists of marks on A4 pages, in points from the bottom-left corner; a
+//! map too tall for one page goes on over the next ones. Text is printed in
+//! the Windows-1252 encoding, so accented names come out as they should.
+
+use super::{stats_lines, title, vga_rgb, LEGEND};
+use crate::app::App;
+use crate::models::ClusterState;
+use crate::ui::cluster_glyph;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const BODY_SIZE: f32 = 10.0;
+const LINE_HEIGHT: f32 = 13.0;
+/// Tallest the map may grow, leaving room for the header and the legend
+const MAP_MAX_HEIGHT: f32 = 470.0;
+/// Smallest side of a map cell before the map goes on over several pages
+const MIN_CELL: f32 = 4.0;
+/// Lowest the body may reach, above the footer
+const BOTTOM: f32 = MARGIN + 2.0 * LINE_HEIGHT;
+
+/// File format of a printout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PrintFormat {
+    #[default]
+    PostScript,
+    Pdf,
+}
+
+impl PrintFormat {
+    /// Parses `ps` or `pdf`
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "ps" | "postscript" => Some(PrintFormat::PostScript),
+            "pdf" => Some(PrintFormat::Pdf),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PrintFormat::PostScript => "PS",
+            PrintFormat::Pdf => "PDF",
+        }
+    }
+}
+
+/// Something drawn on the page
+#[derive(Debug, Clone, PartialEq)]
+enum Mark {
+    Fill {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        rgb: [u8; 3],
+    },
+    Stroke {
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        line_width: f32,
+    },
+    /// Courier text, `y` being the baseline
+    Text {
+        x: f32,
+        y: f32,
+        size: f32,
+        bold: bool,
+        rgb: [u8; 3],
+        text: String,
+    },
+}
+
+/// Prints the disk map of `app` to a new file of `dir`, named after the
+/// drive (`DEFRAG-C1.PS`, `DEFRAG-C2.PS`...) so earlier printouts are kept
+pub fn print_map(app: &App, dir: &Path, format: PrintFormat) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let path = (1..=u32::MAX)
+        .map(|n| {
+            dir.join(format!(
+                "DEFRAG-{}{}.{}",
+                app.current_drive.letter(),
+                n,
+                format.extension()
+            ))
+        })
+        .find(|path| !path.exists())
+        .ok_or_else(|| Error::new(ErrorKind::AlreadyExists, "no printout name left"))?;
+    std::fs::write(&path, render_page(app, format))?;
+    Ok(path)
+}
+
+/// Renders the printout of `app` in `format`, with as many pages as its
+/// map needs
+pub fn render_page(app: &App, format: PrintFormat) -> Vec<u8> {
+    let pages = layout(app);
+    match format {
+        PrintFormat::PostScript => postscript(&title(app), &pages).into_bytes(),
+        PrintFormat::Pdf => pdf(&title(app), &pages),
+    }
+}
+
+const BLACK: [u8; 3] = [0, 0, 0];
+
+fn text(x: f32, y: f32, text: String) -> Mark {
+    Mark::Text {
+        x,
+        y,
+        size: BODY_SIZE,
+        bold: false,
+        rgb: BLACK,
+        text,
+    }
+}
+
+/// Writes the title of a page, returning the baseline of its last line
+fn page_title(marks: &mut Vec<Mark>, title: &str) -> f32 {
+    let mut y = PAGE_HEIGHT - MARGIN - 14.0;
+    marks.push(Mark::Text {
+        x: MARGIN,
+        y,
+        size: 14.0,
+        bold: true,
+        rgb: BLACK,
+        text: title.to_string(),
+    });
+    y -= LINE_HEIGHT;
+    marks.push(text(MARGIN, y, "=".repeat(85)));
+    y
+}
+
+/// Lays out the printout, one list of marks per page
+fn layout(app: &App) -> Vec<Vec<Mark>> {
+    let engine = &app.engine;
+    let drive = &app.current_drive;
+    let mut pages = Vec::new();
+    let mut marks = Vec::new();
+    let mut y = page_title(&mut marks, "MS-DOS Defrag - Disk Map");
+
+    let geometry = drive.config.geometry;
+    let mechanics = if geometry.rpm == 0 {
+        "solid state".to_string()
+    } else {
+        format!(
+            "{} cylinders, {} heads, {} sectors, {} rpm",
+            geometry.cylinders, geometry.heads, geometry.sectors_per_track, geometry.rpm
+        )
+    };
+    for line in [
+        format!(
+            "Drive {}: {:<24} Capacity: {:<8} Media: {}",
+            drive.letter(),
+            drive.label,
+            drive.capacity(),
+            drive.config.media.name()
+        ),
+        format!("Geometry: {}", mechanics),
+        format!(
+            "Map: {} clusters, {} per row",
+            engine.clusters.len(),
+            engine.width
+        ),
+    ] {
+        y -= LINE_HEIGHT;
+        marks.push(text(MARGIN, y, line));
+    }
+
+    // The map, as large as one page allows with square cells, or carried
+    // over to the next pages when its cells would get smaller than MIN_CELL
+    let columns = engine.width.max(1);
+    let rows: Vec<&[ClusterState]> = engine.clusters.chunks(columns).collect();
+    let row_cell = (PAGE_WIDTH - 2.0 * MARGIN) / columns as f32;
+    let cell = row_cell
+        .min(MAP_MAX_HEIGHT / rows.len().max(1) as f32)
+        .max(MIN_CELL.min(row_cell));
+    let mut start = 0;
+    loop {
+        y -= LINE_HEIGHT + 6.0;
+        let fit = (((y - BOTTOM) / cell) as usize).max(1);
+        let end = (start + fit).min(rows.len());
+        y = draw_map(&mut marks, &rows[start..end], y, cell) - 6.0;
+        start = end;
+        if start >= rows.len() {
+            break;
+        }
+        pages.push(std::mem::take(&mut marks));
+        y = page_title(&mut marks, "MS-DOS Defrag - Disk Map (continued)");
+    }
+
+    let stats = stats_lines(app);
+    let legend_height = 2.0 * (LINE_HEIGHT + 4.0)
+        + LEGEND.len().div_ceil(4) as f32 * (LINE_HEIGHT + 2.0)
+        + stats.len() as f32 * LINE_HEIGHT;
+    if y - legend_height < BOTTOM {
+        pages.push(std::mem::take(&mut marks));
+        y = page_title(&mut marks, "MS-DOS Defrag - Disk Map (continued)");
+    }
+
+    y -= LINE_HEIGHT + 4.0;
+    marks.push(Mark::Text {
+        x: MARGIN,
+        y,
+        size: BODY_SIZE,
+        bold: true,
+        rgb: BLACK,
+        text: "Legend".to_string(),
+    });
+    for (index, &(state, label)) in LEGEND.iter().enumerate() {
+        if index % 4 == 0 {
+            y -= LINE_HEIGHT + 2.0;
+        }
+        let x = MARGIN + (index % 4) as f32 * 128.0;
+        draw_cell(&mut marks, state, x, y - 2.0, 10.0);
+        marks.push(text(x + 16.0, y, label.to_string()));
+    }
+
+    y -= LINE_HEIGHT + 4.0;
+    marks.push(text(MARGIN, y, "-".repeat(85)));
+    for line in stats {
+        y -= LINE_HEIGHT;
+        marks.push(text(MARGIN, y, line));
+    }
+    pages.push(marks);
+
+    let count = pages.len();
+    for (index, marks) in pages.iter_mut().enumerate() {
+        let footer = if count == 1 {
+            "- Printed by MS-DOS Defrag -".to_string()
+        } else {
+            format!(
+                "- Printed by MS-DOS Defrag - Page {} of {} -",
+                index + 1,
+                count
+            )
+        };
+        marks.push(text(MARGIN, MARGIN, format!("{:^85}", footer)));
+    }
+    pages
+}
+
+/// Draws `rows` of the map from `top` down in a double rule, as on screen,
+/// returning the bottom of the map
+fn draw_map(marks: &mut Vec<Mark>, rows: &[&[ClusterState]], top: f32, cell: f32) -> f32 {
+    let columns = rows.first().map_or(0, |row| row.len());
+    for (row, clusters) in rows.iter().enumerate() {
+        let bottom = top - (row + 1) as f32 * cell;
+        draw_row(marks, clusters, MARGIN, bottom, cell);
+    }
+    let map_width = cell * columns as f32;
+    let map_height = cell * rows.len() as f32;
+    for (gap, line_width) in [(1.5, 0.6), (3.5, 0.6)] {
+        marks.push(Mark::Stroke {
+            x: MARGIN - gap,
+            y: top - map_height - gap,
+            width: map_width + 2.0 * gap,
+            height: map_height + 2.0 * gap,
+            line_width,
+        });
+    }
+    top - map_height
+}
+
+/// Draws a row of the map, one background fill per run of equal colors
+/// before the symbols
+fn draw_row(marks: &mut Vec<Mark>, clusters: &[ClusterState], x: f32, y: f32, cell: f32) {
+    let background = |state: ClusterState| vga_rgb(cluster_glyph(state).1.bg.unwrap_or_default());
+    let mut start = 0;
+    while start < clusters.len() {
+        let rgb = background(clusters[start]);
+        let end = clusters[start..]
+            .iter()
+            .position(|&state| background(state) != rgb)
+            .map_or(clusters.len(), |length| start + length);
+        marks.push(Mark::Fill {
+            x: x + start as f32 * cell,
+            y,
+            width: (end - start) as f32 * cell,
+            height: cell,
+            rgb,
+        });
+        start = end;
+    }
+    for (column, &state) in clusters.iter().enumerate() {
+        draw_symbol(marks, state, x + column as f32 * cell, y, cell);
+    }
+}
+
+fn draw_cell(marks: &mut Vec<Mark>, state: ClusterState, x: f32, y: f32, cell: f32) {
+    marks.push(Mark::Fill {
+        x,
+        y,
+        width: cell,
+        height: cell,
+        rgb: vga_rgb(cluster_glyph(state).1.bg.unwrap_or_default()),
+    });
+    draw_symbol(marks, state, x, y, cell);
+}
+
+/// Draws the symbol of a cluster over its background: a dot for files, the
+/// letter for the others, or a plain fill when the cell is too small for it
+fn draw_symbol(marks: &mut Vec<Mark>, state: ClusterState, x: f32, y: f32, cell: f32) {
+    let (symbol, style) = cluster_glyph(state);
+    let rgb = vga_rgb(style.fg.unwrap_or_default());
+    match state {
+        ClusterState::Unused => {}
+        ClusterState::Used | ClusterState::Pending => {
+            let side = cell * 0.4;
+            marks.push(Mark::Fill {
+                x: x + (cell - side) / 2.0,
+                y: y + (cell - side) / 2.0,
+                width: side,
+                height: side,
+                rgb,
+            });
+        }
+        _ if cell < 4.0 => marks.push(Mark::Fill {
+            x,
+            y,
+            width: cell,
+            height: cell,
+            rgb,
+        }),
+        _ => {
+            // Courier letters are 0.6 em wide and about 0.57 em high
+            let size = cell * 1.3;
+            marks.push(Mark::Text {
+                x: x + (cell - 0.6 * size) / 2.0,
+                y: y + (cell - 0.57 * size) / 2.0,
+                size,
+                bold: true,
+                rgb,
+                text: symbol.to_string(),
+            });
+        }
+    }
+}
+
+/// Formats a coordinate without needless decimals
+fn num(value: f32) -> String {
+    let text = format!("{:.2}", value);
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+fn color(rgb: [u8; 3]) -> String {
+    rgb.map(|c| num(c as f32 / 255.0)).join(" ")
+}
+
+/// Escapes a string for PostScript and PDF, both using `( )` literals
+///
+/// The fonts use the Windows-1252 encoding, which covers Latin-1 and a few
+/// more typographic signs; other characters print as `?`.
+fn literal(text: &str) -> String {
+    let mut out = String::from("(");
+    for c in text.chars() {
+        match win_ansi(c) {
+            Some(b'(' | b')' | b'\\') => {
+                out.push('\\');
+                out.push(c);
+            }
+            Some(byte @ 0x20..=0x7e) => out.push(byte as char),
+            Some(byte) => out += &format!("\\{:03o}", byte),
+            None => out.push('?'),
+        }
+    }
+    out.push(')');
+    out
+}
+
+/// Unicode characters of the Windows-1252 bytes 0x80 to 0x9F, where Latin-1
+/// has control characters
+const WIN_ANSI_HIGH: [(char, u8); 27] = [
+    ('€', 0x80),
+    ('‚', 0x82),
+    ('ƒ', 0x83),
+    ('„', 0x84),
+    ('…', 0x85),
+    ('†', 0x86),
+    ('‡', 0x87),
+    ('ˆ', 0x88),
+    ('‰', 0x89),
+    ('Š', 0x8a),
+    ('‹', 0x8b),
+    ('Œ', 0x8c),
+    ('Ž', 0x8e),
+    ('‘', 0x91),
+    ('’', 0x92),
+    ('“', 0x93),
+    ('”', 0x94),
+    ('•', 0x95),
+    ('–', 0x96),
+    ('—', 0x97),
+    ('˜', 0x98),
+    ('™', 0x99),
+    ('š', 0x9a),
+    ('›', 0x9b),
+    ('œ', 0x9c),
+    ('ž', 0x9e),
+    ('Ÿ', 0x9f),
+];
+
+/// Windows-1252 byte of a printable character, if it has one
+fn win_ansi(c: char) -> Option<u8> {
+    match c {
+        ' '..='~' | '\u{a0}'..='ÿ' => Some(c as u8),
+        _ => WIN_ANSI_HIGH
+            .iter()
+            .find(|&&(high, _)| high == c)
+            .map(|&(_, byte)| byte),
+    }
+}
+
+/// PostScript names of the Windows-1252 glyphs from 0x80 to 0x9F
+const WIN_ANSI_PS_HIGH: &str = "/Euro /.notdef /quotesinglbase /florin /quotedblbase /ellipsis \
+    /dagger /daggerdbl /circumflex /perthousand /Scaron /guilsinglleft /OE /.notdef /Zcaron \
+    /.notdef /.notdef /quoteleft /quoteright /quotedblleft /quotedblright /bullet /endash \
+    /emdash /tilde /trademark /scaron /guilsinglright /oe /.notdef /zcaron /Ydieresis";
+
+fn postscript(title: &str, pages: &[Vec<Mark>]) -> String {
+    let mut out = format!(
+        "%!PS-Adobe-3.0\n\
+         %%Title: {}\n\
+         %%Creator: MS-DOS Defrag\n\
+         %%BoundingBox: 0 0 {} {}\n\
+         %%Pages: {}\n\
+         %%EndComments\n\
+         %%BeginProlog\n\
+         /WinAnsiEncoding ISOLatin1Encoding 256 array copy\n\
+         dup 39 /quotesingle put dup 96 /grave put\n\
+         dup 128 [{}] putinterval def\n\
+         /reencode {{ findfont dup length dict begin\n\
+         {{ 1 index /FID ne {{ def }} {{ pop pop }} ifelse }} forall\n\
+         /Encoding WinAnsiEncoding def currentdict end definefont pop }} bind def\n\
+         /Courier-Win /Courier reencode\n\
+         /Courier-Bold-Win /Courier-Bold reencode\n\
+         %%EndProlog\n",
+        title.replace(['(', ')'], ""),
+        PAGE_WIDTH,
+        PAGE_HEIGHT,
+        pages.len(),
+        WIN_ANSI_PS_HIGH
+    );
+    for (index, marks) in pages.iter().enumerate() {
+        out += &format!("%%Page: {} {}\n", index + 1, index + 1);
+        for mark in marks {
+            out += &postscript_mark(mark);
+        }
+        out += "showpage\n";
+    }
+    out += "%%EOF\n";
+    out
+}
+
+fn postscript_mark(mark: &Mark) -> String {
+    match mark {
+        Mark::Fill {
+            x,
+            y,
+            width,
+            height,
+            rgb,
+        } => format!(
+            "{} setrgbcolor {} {} {} {} rectfill\n",
+            color(*rgb),
+            num(*x),
+            num(*y),
+            num(*width),
+            num(*height)
+        ),
+        Mark::Stroke {
+            x,
+            y,
+            width,
+            height,
+            line_width,
+        } => format!(
+            "0 setgray {} setlinewidth {} {} {} {} rectstroke\n",
+            num(*line_width),
+            num(*x),
+            num(*y),
+            num(*width),
+            num(*height)
+        ),
+        Mark::Text {
+            x,
+            y,
+            size,
+            bold,
+            rgb,
+            text,
+        } => format!(
+            "/{} findfont {} scalefont setfont {} setrgbcolor {} {} moveto {} show\n",
+            if *bold {
+                "Courier-Bold-Win"
+            } else {
+                "Courier-Win"
+            },
+            num(*size),
+            color(*rgb),
+            num(*x),
+            num(*y),
+            literal(text)
+        ),
+    }
+}
+
+fn pdf_mark(mark: &Mark) -> String {
+    match mark {
+        Mark::Fill {
+            x,
+            y,
+            width,
+            height,
+            rgb,
+        } => format!(
+            "{} rg {} {} {} {} re f\n",
+            color(*rgb),
+            num(*x),
+            num(*y),
+            num(*width),
+            num(*height)
+        ),
+        Mark::Stroke {
+            x,
+            y,
+            width,
+            height,
+            line_width,
+        } => format!(
+            "0 G {} w {} {} {} {} re S\n",
+            num(*line_width),
+            num(*x),
+            num(*y),
+            num(*width),
+            num(*height)
+        ),
+        Mark::Text {
+            x,
+            y,
+            size,
+            bold,
+            rgb,
+            text,
+        } => format!(
+            "BT /{} {} Tf {} rg {} {} Td {} Tj ET\n",
+            if *bold { "F2" } else { "F1" },
+            num(*size),
+            color(*rgb),
+            num(*x),
+            num(*y),
+            literal(text)
+        ),
+    }
+}
+
+fn pdf(title: &str, pages: &[Vec<Mark>]) -> Vec<u8> {
+    // The catalog, the page tree, the fonts and the document information,
+    // then each page and its contents
+    let kids: Vec<String> = (0..pages.len())
+        .map(|index| format!("{} 0 R", 6 + 2 * index))
+        .collect();
+    let mut objects = vec![
+        "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Courier-Bold /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+        format!("<< /Title {} /Creator (MS-DOS Defrag) >>", literal(title)),
+    ];
+    for (index, marks) in pages.iter().enumerate() {
+        let content: String = marks.iter().map(pdf_mark).collect();
+        objects.push(format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+             /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+            PAGE_WIDTH,
+            PAGE_HEIGHT,
+            7 + 2 * index
+        ));
+        objects.push(format!(
+            "<< /Length {} >>\nstream\n{}endstream",
+            content.len(),
+            content
+        ));
+    }
+    let mut out = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (index, object) in objects.iter().enumerate() {
+        offsets.push(out.len());
+        out += &format!("{} 0 obj\n{}\nendobj\n", index + 1, object);
+    }
+    let xref = out.len();
+    out += &format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+    for offset in offsets {
+        out += &format!("{:010} 00000 n \n", offset);
+    }
+    out += &format!(
+        "trailer\n<< /Size {} /Root 1 0 R /Info 5 0 R >>\nstartxref\n{}\n%%EOF\n",
+        objects.len() + 1,
+        xref
+    );
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+
+    #[test]
+    fn test_pdf_cross_reference_points_at_objects() {
+        let pdf = render_page(&test_app(40, 8, 5), PrintFormat::Pdf);
+        let text = String::from_utf8(pdf).unwrap();
+        let startxref: usize = text
+            .rsplit("startxref\n")
+            .next()
+            .and_then(|rest| rest.lines().next())
+            .and_then(|line| line.parse().ok())
+            .unwrap();
+        assert!(text[startxref..].starts_with("xref\n0 8\n"));
+
+        let entries = text[startxref..].lines().skip(3).take(7);
+        for (index, entry) in entries.enumerate() {
+            let offset: usize = entry[..10].parse().unwrap();
+            assert!(text[offset..].starts_with(&format!("{} 0 obj", index + 1)));
+        }
+    }
+
+    #[test]
+    fn test_printouts_are_numbered() {
+        let app = test_app(40, 8, 5);
+        let dir = std::env::temp_dir().join(format!("defrag-print-{}", std::process::id()));
+        let first = print_map(&app, &dir, PrintFormat::PostScript).unwrap();
+        let second = print_map(&app, &dir, PrintFormat::PostScript).unwrap();
+        assert_eq!(first.file_name().unwrap(), "DEFRAG-C1.PS");
+        assert_eq!(second.file_name().unwrap(), "DEFRAG-C2.PS");
+
+        let page = std::fs::read_to_string(&first).unwrap();
+        assert!(page.starts_with("%!PS-Adobe-3.0\n"));
+        assert!(page.contains("(Drive C: Hard Disk"));
+        assert!(page.ends_with("showpage\n%%EOF\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tall_maps_go_on_over_several_pages() {
+        let app = test_app(78, 400, 3);
+        let page = String::from_utf8(render_page(&app, PrintFormat::PostScript)).unwrap();
+        assert!(page.contains("%%Pages: 3\n"));
+        assert_eq!(page.matches("showpage\n").count(), 3);
+        assert!(page.contains("(MS-DOS Defrag - Disk Map \\(continued\\)) show"));
+        assert!(page.contains("Page 3 of 3"));
+
+        let pdf = String::from_utf8(render_page(&app, PrintFormat::Pdf)).unwrap();
+        assert!(pdf.contains("/Kids [6 0 R 8 0 R 10 0 R] /Count 3"));
+    }
+
+    #[test]
+    fn test_text_is_encoded_as_windows_1252() {
+        assert_eq!(literal("Café (ç)"), "(Caf\\351 \\(\\347\\))");
+        assert_eq!(literal("‘Zoë’ – 5 €"), "(\\221Zo\\353\\222 \\226 5 \\200)");
+        assert_eq!(literal("Ωmega\t"), "(?mega?)");
+    }
+}
@@ -0,0 +1,289 @@
+//! Recordings of whole runs as animated GIF or APNG
+//!
+//! The run is driven on a virtual clock, as fast as the machine allows, and
+//! the cluster grid is kept every few ticks. Frames only hold the cluster
+//! states until the run is over, then they are drawn and encoded; a frame
+//! equal to the previous one just makes that one last longer.
+
+use super::snapshot::{render_clusters, Image, Palette};
+use crate::app::App;
+use crate::clock::{Clock, SharedClock};
+use crate::models::ClusterState;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::Duration;
+
+/// File format of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Gif,
+    /// Animated PNG
+    Apng,
+}
+
+impl RecordFormat {
+    /// Picks the format from a file extension: `.gif`, or `.png`/`.apng`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(RecordFormat::Gif),
+            "png" | "apng" => Some(RecordFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+/// How a run is recorded
+#[derive(Debug, Clone, Copy)]
+pub struct RecordOptions {
+    /// Ticks between two frames
+    pub every: u32,
+    pub palette: Palette,
+    /// Frames are shrunk this many times (1 keeps them full size)
+    pub downscale: usize,
+}
+
+impl Default for RecordOptions {
+    fn default() -> Self {
+        Self {
+            every: 1,
+            palette: Palette::MsDos,
+            downscale: 1,
+        }
+    }
+}
+
+/// A kept grid, shown from `start` on
+struct Frame {
+    clusters: Vec<ClusterState>,
+    start: Duration,
+}
+
+/// Part of a frame drawn over the previous one, at `(x, y)`
+struct Patch {
+    x: usize,
+    y: usize,
+    image: Image,
+}
+
+/// Runs `app` to the end on virtual time, keeping the grid every `every`
+/// ticks when it changed, and the finished grid; returns the frames and the
+/// time the last one ends
+fn run(app: &mut App, every: u32) -> (Vec<Frame>, Duration) {
+    let clock = SharedClock::new();
+    app.set_clock(Box::new(clock.clone()));
+    app.unattended = true;
+
+    let every = every.max(1);
+    let mut frames = vec![Frame {
+        clusters: app.engine.clusters.clone(),
+        start: Duration::ZERO,
+    }];
+    let mut tick = 0u64;
+    while app.running {
+        app.update();
+        clock.advance(app.tick_rate);
+        tick += 1;
+        let changed = frames
+            .last()
+            .is_none_or(|frame| frame.clusters != app.engine.clusters);
+        if tick.is_multiple_of(u64::from(every)) && changed {
+            frames.push(Frame {
+                clusters: app.engine.clusters.clone(),
+                start: clock.now(),
+            });
+        }
+    }
+    let mut end = clock.now();
+    // The run rarely ends on a kept tick; the finished grid then shows for
+    // as long as the other frames
+    if frames
+        .last()
+        .is_some_and(|frame| frame.clusters != app.engine.clusters)
+    {
+        frames.push(Frame {
+            clusters: app.engine.clusters.clone(),
+            start: end,
+        });
+        end += app.tick_rate * every;
+    }
+    (frames, end)
+}
+
+/// Runs `app` to the end on virtual time and records it to `path`, in the
+/// format its extension names; returns the number of frames written
+pub fn record(app: &mut App, path: &Path, options: &RecordOptions) -> Result<usize> {
+    let format = RecordFormat::from_path(path).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "recordings must be .gif, .png or .apng files",
+        )
+    })?;
+
+    let (frames, end) = run(app, options.every);
+
+    let width = app.engine.width;
+    let draw = |frame: &Frame| {
+        render_clusters(&frame.clusters, width, options.palette).downscale(options.downscale)
+    };
+    // Frames of an "All drives" run differ in height: all take the size of
+    // the largest disk
+    let largest = frames
+        .iter()
+        .max_by_key(|frame| frame.clusters.len())
+        .map(draw)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "the run recorded no frame"))?;
+    // Past the first frame, only the part that changed is stored
+    let mut previous: Option<Image> = None;
+    let patches = frames.iter().map(|frame| {
+        let image = draw(frame).padded(largest.width, largest.height);
+        let (x, y, width, height) = match previous {
+            Some(ref previous) => image.changed_area(previous),
+            None => (0, 0, image.width, image.height),
+        };
+        let patch = Patch {
+            x,
+            y,
+            image: image.crop(x, y, width, height),
+        };
+        previous = Some(image);
+        patch
+    });
+    let durations: Vec<Duration> = frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| {
+            frames
+                .get(index + 1)
+                .map_or(end, |next| next.start)
+                .saturating_sub(frame.start)
+        })
+        .collect();
+
+    let file = BufWriter::new(File::create(path)?);
+    match format {
+        RecordFormat::Gif => write_gif(file, (largest.width, largest.height), patches, &durations)?,
+        RecordFormat::Apng => {
+            write_apng(file, (largest.width, largest.height), patches, &durations)?
+        }
+    }
+    Ok(frames.len())
+}
+
+fn write_gif(
+    file: BufWriter<File>,
+    (width, height): (usize, usize),
+    patches: impl Iterator<Item = Patch>,
+    durations: &[Duration],
+) -> Result<()> {
+    // Patches fit in the canvas, so its size bounds theirs
+    let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "frames are too large for a GIF, use --record-downscale",
+        ));
+    };
+    let mut encoder = gif::Encoder::new(file, width, height, &[]).map_err(Error::other)?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .map_err(Error::other)?;
+
+    // Delays are rounded on the time line, so rounding errors do not add up;
+    // viewers slow down delays under 2 centiseconds
+    let mut shown = Duration::ZERO;
+    let mut shown_centiseconds = 0;
+    for (patch, duration) in patches.zip(durations) {
+        let image = &patch.image;
+        let (width, height) = (image.width as u16, image.height as u16);
+        let mut frame = match image.indexed() {
+            Some((palette, pixels)) => {
+                gif::Frame::from_palette_pixels(width, height, pixels, palette, None)
+            }
+            None => gif::Frame::from_rgb_speed(width, height, &image.pixels, 10),
+        };
+        frame.left = patch.x as u16;
+        frame.top = patch.y as u16;
+        frame.dispose = gif::DisposalMethod::Keep;
+
+        shown += *duration;
+        let end = (shown.as_millis() as u64 + 5) / 10;
+        let end = end.max(shown_centiseconds + 2);
+        frame.delay = u16::try_from(end - shown_centiseconds).unwrap_or(u16::MAX);
+        shown_centiseconds = end;
+        encoder.write_frame(&frame).map_err(Error::other)?;
+    }
+    Ok(())
+}
+
+fn write_apng(
+    file: BufWriter<File>,
+    (width, height): (usize, usize),
+    patches: impl Iterator<Item = Patch>,
+    durations: &[Duration],
+) -> Result<()> {
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(durations.len() as u32, 0)
+        .map_err(Error::other)?;
+    let mut writer = encoder.write_header().map_err(Error::other)?;
+    for (patch, duration) in patches.zip(durations) {
+        let milliseconds = u16::try_from(duration.as_millis()).unwrap_or(u16::MAX);
+        writer
+            .set_frame_delay(milliseconds, 1000)
+            .map_err(Error::other)?;
+        // Back to the corner first, so the new size always fits
+        writer.reset_frame_position().map_err(Error::other)?;
+        writer
+            .set_frame_dimension(patch.image.width as u32, patch.image.height as u32)
+            .map_err(Error::other)?;
+        writer
+            .set_frame_position(patch.x as u32, patch.y as u32)
+            .map_err(Error::other)?;
+        writer
+            .write_image_data(&patch.image.pixels)
+            .map_err(Error::other)?;
+    }
+    writer.finish().map_err(Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+
+    #[test]
+    fn test_recording_runs_to_the_end() {
+        let mut app = test_app(16, 4, 4);
+        let path = std::env::temp_dir().join(format!("defrag-record-{}.png", std::process::id()));
+        let options = RecordOptions {
+            every: 5,
+            ..RecordOptions::default()
+        };
+        let frames = record(&mut app, &path, &options).unwrap();
+        assert!(frames > 2);
+        assert!(!app.running);
+        assert_eq!(app.engine.phase, crate::models::DefragPhase::Finished);
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let reader = decoder.read_info().unwrap();
+        let animation = reader.info().animation_control.unwrap();
+        assert_eq!(animation.num_frames as usize, frames);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_finished_grid_is_always_kept() {
+        let mut app = test_app(16, 4, 4);
+        let (frames, end) = run(&mut app, 1_000_000);
+        assert_eq!(frames.len(), 2);
+        let last = frames.last().unwrap();
+        assert_eq!(last.clusters, app.engine.clusters);
+        assert!(!last
+            .clusters
+            .contains(&crate::models::ClusterState::Pending));
+        assert!(end > last.start);
+    }
+}
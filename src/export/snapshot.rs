@@ -0,0 +1,369 @@
+//! PNG snapshots of the disk map
+//!
+//! The grid is drawn in software, one cell per cluster, so snapshots work in
+//! the terminal build as well as the SDL one. The MS-DOS palette draws text
+//! mode cells with their symbols; the Windows 98 palette draws the plain
+//! colored squares of the Win98 defragmenter.
+
+use super::vga_rgb;
+use crate::app::App;
+use crate::graphics::palette::Win98ClusterState;
+use crate::models::ClusterState;
+use crate::ui::cluster_glyph;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+/// Size of an MS-DOS cell: a VGA text mode character
+const DOS_CELL: (usize, usize) = (8, 16);
+/// Border around the MS-DOS grid, holding the double frame
+const DOS_BORDER: usize = 8;
+/// Size and spacing of a Win98 cluster, as in the graphical renderer
+const WIN98_CELL: usize = 8;
+const WIN98_GAP: usize = 1;
+
+/// 5x7 bitmaps of the letters the MS-DOS grid shows
+const GLYPH_B: [u8; 7] = [
+    0b11110, 0b10001, 0b10001, 0b11110, 0b10001, 0b10001, 0b11110,
+];
+const GLYPH_X: [u8; 7] = [
+    0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+];
+const GLYPH_R: [u8; 7] = [
+    0b00000, 0b00000, 0b10110, 0b11001, 0b10000, 0b10000, 0b10000,
+];
+const GLYPH_W: [u8; 7] = [
+    0b10001, 0b10001, 0b10001, 0b10101, 0b10101, 0b10101, 0b01010,
+];
+
+/// Colors the grid is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Palette {
+    #[default]
+    MsDos,
+    Win98,
+}
+
+impl Palette {
+    /// Parses `msdos` (or `dos`) or `win98` (or `win95`)
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "msdos" | "dos" => Some(Palette::MsDos),
+            "win98" | "windows98" | "98" | "win95" | "windows95" | "95" => Some(Palette::Win98),
+            _ => None,
+        }
+    }
+}
+
+/// An RGB picture
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Rows of RGB pixels, top to bottom
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize, rgb: [u8; 3]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: rgb.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = (y * self.width + x) * 3;
+            self.pixels[offset..offset + 3].copy_from_slice(&rgb);
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, rgb: [u8; 3]) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.set(column, row, rgb);
+            }
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let offset = (y * self.width + x) * 3;
+        [
+            self.pixels[offset],
+            self.pixels[offset + 1],
+            self.pixels[offset + 2],
+        ]
+    }
+
+    /// Shrinks the picture `factor` times, each pixel averaging a
+    /// `factor`x`factor` block
+    pub fn downscale(&self, factor: usize) -> Image {
+        if factor <= 1 {
+            return Image {
+                width: self.width,
+                height: self.height,
+                pixels: self.pixels.clone(),
+            };
+        }
+        let width = self.width.div_ceil(factor);
+        let height = self.height.div_ceil(factor);
+        let mut image = Image::new(width, height, [0, 0, 0]);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0usize; 3];
+                let mut count = 0;
+                for source_y in y * factor..((y + 1) * factor).min(self.height) {
+                    for source_x in x * factor..((x + 1) * factor).min(self.width) {
+                        let pixel = self.pixel(source_x, source_y);
+                        for (total, channel) in sum.iter_mut().zip(pixel) {
+                            *total += channel as usize;
+                        }
+                        count += 1;
+                    }
+                }
+                image.set(x, y, sum.map(|total| (total / count) as u8));
+            }
+        }
+        image
+    }
+
+    /// Enlarges the canvas to `width`x`height`, the new area taking the
+    /// color of the top-left pixel
+    pub fn padded(self, width: usize, height: usize) -> Image {
+        if (width, height) == (self.width, self.height) {
+            return self;
+        }
+        let mut image = Image::new(width, height, self.pixel(0, 0));
+        for y in 0..self.height.min(height) {
+            let length = self.width.min(width) * 3;
+            let source = y * self.width * 3;
+            let target = y * width * 3;
+            image.pixels[target..target + length]
+                .copy_from_slice(&self.pixels[source..source + length]);
+        }
+        image
+    }
+
+    /// Smallest rectangle holding every pixel that differs from `previous`,
+    /// a picture of the same size, as `(x, y, width, height)`; a single
+    /// pixel when nothing changed
+    pub fn changed_area(&self, previous: &Image) -> (usize, usize, usize, usize) {
+        let mut area: Option<(usize, usize, usize, usize)> = None;
+        for y in 0..self.height {
+            let row = y * self.width * 3..(y + 1) * self.width * 3;
+            if self.pixels[row.clone()] == previous.pixels[row] {
+                continue;
+            }
+            for x in 0..self.width {
+                if self.pixel(x, y) != previous.pixel(x, y) {
+                    area = Some(match area {
+                        None => (x, y, x, y),
+                        Some((left, top, right, _)) => (left.min(x), top, right.max(x), y),
+                    });
+                }
+            }
+        }
+        area.map_or((0, 0, 1, 1), |(left, top, right, bottom)| {
+            (left, top, right - left + 1, bottom - top + 1)
+        })
+    }
+
+    /// The `width`x`height` part of the picture at `(x, y)`
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let mut pixels = Vec::with_capacity(width * height * 3);
+        for row in y..y + height {
+            let start = (row * self.width + x) * 3;
+            pixels.extend_from_slice(&self.pixels[start..start + width * 3]);
+        }
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// The palette and indexed pixels of the picture, when it has no more
+    /// than 256 colors
+    pub fn indexed(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut palette: Vec<[u8; 3]> = Vec::new();
+        let mut indices = Vec::with_capacity(self.width * self.height);
+        for pixel in self.pixels.chunks_exact(3) {
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            let index = match palette.iter().position(|&color| color == rgb) {
+                Some(index) => index,
+                None if palette.len() < 256 => {
+                    palette.push(rgb);
+                    palette.len() - 1
+                }
+                None => return None,
+            };
+            indices.push(index as u8);
+        }
+        Some((palette.concat(), indices))
+    }
+
+    /// Encodes the picture as a PNG file
+    pub fn to_png(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(Error::other)?;
+        writer
+            .write_image_data(&self.pixels)
+            .map_err(Error::other)?;
+        writer.finish().map_err(Error::other)?;
+        Ok(out)
+    }
+}
+
+/// Draws the disk map of `app` with `palette`
+pub fn render_image(app: &App, palette: Palette) -> Image {
+    render_clusters(&app.engine.clusters, app.engine.width, palette)
+}
+
+/// Draws `clusters`, `columns` to a row, with `palette`
+pub fn render_clusters(clusters: &[ClusterState], columns: usize, palette: Palette) -> Image {
+    let columns = columns.max(1);
+    let rows = clusters.len().div_ceil(columns).max(1);
+    match palette {
+        Palette::MsDos => render_dos(clusters, columns, rows),
+        Palette::Win98 => render_win98(clusters, columns, rows),
+    }
+}
+
+fn render_dos(clusters: &[ClusterState], columns: usize, rows: usize) -> Image {
+    let (cell_width, cell_height) = DOS_CELL;
+    let blue = vga_rgb(ratatui::style::Color::Blue);
+    let white = [255, 255, 255];
+    let mut image = Image::new(
+        columns * cell_width + 2 * DOS_BORDER,
+        rows * cell_height + 2 * DOS_BORDER,
+        blue,
+    );
+
+    // Double frame, as drawn around the grid on screen
+    for inset in [2, 5] {
+        let width = image.width - 2 * inset;
+        let height = image.height - 2 * inset;
+        image.fill(inset, inset, width, 1, white);
+        image.fill(inset, image.height - inset - 1, width, 1, white);
+        image.fill(inset, inset, 1, height, white);
+        image.fill(image.width - inset - 1, inset, 1, height, white);
+    }
+
+    for (index, &cluster) in clusters.iter().enumerate() {
+        let x = DOS_BORDER + (index % columns) * cell_width;
+        let y = DOS_BORDER + (index / columns) * cell_height;
+        draw_dos_cell(&mut image, cluster, x, y);
+    }
+    image
+}
+
+fn draw_dos_cell(image: &mut Image, cluster: ClusterState, x: usize, y: usize) {
+    let (cell_width, cell_height) = DOS_CELL;
+    let (_, style) = cluster_glyph(cluster);
+    let foreground = vga_rgb(style.fg.unwrap_or_default());
+    image.fill(
+        x,
+        y,
+        cell_width,
+        cell_height,
+        vga_rgb(style.bg.unwrap_or_default()),
+    );
+
+    let glyph = match cluster {
+        ClusterState::Used | ClusterState::Pending => {
+            // The "•" dot
+            image.fill(x + 2, y + 6, 4, 4, foreground);
+            return;
+        }
+        ClusterState::Unused => {
+            // The "░" light shade: one pixel in four
+            for row in 0..cell_height {
+                for column in 0..cell_width {
+                    if (column + row % 2 * 2) % 4 == 0 {
+                        image.set(x + column, y + row, foreground);
+                    }
+                }
+            }
+            return;
+        }
+        ClusterState::Bad => GLYPH_B,
+        ClusterState::Unmovable => GLYPH_X,
+        ClusterState::Reading => GLYPH_R,
+        ClusterState::Writing => GLYPH_W,
+    };
+    // Letters are drawn twice as tall, as VGA text characters are
+    for (row, bits) in glyph.iter().enumerate() {
+        for column in 0..5 {
+            if bits & (0b10000 >> column) != 0 {
+                image.fill(x + 1 + column, y + 1 + row * 2, 1, 2, foreground);
+            }
+        }
+    }
+}
+
+fn render_win98(clusters: &[ClusterState], columns: usize, rows: usize) -> Image {
+    let pitch = WIN98_CELL + WIN98_GAP;
+    let mut image = Image::new(
+        columns * pitch + WIN98_GAP,
+        rows * pitch + WIN98_GAP,
+        [255, 255, 255],
+    );
+    for (index, cluster) in clusters.iter().enumerate() {
+        image.fill(
+            WIN98_GAP + (index % columns) * pitch,
+            WIN98_GAP + (index / columns) * pitch,
+            WIN98_CELL,
+            WIN98_CELL,
+            Win98ClusterState::from(cluster).rgb(),
+        );
+    }
+    image
+}
+
+/// First snapshot file of `dir` numbered `first` or more that does not
+/// exist yet (`DEFRAG-C-0001.PNG`...), with its number
+pub fn free_path(dir: &Path, letter: char, first: u32) -> Result<(PathBuf, u32)> {
+    (first..=u32::MAX)
+        .map(|n| (dir.join(format!("DEFRAG-{}-{:04}.PNG", letter, n)), n))
+        .find(|(path, _)| !path.exists())
+        .ok_or_else(|| Error::new(ErrorKind::AlreadyExists, "no snapshot name left"))
+}
+
+/// Writes a PNG snapshot of the disk map of `app` to `path`
+pub fn save_png(app: &App, path: &Path, palette: Palette) -> Result<()> {
+    std::fs::write(path, render_image(app, palette).to_png()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+    use crate::graphics::palette;
+
+    #[test]
+    fn test_palettes_draw_every_cluster() {
+        let app = test_app(10, 2, 2);
+        // The first cluster is the unmovable boot sector
+        assert_eq!(app.engine.clusters[0], ClusterState::Unmovable);
+
+        let dos = render_image(&app, Palette::MsDos);
+        assert_eq!((dos.width, dos.height), (96, 48));
+        // Blue background, white "X" stroke in its top-left corner
+        assert_eq!(dos.pixel(DOS_BORDER, DOS_BORDER), [0, 0, 170]);
+        assert_eq!(dos.pixel(DOS_BORDER + 1, DOS_BORDER + 1), [255, 255, 255]);
+
+        let win98 = render_image(&app, Palette::Win98);
+        assert_eq!((win98.width, win98.height), (91, 19));
+        assert_eq!(win98.pixel(0, 0), [255, 255, 255]);
+        assert_eq!(win98.pixel(1, 1), palette::DEFRAG_IDLE);
+
+        let png = win98.to_png().unwrap();
+        let decoder = png::Decoder::new(png.as_slice());
+        let reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().width, 91);
+        assert_eq!(reader.info().height, 19);
+    }
+}
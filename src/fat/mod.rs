@@ -0,0 +1,1152 @@
+//! FAT12/FAT16/FAT32 disk images
+//!
+//! Parses the boot sector, the file allocation table and the directory tree
+//! (including VFAT long file names) of a raw disk image, so the defragmenter
+//! can show and replay a plan for a real disk instead of a random one. The
+//! image itself is never modified, a defragmented copy can be written to a new
+//! file instead.
+
+use crate::layout::DiskLayout;
+use crate::models::{ClusterState, FileFragment};
+use std::collections::HashSet;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+mod writer;
+
+/// First cluster number of the data area, clusters 0 and 1 are reserved
+pub const FIRST_DATA_CLUSTER: u32 = 2;
+
+/// Size of a directory entry in bytes
+const DIR_ENTRY_SIZE: usize = 32;
+
+/// Directory entry attribute bits
+pub mod attr {
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    /// Attribute combination used by long file name entries
+    pub const LONG_NAME: u8 = READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID;
+}
+
+/// Characters of code page 437 bytes 0x80 to 0xFF, used by 8.3 names
+#[rustfmt::skip]
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+fn invalid(message: impl Into<String>) -> Error {
+    Error::new(ErrorKind::InvalidData, message.into())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// FAT variant of a volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+impl FatType {
+    /// FAT value marking a bad cluster
+    pub fn bad_cluster(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF7,
+            FatType::Fat16 => 0xFFF7,
+            FatType::Fat32 => 0x0FFF_FFF7,
+        }
+    }
+
+    /// Smallest FAT value marking the end of a cluster chain
+    pub fn end_of_chain(&self) -> u32 {
+        match self {
+            FatType::Fat12 => 0xFF8,
+            FatType::Fat16 => 0xFFF8,
+            FatType::Fat32 => 0x0FFF_FFF8,
+        }
+    }
+
+    /// Bytes of FAT needed to hold `entries` entries
+    pub fn table_size(&self, entries: u32) -> usize {
+        let entries = entries as usize;
+        match self {
+            FatType::Fat12 => (entries * 3).div_ceil(2),
+            FatType::Fat16 => entries * 2,
+            FatType::Fat32 => entries * 4,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FatType::Fat12 => "FAT12",
+            FatType::Fat16 => "FAT16",
+            FatType::Fat32 => "FAT32",
+        }
+    }
+}
+
+/// FAT32 FSInfo sector, a hint of the free space left on the volume
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsInfo {
+    /// Number of free clusters, 0xFFFFFFFF when unknown
+    pub free_count: u32,
+    /// Cluster to start looking for free space from, 0xFFFFFFFF when unknown
+    pub next_free: u32,
+}
+
+impl FsInfo {
+    const LEAD_SIGNATURE: u32 = 0x4161_5252;
+    const STRUCT_SIGNATURE: u32 = 0x6141_7272;
+    const TRAIL_SIGNATURE: u32 = 0xAA55_0000;
+
+    /// Parses an FSInfo sector, returning None when its signatures are wrong
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if sector.len() < 512
+            || read_u32(sector, 0) != Self::LEAD_SIGNATURE
+            || read_u32(sector, 484) != Self::STRUCT_SIGNATURE
+            || read_u32(sector, 508) != Self::TRAIL_SIGNATURE
+        {
+            return None;
+        }
+        Some(Self {
+            free_count: read_u32(sector, 488),
+            next_free: read_u32(sector, 492),
+        })
+    }
+
+    /// Stores the free space hint back into an FSInfo sector
+    pub fn write(&self, sector: &mut [u8]) {
+        sector[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+    }
+}
+
+/// BIOS parameter block of the boot sector
+#[derive(Debug, Clone)]
+pub struct BootSector {
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub root_entries: u16,
+    pub total_sectors: u32,
+    pub sectors_per_fat: u32,
+    pub volume_label: String,
+    /// First cluster of the root directory (FAT32 only, 0 otherwise)
+    pub root_cluster: u32,
+    /// Sector of the FSInfo structure (FAT32 only)
+    pub fs_info_sector: u16,
+    /// Sector of the boot sector backup copy (FAT32 only)
+    pub backup_boot_sector: u16,
+    /// First sector of the data area, after the FATs and root directory
+    data_start: u32,
+}
+
+impl BootSector {
+    /// Parses the boot sector found at the start of `data`
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 512 {
+            return Err(invalid("image is smaller than a boot sector"));
+        }
+
+        let bytes_per_sector = read_u16(data, 11);
+        let sectors_per_cluster = data[13];
+        let reserved_sectors = read_u16(data, 14);
+        let fat_count = data[16];
+        let root_entries = read_u16(data, 17);
+        let total_sectors = match read_u16(data, 19) {
+            0 => read_u32(data, 32),
+            sectors => sectors as u32,
+        };
+        // FAT32 leaves the 16-bit FAT size empty and uses an extended BPB
+        let fat32 = read_u16(data, 22) == 0;
+        let sectors_per_fat = if fat32 {
+            read_u32(data, 36)
+        } else {
+            read_u16(data, 22) as u32
+        };
+
+        if !matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096) {
+            return Err(invalid(format!(
+                "unsupported sector size {}",
+                bytes_per_sector
+            )));
+        }
+        if !sectors_per_cluster.is_power_of_two() {
+            return Err(invalid(format!(
+                "invalid sectors per cluster {}",
+                sectors_per_cluster
+            )));
+        }
+        if fat_count == 0 || reserved_sectors == 0 {
+            return Err(invalid("boot sector has no FAT or reserved sectors"));
+        }
+        if sectors_per_fat == 0 {
+            return Err(invalid("boot sector has an empty FAT"));
+        }
+
+        let (signature, label) = if fat32 { (66, 71) } else { (38, 43) };
+        let volume_label = if data[signature] == 0x29 {
+            String::from_utf8_lossy(&data[label..label + 11])
+                .trim_end()
+                .to_string()
+        } else {
+            String::new()
+        };
+        let (root_cluster, fs_info_sector, backup_boot_sector) = if fat32 {
+            let root_cluster = read_u32(data, 44);
+            if root_cluster < FIRST_DATA_CLUSTER {
+                return Err(invalid(format!("invalid root cluster {}", root_cluster)));
+            }
+            (root_cluster, read_u16(data, 48), read_u16(data, 50))
+        } else {
+            (0, 0, 0)
+        };
+
+        let mut boot = Self {
+            bytes_per_sector,
+            sectors_per_cluster,
+            reserved_sectors,
+            fat_count,
+            root_entries,
+            total_sectors,
+            sectors_per_fat,
+            volume_label,
+            root_cluster,
+            fs_info_sector,
+            backup_boot_sector,
+            data_start: 0,
+        };
+        // The sums below come from the image, so they may overflow
+        boot.data_start = boot
+            .data_start_sector()
+            .filter(|&start| start < boot.total_sectors)
+            .ok_or_else(|| invalid("boot sector describes no data area"))?;
+        if FIRST_DATA_CLUSTER
+            .checked_add(boot.cluster_count())
+            .is_none()
+        {
+            return Err(invalid("boot sector describes too many clusters"));
+        }
+        // Readers and writers index the FAT by cluster without bounds checks
+        let entries = FIRST_DATA_CLUSTER + boot.cluster_count();
+        if boot.fat_size() < boot.fat_type()?.table_size(entries) {
+            return Err(invalid(format!(
+                "FAT of {} bytes is too small for {} clusters",
+                boot.fat_size(),
+                boot.cluster_count()
+            )));
+        }
+        Ok(boot)
+    }
+
+    /// Byte offset of the first FAT copy
+    pub fn fat_offset(&self) -> usize {
+        self.reserved_sectors as usize * self.bytes_per_sector as usize
+    }
+
+    /// Size of one FAT copy in bytes
+    pub fn fat_size(&self) -> usize {
+        self.sectors_per_fat as usize * self.bytes_per_sector as usize
+    }
+
+    /// Byte offset of sector `sector`
+    pub fn sector_offset(&self, sector: u16) -> usize {
+        sector as usize * self.bytes_per_sector as usize
+    }
+
+    /// Byte offset of the fixed-size root directory (FAT12/FAT16)
+    pub fn root_dir_offset(&self) -> usize {
+        self.fat_offset() + self.fat_count as usize * self.fat_size()
+    }
+
+    /// Size of the fixed-size root directory in bytes
+    pub fn root_dir_size(&self) -> usize {
+        self.root_entries as usize * DIR_ENTRY_SIZE
+    }
+
+    /// First sector of the data area, `None` when it does not fit a sector
+    /// number
+    fn data_start_sector(&self) -> Option<u32> {
+        let root_sectors = self
+            .root_dir_size()
+            .div_ceil(self.bytes_per_sector as usize);
+        (self.fat_count as u32)
+            .checked_mul(self.sectors_per_fat)?
+            .checked_add(self.reserved_sectors as u32)?
+            .checked_add(u32::try_from(root_sectors).ok()?)
+    }
+
+    /// Size of a cluster in bytes
+    pub fn cluster_size(&self) -> usize {
+        self.sectors_per_cluster as usize * self.bytes_per_sector as usize
+    }
+
+    /// Number of data clusters on the volume
+    pub fn cluster_count(&self) -> u32 {
+        (self.total_sectors - self.data_start) / self.sectors_per_cluster as u32
+    }
+
+    /// Byte offset of data cluster `cluster`
+    pub fn cluster_offset(&self, cluster: u32) -> usize {
+        self.data_start as usize * self.bytes_per_sector as usize
+            + (cluster - FIRST_DATA_CLUSTER) as usize * self.cluster_size()
+    }
+
+    /// FAT variant of the volume
+    ///
+    /// FAT32 is recognised by its extended boot sector, like most drivers
+    /// do, so that small FAT32 volumes load too. FAT12 and FAT16 are told
+    /// apart by their number of clusters.
+    pub fn fat_type(&self) -> Result<FatType> {
+        if self.root_cluster != 0 {
+            return Ok(FatType::Fat32);
+        }
+        match self.cluster_count() {
+            0..4085 => Ok(FatType::Fat12),
+            4085..65525 => Ok(FatType::Fat16),
+            _ => Err(invalid("too many clusters for a FAT16 volume")),
+        }
+    }
+}
+
+/// A file or directory found while walking the directory tree
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    /// DOS path of the entry (e.g. `DOS\EDIT.COM`)
+    pub path: String,
+    /// Path made of the long (VFAT) names where present
+    /// (e.g. `Program Files\Internet Explorer`)
+    pub long_path: String,
+    pub attributes: u8,
+    pub start_cluster: u32,
+    /// File size in bytes, 0 for directories
+    pub size: u32,
+    /// Last modification time, packed as in [`FileFragment::modified`]
+    pub modified: u32,
+    /// Byte offset of the 32-byte entry in the image
+    pub entry_offset: usize,
+}
+
+impl DirEntry {
+    pub fn is_directory(&self) -> bool {
+        self.attributes & attr::DIRECTORY != 0
+    }
+
+    /// Hidden and system files (e.g. IO.SYS) must stay where they are, as
+    /// MS-DOS DEFRAG never moves them
+    pub fn is_unmovable(&self) -> bool {
+        self.attributes & (attr::HIDDEN | attr::SYSTEM) != 0
+    }
+}
+
+/// A parsed FAT disk image
+#[derive(Debug, Clone)]
+pub struct FatVolume {
+    pub boot: BootSector,
+    pub fat_type: FatType,
+    /// Free space hint of FAT32 volumes
+    pub fs_info: Option<FsInfo>,
+    /// FAT value of every cluster, indexed by cluster number
+    pub fat: Vec<u32>,
+    /// Every file and directory of the volume, parents before children
+    pub entries: Vec<DirEntry>,
+    /// Raw image contents
+    data: Vec<u8>,
+}
+
+impl FatVolume {
+    /// Reads and parses the disk image at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::parse(std::fs::read(path)?)
+    }
+
+    /// Parses a raw disk image
+    pub fn parse(data: Vec<u8>) -> Result<Self> {
+        let mut boot = BootSector::parse(&data)?;
+        let fat_type = boot.fat_type()?;
+
+        let end = boot.cluster_offset(FIRST_DATA_CLUSTER + boot.cluster_count());
+        if data.len() < end {
+            return Err(invalid(format!(
+                "image is truncated ({} bytes, {} expected)",
+                data.len(),
+                end
+            )));
+        }
+
+        let fat = Self::read_fat(&data, &boot, fat_type);
+        let fs_info = match fat_type {
+            FatType::Fat32 => data
+                .get(boot.sector_offset(boot.fs_info_sector)..)
+                .and_then(FsInfo::parse),
+            _ => None,
+        };
+        let mut volume = Self {
+            boot: boot.clone(),
+            fat_type,
+            fs_info,
+            fat,
+            entries: Vec::new(),
+            data,
+        };
+
+        let (entries, label) = volume.read_tree()?;
+        volume.entries = entries;
+        if let Some(label) = label {
+            boot.volume_label = label;
+            volume.boot = boot;
+        }
+        Ok(volume)
+    }
+
+    /// Decodes the first FAT copy, which [`BootSector::parse`] made sure
+    /// holds every cluster
+    fn read_fat(data: &[u8], boot: &BootSector, fat_type: FatType) -> Vec<u32> {
+        let table = &data[boot.fat_offset()..boot.fat_offset() + boot.fat_size()];
+        let entries = (FIRST_DATA_CLUSTER + boot.cluster_count()) as usize;
+
+        (0..entries)
+            .map(|cluster| match fat_type {
+                FatType::Fat12 => {
+                    let value = read_u16(table, cluster + cluster / 2) as u32;
+                    if cluster % 2 == 1 {
+                        value >> 4
+                    } else {
+                        value & 0xFFF
+                    }
+                }
+                FatType::Fat16 => read_u16(table, cluster * 2) as u32,
+                // The top four bits are reserved
+                FatType::Fat32 => read_u32(table, cluster * 4) & 0x0FFF_FFFF,
+            })
+            .collect()
+    }
+
+    /// Whether `cluster` is a valid data cluster number
+    pub fn is_data_cluster(&self, cluster: u32) -> bool {
+        (FIRST_DATA_CLUSTER..self.fat.len() as u32).contains(&cluster)
+    }
+
+    /// Follows the cluster chain starting at `start`
+    ///
+    /// Fails on chains that leave the data area, run into a free or bad
+    /// cluster, or loop.
+    pub fn chain(&self, start: u32) -> Result<Vec<u32>> {
+        let mut chain = Vec::new();
+        let mut cluster = start;
+        loop {
+            if !self.is_data_cluster(cluster) {
+                return Err(invalid(format!("cluster {} is out of range", cluster)));
+            }
+            if chain.len() >= self.fat.len() {
+                return Err(invalid(format!("cluster chain at {} loops", start)));
+            }
+            chain.push(cluster);
+
+            let next = self.fat[cluster as usize];
+            if next >= self.fat_type.end_of_chain() {
+                return Ok(chain);
+            }
+            if next == 0 || next == self.fat_type.bad_cluster() {
+                return Err(invalid(format!(
+                    "cluster chain at {} runs into cluster {} marked {:#X}",
+                    start, cluster, next
+                )));
+            }
+            cluster = next;
+        }
+    }
+
+    /// Contents of the clusters of `chain`, in order
+    fn read_chain(&self, chain: &[u32]) -> Vec<u8> {
+        let size = self.boot.cluster_size();
+        chain
+            .iter()
+            .flat_map(|&cluster| {
+                let offset = self.boot.cluster_offset(cluster);
+                self.data[offset..offset + size].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Byte offsets of the directory entry slots stored in `chain`
+    fn chain_slots(&self, chain: &[u32]) -> Vec<usize> {
+        let size = self.boot.cluster_size();
+        chain
+            .iter()
+            .flat_map(|&cluster| {
+                let offset = self.boot.cluster_offset(cluster);
+                (offset..offset + size).step_by(DIR_ENTRY_SIZE)
+            })
+            .collect()
+    }
+
+    /// Byte offsets of the entry slots of the root directory
+    fn root_slots(&self) -> Result<Vec<usize>> {
+        match self.fat_type {
+            FatType::Fat32 => Ok(self.chain_slots(&self.chain(self.boot.root_cluster)?)),
+            FatType::Fat12 | FatType::Fat16 => {
+                let offset = self.boot.root_dir_offset();
+                Ok((offset..offset + self.boot.root_dir_size())
+                    .step_by(DIR_ENTRY_SIZE)
+                    .collect())
+            }
+        }
+    }
+
+    /// Walks the directory tree, returning its entries and the volume label
+    fn read_tree(&self) -> Result<(Vec<DirEntry>, Option<String>)> {
+        let mut entries = Vec::new();
+        let mut label = None;
+        let mut pending = vec![(String::new(), String::new(), self.root_slots()?)];
+        let mut visited = HashSet::new();
+
+        while let Some((parent, long_parent, slots)) = pending.pop() {
+            let mut long_name = LongName::default();
+            for offset in slots {
+                let raw = &self.data[offset..offset + DIR_ENTRY_SIZE];
+                match raw[0] {
+                    0x00 => break,
+                    0xE5 => {
+                        long_name = LongName::default();
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                let attributes = raw[11];
+                if attributes & attr::LONG_NAME == attr::LONG_NAME {
+                    long_name.push(raw);
+                    continue;
+                }
+                let long = std::mem::take(&mut long_name).finish(raw);
+                if attributes & attr::VOLUME_ID != 0 {
+                    if parent.is_empty() {
+                        label = Some(String::from_utf8_lossy(&raw[..11]).trim_end().to_string());
+                    }
+                    continue;
+                }
+                let name = Self::entry_name(raw);
+                if name == "." || name == ".." {
+                    continue;
+                }
+
+                let long = long.unwrap_or_else(|| name.clone());
+                let (path, long_path) = if parent.is_empty() {
+                    (name, long)
+                } else {
+                    (
+                        format!("{}\\{}", parent, name),
+                        format!("{}\\{}", long_parent, long),
+                    )
+                };
+                let mut start_cluster = read_u16(raw, 26) as u32;
+                if self.fat_type == FatType::Fat32 {
+                    start_cluster |= (read_u16(raw, 20) as u32) << 16;
+                }
+                let entry = DirEntry {
+                    path,
+                    long_path,
+                    attributes,
+                    start_cluster,
+                    size: read_u32(raw, 28),
+                    modified: ((read_u16(raw, 24) as u32) << 16) | read_u16(raw, 22) as u32,
+                    entry_offset: offset,
+                };
+
+                if entry.is_directory() && visited.insert(entry.start_cluster) {
+                    if let Ok(chain) = self.chain(entry.start_cluster) {
+                        pending.push((
+                            entry.path.clone(),
+                            entry.long_path.clone(),
+                            self.chain_slots(&chain),
+                        ));
+                    }
+                }
+                entries.push(entry);
+            }
+        }
+
+        Ok((entries, label))
+    }
+
+    /// Decodes the 8.3 name of a raw directory entry, stored in code page
+    /// 437
+    fn entry_name(raw: &[u8]) -> String {
+        let decode = |bytes: &[u8]| -> String {
+            bytes
+                .iter()
+                .map(|&b| match b {
+                    0x80.. => CP437_HIGH[(b - 0x80) as usize],
+                    _ => b as char,
+                })
+                .collect::<String>()
+                .trim_end()
+                .to_string()
+        };
+
+        let mut base = raw[..8].to_vec();
+        if base[0] == 0x05 {
+            base[0] = 0xE5;
+        }
+        let base = decode(&base);
+        let extension = decode(&raw[8..11]);
+        if extension.is_empty() {
+            base
+        } else {
+            format!("{}.{}", base, extension)
+        }
+    }
+
+    /// Grid cell showing data cluster `cluster`
+    pub fn cell(cluster: u32) -> usize {
+        (cluster - FIRST_DATA_CLUSTER) as usize
+    }
+
+    /// Entries the defragmenter may move, with their cluster chains
+    ///
+    /// Hidden and system files stay in place, and so do entries whose chain
+    /// is damaged or shares clusters with any other entry: moving one file of
+    /// a cross-linked group would leave the others pointing at stale data.
+    pub fn movable_entries(&self) -> Vec<(usize, Vec<u32>)> {
+        let chains: Vec<(usize, Vec<u32>)> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| entry.start_cluster != 0)
+            .filter_map(|(index, entry)| Some((index, self.chain(entry.start_cluster).ok()?)))
+            .collect();
+
+        let mut claimed = HashSet::new();
+        let mut cross_linked = HashSet::new();
+        for (_, chain) in &chains {
+            for &cluster in chain {
+                if !claimed.insert(cluster) {
+                    cross_linked.insert(cluster);
+                }
+            }
+        }
+
+        chains
+            .into_iter()
+            .filter(|(index, chain)| {
+                !self.entries[*index].is_unmovable()
+                    && chain.iter().all(|cluster| !cross_linked.contains(cluster))
+            })
+            .collect()
+    }
+
+    /// Builds the cluster map and file list of the volume
+    ///
+    /// Each data cluster is one cell, starting with cluster 2. Files are
+    /// listed in the order of [`Self::movable_entries`]. Allocated clusters
+    /// that no movable entry owns are shown as unmovable.
+    pub fn layout(&self) -> DiskLayout {
+        let mut clusters: Vec<ClusterState> = self.fat[FIRST_DATA_CLUSTER as usize..]
+            .iter()
+            .map(|&value| match value {
+                0 => ClusterState::Unused,
+                value if value == self.fat_type.bad_cluster() => ClusterState::Bad,
+                _ => ClusterState::Unmovable,
+            })
+            .collect();
+
+        let files = self
+            .movable_entries()
+            .into_iter()
+            .map(|(index, chain)| {
+                let entry = &self.entries[index];
+                let cells: Vec<usize> = chain.iter().map(|&cluster| Self::cell(cluster)).collect();
+                for &cell in &cells {
+                    clusters[cell] = ClusterState::Pending;
+                }
+                let mut file = FileFragment::new(entry.path.clone(), cells);
+                file.modified = entry.modified;
+                if entry.long_path != entry.path {
+                    file.long_name = Some(entry.long_path.clone());
+                }
+                file
+            })
+            .collect();
+
+        DiskLayout { clusters, files }
+    }
+}
+
+/// Long file name assembled from the VFAT entries preceding a short entry
+#[derive(Default)]
+struct LongName {
+    /// UCS-2 characters, 13 per VFAT entry
+    chars: Vec<u16>,
+    /// Sequence number of the next VFAT entry expected
+    expected: u8,
+    checksum: u8,
+    valid: bool,
+}
+
+impl LongName {
+    /// Positions of the 13 name characters inside a VFAT entry
+    const CHAR_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+    /// Adds a VFAT entry; they are stored last part first
+    fn push(&mut self, raw: &[u8]) {
+        let sequence = raw[0] & 0x1F;
+        if raw[0] & 0x40 != 0 {
+            *self = Self {
+                chars: vec![0xFFFF; sequence as usize * 13],
+                expected: sequence,
+                checksum: raw[13],
+                valid: sequence > 0,
+            };
+        }
+        // Sequence numbers start at 1: an entry numbered 0 is corrupt
+        if !self.valid || sequence == 0 || sequence != self.expected || raw[13] != self.checksum {
+            self.valid = false;
+            return;
+        }
+
+        let start = (sequence as usize - 1) * 13;
+        for (i, &offset) in Self::CHAR_OFFSETS.iter().enumerate() {
+            self.chars[start + i] = read_u16(raw, offset);
+        }
+        self.expected -= 1;
+    }
+
+    /// Checksum of an 8.3 name, stored in each of its VFAT entries
+    fn short_name_checksum(name: &[u8]) -> u8 {
+        name[..11]
+            .iter()
+            .fold(0u8, |sum, &b| sum.rotate_right(1).wrapping_add(b))
+    }
+
+    /// The long name belonging to the short entry `raw`, if one was read
+    fn finish(self, raw: &[u8]) -> Option<String> {
+        if !self.valid || self.expected != 0 || Self::short_name_checksum(raw) != self.checksum {
+            return None;
+        }
+        let end = self
+            .chars
+            .iter()
+            .position(|&c| c == 0x0000 || c == 0xFFFF)
+            .unwrap_or(self.chars.len());
+        Some(String::from_utf16_lossy(&self.chars[..end]))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Builds a blank 1.44 MB floppy image formatted as FAT12
+    pub(crate) fn floppy() -> Vec<u8> {
+        let mut image = vec![0u8; 2880 * 512];
+        image[0..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        image[3..11].copy_from_slice(b"MSDOS5.0");
+        image[11..13].copy_from_slice(&512u16.to_le_bytes());
+        image[13] = 1;
+        image[14..16].copy_from_slice(&1u16.to_le_bytes());
+        image[16] = 2;
+        image[17..19].copy_from_slice(&224u16.to_le_bytes());
+        image[19..21].copy_from_slice(&2880u16.to_le_bytes());
+        image[21] = 0xF0;
+        image[22..24].copy_from_slice(&9u16.to_le_bytes());
+        image[38] = 0x29;
+        image[43..54].copy_from_slice(b"NO NAME    ");
+        image[510] = 0x55;
+        image[511] = 0xAA;
+
+        for copy in 0..2 {
+            let fat = 512 + copy * 9 * 512;
+            image[fat..fat + 3].copy_from_slice(&[0xF0, 0xFF, 0xFF]);
+        }
+        image
+    }
+
+    /// Sets FAT12 entry `cluster` to `value` in every FAT copy
+    pub(crate) fn set_fat12(image: &mut [u8], cluster: usize, value: u16) {
+        for copy in 0..2 {
+            let offset = 512 + copy * 9 * 512 + cluster + cluster / 2;
+            let current = read_u16(image, offset);
+            let updated = if cluster % 2 == 1 {
+                (current & 0x000F) | (value << 4)
+            } else {
+                (current & 0xF000) | (value & 0x0FFF)
+            };
+            image[offset..offset + 2].copy_from_slice(&updated.to_le_bytes());
+        }
+    }
+
+    /// Writes a short directory entry at `offset`
+    pub(crate) fn write_entry(
+        image: &mut [u8],
+        offset: usize,
+        name: &[u8; 11],
+        attributes: u8,
+        start: u16,
+        size: u32,
+    ) {
+        image[offset..offset + 11].copy_from_slice(name);
+        image[offset + 11] = attributes;
+        image[offset + 24..offset + 26].copy_from_slice(&0x1A21u16.to_le_bytes());
+        image[offset + 26..offset + 28].copy_from_slice(&start.to_le_bytes());
+        image[offset + 28..offset + 32].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Links `chain` in the FAT, ending it with an end-of-chain marker
+    pub(crate) fn link(image: &mut [u8], chain: &[u16]) {
+        for pair in chain.windows(2) {
+            set_fat12(image, pair[0] as usize, pair[1]);
+        }
+        set_fat12(image, *chain.last().unwrap() as usize, 0xFFF);
+    }
+
+    /// Byte offset of the root directory of [`floppy`]
+    pub(crate) const ROOT: usize = 19 * 512;
+
+    /// Byte offset of data cluster `cluster` of [`floppy`]
+    pub(crate) fn cluster_offset(cluster: usize) -> usize {
+        (33 + cluster - 2) * 512
+    }
+
+    /// A floppy with a fragmented file, a system file and a subdirectory
+    pub(crate) fn sample_floppy() -> Vec<u8> {
+        let mut image = floppy();
+        write_entry(
+            &mut image,
+            ROOT,
+            b"IO      SYS",
+            attr::SYSTEM | attr::HIDDEN,
+            2,
+            1024,
+        );
+        link(&mut image, &[2, 3]);
+        write_entry(&mut image, ROOT + 32, b"GAMES      ", attr::VOLUME_ID, 0, 0);
+        write_entry(
+            &mut image,
+            ROOT + 64,
+            b"README  TXT",
+            attr::ARCHIVE,
+            10,
+            1500,
+        );
+        link(&mut image, &[10, 5, 20]);
+        write_entry(&mut image, ROOT + 96, b"DOS        ", attr::DIRECTORY, 8, 0);
+        link(&mut image, &[8]);
+
+        let dir = cluster_offset(8);
+        write_entry(&mut image, dir, b".          ", attr::DIRECTORY, 8, 0);
+        write_entry(&mut image, dir + 32, b"..         ", attr::DIRECTORY, 0, 0);
+        write_entry(&mut image, dir + 64, b"EDIT    COM", attr::ARCHIVE, 30, 600);
+        link(&mut image, &[30, 31]);
+        set_fat12(&mut image, 40, 0xFF7);
+        image
+    }
+
+    /// Builds a blank 2 MB FAT32 volume with 4000 clusters of 512 bytes
+    pub(crate) fn fat32_disk() -> Vec<u8> {
+        let mut image = vec![0u8; 4096 * 512];
+        image[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        image[3..11].copy_from_slice(b"MSWIN4.1");
+        image[11..13].copy_from_slice(&512u16.to_le_bytes());
+        image[13] = 1;
+        image[14..16].copy_from_slice(&32u16.to_le_bytes());
+        image[16] = 2;
+        image[21] = 0xF8;
+        image[32..36].copy_from_slice(&4096u32.to_le_bytes());
+        image[36..40].copy_from_slice(&32u32.to_le_bytes());
+        image[44..48].copy_from_slice(&2u32.to_le_bytes());
+        image[48..50].copy_from_slice(&1u16.to_le_bytes());
+        image[50..52].copy_from_slice(&6u16.to_le_bytes());
+        image[66] = 0x29;
+        image[71..82].copy_from_slice(b"WIN98      ");
+        image[510] = 0x55;
+        image[511] = 0xAA;
+
+        for sector in [1, 7] {
+            let fs_info = sector * 512;
+            image[fs_info..fs_info + 4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+            image[fs_info + 484..fs_info + 488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+            image[fs_info + 488..fs_info + 496].copy_from_slice(&[0xFF; 8]);
+            image[fs_info + 508..fs_info + 512].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+        }
+
+        set_fat32(&mut image, 0, 0x0FFF_FFF8);
+        set_fat32(&mut image, 1, 0x0FFF_FFFF);
+        set_fat32(&mut image, 2, 0x0FFF_FFFF);
+        image
+    }
+
+    /// Sets FAT32 entry `cluster` to `value` in both FAT copies
+    pub(crate) fn set_fat32(image: &mut [u8], cluster: usize, value: u32) {
+        for copy in 0..2 {
+            let offset = (32 + copy * 32) * 512 + cluster * 4;
+            image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    /// Byte offset of data cluster `cluster` of [`fat32_disk`]
+    pub(crate) fn fat32_cluster_offset(cluster: usize) -> usize {
+        (96 + cluster - 2) * 512
+    }
+
+    /// Writes `long` as VFAT entries followed by the short entry `short`,
+    /// returning the offset of the short entry
+    pub(crate) fn write_long_entry(
+        image: &mut [u8],
+        offset: usize,
+        long: &str,
+        short: &[u8; 11],
+        attributes: u8,
+        start: u16,
+        size: u32,
+    ) -> usize {
+        let mut chars: Vec<u16> = long.encode_utf16().collect();
+        if !chars.len().is_multiple_of(13) {
+            chars.push(0);
+        }
+        while !chars.len().is_multiple_of(13) {
+            chars.push(0xFFFF);
+        }
+
+        let count = chars.len() / 13;
+        let checksum = LongName::short_name_checksum(short);
+        let mut slot = offset;
+        for sequence in (1..=count).rev() {
+            let raw = &mut image[slot..slot + 32];
+            raw[0] = sequence as u8 | if sequence == count { 0x40 } else { 0 };
+            raw[11] = attr::LONG_NAME;
+            raw[13] = checksum;
+            let part = &chars[(sequence - 1) * 13..sequence * 13];
+            for (&c, &at) in part.iter().zip(&LongName::CHAR_OFFSETS) {
+                raw[at..at + 2].copy_from_slice(&c.to_le_bytes());
+            }
+            slot += 32;
+        }
+        write_entry(image, slot, short, attributes, start, size);
+        slot
+    }
+
+    /// A FAT32 volume with long names, a subdirectory and a fragmented file
+    pub(crate) fn sample_fat32() -> Vec<u8> {
+        let mut image = fat32_disk();
+        let root = fat32_cluster_offset(2);
+        let next = write_long_entry(
+            &mut image,
+            root,
+            "Program Files",
+            b"PROGRA~1   ",
+            attr::DIRECTORY,
+            10,
+            0,
+        );
+        set_fat32(&mut image, 10, 0x0FFF_FFFF);
+        write_entry(
+            &mut image,
+            next + 32,
+            b"AUTOEXECBAT",
+            attr::ARCHIVE,
+            12,
+            100,
+        );
+        set_fat32(&mut image, 12, 0x0FFF_FFFF);
+
+        let dir = fat32_cluster_offset(10);
+        write_entry(&mut image, dir, b".          ", attr::DIRECTORY, 10, 0);
+        write_entry(&mut image, dir + 32, b"..         ", attr::DIRECTORY, 0, 0);
+        write_long_entry(
+            &mut image,
+            dir + 64,
+            "Long Document Name.txt",
+            b"LONGDO~1TXT",
+            attr::ARCHIVE,
+            20,
+            1400,
+        );
+        set_fat32(&mut image, 20, 15);
+        set_fat32(&mut image, 15, 30);
+        set_fat32(&mut image, 30, 0x0FFF_FFFF);
+        image
+    }
+
+    #[test]
+    fn test_parse_floppy_directory_tree() {
+        let volume = FatVolume::parse(sample_floppy()).unwrap();
+
+        assert_eq!(volume.fat_type, FatType::Fat12);
+        assert_eq!(volume.boot.cluster_count(), 2847);
+        assert_eq!(volume.boot.volume_label, "GAMES");
+
+        let paths: Vec<&str> = volume.entries.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["IO.SYS", "README.TXT", "DOS", "DOS\\EDIT.COM"]);
+        assert_eq!(volume.chain(10).unwrap(), [10, 5, 20]);
+    }
+
+    #[test]
+    fn test_layout_maps_clusters_and_files() {
+        let volume = FatVolume::parse(sample_floppy()).unwrap();
+        let layout = volume.layout();
+
+        assert_eq!(layout.clusters.len(), 2847);
+        assert_eq!(layout.clusters[0], ClusterState::Unmovable);
+        assert_eq!(layout.clusters[1], ClusterState::Unmovable);
+        assert_eq!(layout.clusters[38], ClusterState::Bad);
+        assert_eq!(layout.clusters[2], ClusterState::Unused);
+
+        let readme = layout
+            .files
+            .iter()
+            .find(|f| f.name == "README.TXT")
+            .unwrap();
+        assert_eq!(readme.clusters, [8, 3, 18]);
+        assert!(readme.is_fragmented);
+        assert!(layout.files.iter().all(|f| f.name != "IO.SYS"));
+        assert_eq!(layout.files.len(), 3);
+    }
+
+    #[test]
+    fn test_broken_chain_is_left_in_place() {
+        let mut image = sample_floppy();
+        set_fat12(&mut image, 5, 0);
+        let volume = FatVolume::parse(image).unwrap();
+
+        assert!(volume.chain(10).is_err());
+        let layout = volume.layout();
+        assert!(layout.files.iter().all(|f| f.name != "README.TXT"));
+        assert_eq!(layout.clusters[8], ClusterState::Unmovable);
+    }
+
+    #[test]
+    fn test_cross_linked_files_are_left_in_place() {
+        // EDIT.COM ends on the last cluster of README.TXT
+        let mut image = sample_floppy();
+        link(&mut image, &[30, 20]);
+        let volume = FatVolume::parse(image).unwrap();
+
+        let layout = volume.layout();
+        assert!(layout
+            .files
+            .iter()
+            .all(|f| f.name != "README.TXT" && f.name != "DOS\\EDIT.COM"));
+        assert_eq!(layout.clusters[18], ClusterState::Unmovable);
+        assert_eq!(layout.clusters[28], ClusterState::Unmovable);
+    }
+
+    #[test]
+    fn test_overflowing_boot_sector_is_rejected() {
+        // 255 FATs of 2^30 sectors each overflow a sector number
+        let mut image = fat32_disk();
+        image[16] = 255;
+        image[36..40].copy_from_slice(&(1u32 << 30).to_le_bytes());
+        assert!(BootSector::parse(&image).is_err());
+    }
+
+    #[test]
+    fn test_undersized_fat_is_rejected() {
+        // One FAT sector holds 341 FAT12 entries, not the 2800 clusters
+        let mut image = floppy();
+        image[22..24].copy_from_slice(&1u16.to_le_bytes());
+        let error = BootSector::parse(&image).unwrap_err();
+        assert!(error.to_string().contains("too small"), "{}", error);
+    }
+
+    #[test]
+    fn test_parse_fat32_long_names() {
+        let volume = FatVolume::parse(sample_fat32()).unwrap();
+
+        assert_eq!(volume.fat_type, FatType::Fat32);
+        assert_eq!(volume.boot.volume_label, "WIN98");
+        assert_eq!(volume.boot.cluster_count(), 4000);
+        assert!(volume.fs_info.is_some());
+
+        let paths: Vec<(&str, &str)> = volume
+            .entries
+            .iter()
+            .map(|e| (e.path.as_str(), e.long_path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("PROGRA~1", "Program Files"),
+                ("AUTOEXEC.BAT", "AUTOEXEC.BAT"),
+                (
+                    "PROGRA~1\\LONGDO~1.TXT",
+                    "Program Files\\Long Document Name.txt"
+                ),
+            ]
+        );
+
+        let layout = volume.layout();
+        let document = layout
+            .files
+            .iter()
+            .find(|f| f.name == "PROGRA~1\\LONGDO~1.TXT")
+            .unwrap();
+        assert_eq!(
+            document.display_name(),
+            "Program Files\\Long Document Name.txt"
+        );
+        assert!(document.is_fragmented);
+        // The root directory chain stays in place
+        assert_eq!(layout.clusters[0], ClusterState::Unmovable);
+    }
+
+    #[test]
+    fn test_long_name_with_bad_checksum_is_ignored() {
+        let mut image = sample_fat32();
+        // Rename the short entry without updating its VFAT entries
+        let short = fat32_cluster_offset(2) + 32;
+        image[short..short + 11].copy_from_slice(b"PROGRAMS   ");
+        let volume = FatVolume::parse(image).unwrap();
+
+        assert_eq!(volume.entries[0].long_path, "PROGRAMS");
+    }
+
+    #[test]
+    fn test_short_names_are_code_page_437() {
+        let mut raw = [b' '; DIR_ENTRY_SIZE];
+        raw[..8].copy_from_slice(b"\x05T\x82\x9A    ");
+        raw[8..11].copy_from_slice(b"\xE1\xB0 ");
+        // 0x05 stands for a leading 0xE5, sigma in code page 437
+        assert_eq!(FatVolume::entry_name(&raw), "σTéÜ.ß░");
+    }
+
+    #[test]
+    fn test_vfat_entry_numbered_zero_is_rejected() {
+        let mut long_name = LongName::default();
+        let mut raw = [0u8; DIR_ENTRY_SIZE];
+        raw[0] = 0x41;
+        raw[11] = attr::LONG_NAME;
+        raw[13] = 7;
+        long_name.push(&raw);
+        assert!(long_name.valid);
+
+        // Right after the complete name, nothing more is expected
+        raw[0] = 0x20;
+        long_name.push(&raw);
+        assert!(!long_name.valid);
+    }
+}
@@ -0,0 +1,401 @@
+//! Writing a defragmented copy of a FAT image
+//!
+//! The defragmenter works on grid cells; once files have been moved, their
+//! new cell lists are turned back into cluster chains, FAT copies and
+//! directory entries of a new image. Both the move plan and the rebuilt image
+//! go through a CHKDSK-like consistency pass before anything is written.
+
+use super::{invalid, read_u16, read_u32, FatType, FatVolume, FsInfo, FIRST_DATA_CLUSTER};
+use crate::models::FileFragment;
+use std::collections::{HashMap, HashSet};
+use std::io::Result;
+use std::path::Path;
+
+/// Directory entry names of the "." and ".." entries
+const DOT: &[u8; 11] = b".          ";
+const DOT_DOT: &[u8; 11] = b"..         ";
+
+/// Encodes `value` as the FAT entry of `cluster` in a raw FAT copy
+fn write_fat_entry(fat_type: FatType, table: &mut [u8], cluster: usize, value: u32) {
+    match fat_type {
+        FatType::Fat12 => {
+            let offset = cluster + cluster / 2;
+            let current = read_u16(table, offset);
+            let value = value as u16 & 0x0FFF;
+            let updated = if cluster % 2 == 1 {
+                (current & 0x000F) | (value << 4)
+            } else {
+                (current & 0xF000) | value
+            };
+            table[offset..offset + 2].copy_from_slice(&updated.to_le_bytes());
+        }
+        FatType::Fat16 => {
+            let offset = cluster * 2;
+            table[offset..offset + 2].copy_from_slice(&(value as u16).to_le_bytes());
+        }
+        FatType::Fat32 => {
+            // The top four bits are reserved and must be kept
+            let offset = cluster * 4;
+            let updated = (read_u32(table, offset) & 0xF000_0000) | (value & 0x0FFF_FFFF);
+            table[offset..offset + 4].copy_from_slice(&updated.to_le_bytes());
+        }
+    }
+}
+
+/// A movable entry with its cluster chain before and after defragmentation
+struct MovedFile {
+    /// Index of the entry in [`FatVolume::entries`]
+    entry: usize,
+    old: Vec<u32>,
+    new: Vec<u32>,
+}
+
+impl FatVolume {
+    /// Cluster chains of the movable entries once moved to `files`
+    ///
+    /// `files` is the file list of [`Self::layout`] after defragmentation.
+    fn new_chains(&self, files: &[FileFragment]) -> Result<Vec<MovedFile>> {
+        let movable = self.movable_entries();
+        if movable.len() != files.len() {
+            return Err(invalid(format!(
+                "plan covers {} files but the image has {}",
+                files.len(),
+                movable.len()
+            )));
+        }
+
+        Ok(movable
+            .into_iter()
+            .zip(files)
+            .map(|((entry, old), file)| MovedFile {
+                entry,
+                old,
+                new: file
+                    .clusters
+                    .iter()
+                    .map(|&cell| cell as u32 + FIRST_DATA_CLUSTER)
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Checks that the moved files fit the volume, like CHKDSK would
+    ///
+    /// Every file must keep its length, only use valid clusters, and never
+    /// share a cluster with another file, a bad cluster or anything the
+    /// defragmenter left in place.
+    pub fn check_plan(&self, files: &[FileFragment]) -> Result<()> {
+        let chains = self.new_chains(files)?;
+
+        // Clusters allocated in the FAT that are not moved around
+        let moved: HashSet<u32> = chains.iter().flat_map(|m| m.old.clone()).collect();
+        let mut allocated: HashSet<u32> = (FIRST_DATA_CLUSTER..self.fat.len() as u32)
+            .filter(|&cluster| self.fat[cluster as usize] != 0 && !moved.contains(&cluster))
+            .collect();
+
+        for MovedFile { entry, old, new } in &chains {
+            let path = &self.entries[*entry].path;
+            if old.len() != new.len() {
+                return Err(invalid(format!(
+                    "{}: {} clusters planned for {} allocated",
+                    path,
+                    new.len(),
+                    old.len()
+                )));
+            }
+            for &cluster in new {
+                if !self.is_data_cluster(cluster) {
+                    return Err(invalid(format!(
+                        "{}: cluster {} is out of range",
+                        path, cluster
+                    )));
+                }
+                if !allocated.insert(cluster) {
+                    return Err(invalid(format!(
+                        "{}: cluster {} is cross-linked",
+                        path, cluster
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds a copy of the image with files moved to their place in `files`
+    ///
+    /// Cluster contents are copied unchanged; the FAT copies, the start
+    /// clusters of the directory entries and the "." and ".." entries of
+    /// moved directories are updated. The result is parsed back and compared
+    /// with the original before being returned.
+    pub fn defragmented_image(&self, files: &[FileFragment]) -> Result<Vec<u8>> {
+        self.check_plan(files)?;
+        let chains = self.new_chains(files)?;
+
+        let relocation: HashMap<u32, u32> = chains
+            .iter()
+            .flat_map(|m| m.old.iter().copied().zip(m.new.iter().copied()))
+            .collect();
+        let relocate = |cluster: u32| relocation.get(&cluster).copied().unwrap_or(cluster);
+
+        // Cluster data, read from the untouched original
+        let mut image = self.data.clone();
+        let size = self.boot.cluster_size();
+        for (&old, &new) in &relocation {
+            let from = self.boot.cluster_offset(old);
+            let to = self.boot.cluster_offset(new);
+            image[to..to + size].copy_from_slice(&self.data[from..from + size]);
+        }
+
+        // File allocation table, in every copy
+        let mut fat = self.fat.clone();
+        for moved in &chains {
+            for &cluster in &moved.old {
+                fat[cluster as usize] = 0;
+            }
+        }
+        for moved in &chains {
+            for pair in moved.new.windows(2) {
+                fat[pair[0] as usize] = pair[1];
+            }
+            if let Some(&last) = moved.new.last() {
+                fat[last as usize] = self.fat_type.end_of_chain() | 0x7;
+            }
+        }
+        for copy in 0..self.boot.fat_count as usize {
+            let start = self.boot.fat_offset() + copy * self.boot.fat_size();
+            let table = &mut image[start..start + self.boot.fat_size()];
+            for (cluster, &value) in fat.iter().enumerate().skip(FIRST_DATA_CLUSTER as usize) {
+                write_fat_entry(self.fat_type, table, cluster, value);
+            }
+        }
+
+        // Directory entries live in directories that may have moved too
+        let data_start = self.boot.cluster_offset(FIRST_DATA_CLUSTER);
+        let relocate_offset = |offset: usize| -> usize {
+            if offset < data_start {
+                return offset;
+            }
+            let cluster = ((offset - data_start) / size) as u32 + FIRST_DATA_CLUSTER;
+            self.boot.cluster_offset(relocate(cluster)) + (offset - data_start) % size
+        };
+        let fat32 = self.fat_type == FatType::Fat32;
+        let set_start = |image: &mut [u8], offset: usize, cluster: u32| {
+            image[offset + 26..offset + 28].copy_from_slice(&(cluster as u16).to_le_bytes());
+            if fat32 {
+                image[offset + 20..offset + 22]
+                    .copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+            }
+        };
+
+        let starts: HashMap<&str, u32> = self
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), relocate(entry.start_cluster)))
+            .collect();
+        for entry in &self.entries {
+            let start = relocate(entry.start_cluster);
+            set_start(&mut image, relocate_offset(entry.entry_offset), start);
+
+            if entry.is_directory() && self.is_data_cluster(start) {
+                let parent = entry
+                    .path
+                    .rsplit_once('\\')
+                    .and_then(|(parent, _)| starts.get(parent).copied())
+                    .unwrap_or(0);
+                let first = self.boot.cluster_offset(start);
+                if &image[first..first + 11] == DOT {
+                    set_start(&mut image, first, start);
+                }
+                if &image[first + 32..first + 43] == DOT_DOT {
+                    set_start(&mut image, first + 32, parent);
+                }
+            }
+        }
+
+        // Free space hint of FAT32, in the FSInfo sector and its backup
+        if self.fs_info.is_some() {
+            let free: Vec<usize> = (FIRST_DATA_CLUSTER as usize..fat.len())
+                .filter(|&cluster| fat[cluster] == 0)
+                .collect();
+            let fs_info = FsInfo {
+                free_count: free.len() as u32,
+                next_free: free.first().map_or(u32::MAX, |&cluster| cluster as u32),
+            };
+            let mut sectors = vec![self.boot.fs_info_sector];
+            if self.boot.backup_boot_sector != 0 {
+                sectors.push(self.boot.backup_boot_sector + self.boot.fs_info_sector);
+            }
+            for sector in sectors {
+                let offset = self.boot.sector_offset(sector);
+                if let Some(bytes) = image.get_mut(offset..offset + 512) {
+                    if FsInfo::parse(bytes).is_some() {
+                        fs_info.write(bytes);
+                    }
+                }
+            }
+        }
+
+        self.verify_copy(&image, &chains)?;
+        Ok(image)
+    }
+
+    /// Parses the rebuilt image back and checks every file kept its path and
+    /// contents and got the planned cluster chain
+    fn verify_copy(&self, image: &[u8], chains: &[MovedFile]) -> Result<()> {
+        let copy = FatVolume::parse(image.to_vec())?;
+        if copy.entries.len() != self.entries.len() {
+            return Err(invalid(format!(
+                "rebuilt image lists {} entries instead of {}",
+                copy.entries.len(),
+                self.entries.len()
+            )));
+        }
+
+        let planned: HashMap<usize, &Vec<u32>> = chains.iter().map(|m| (m.entry, &m.new)).collect();
+        let by_path: HashMap<&str, usize> = copy
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (entry.path.as_str(), index))
+            .collect();
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let Some(&moved) = by_path.get(entry.path.as_str()) else {
+                return Err(invalid(format!("{} is missing", entry.path)));
+            };
+            if entry.start_cluster == 0 {
+                continue;
+            }
+            let Ok(before) = self.chain(entry.start_cluster) else {
+                continue;
+            };
+            let after = copy.chain(copy.entries[moved].start_cluster)?;
+
+            if let Some(new) = planned.get(&index) {
+                if &after != *new {
+                    return Err(invalid(format!("{} was not moved as planned", entry.path)));
+                }
+            }
+            // Directories legitimately differ in their "." and ".." entries
+            if !entry.is_directory() && self.read_chain(&before) != copy.read_chain(&after) {
+                return Err(invalid(format!("{} contents changed", entry.path)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a defragmented copy of the image to `path`
+    pub fn write_defragmented(&self, files: &[FileFragment], path: impl AsRef<Path>) -> Result<()> {
+        let image = self.defragmented_image(files)?;
+        std::fs::write(path, image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{cluster_offset, sample_fat32, sample_floppy};
+    use super::*;
+    use crate::models::OptimizationMethod;
+    use crate::planner;
+
+    /// Fills every data cluster with bytes identifying it
+    fn stamped_floppy() -> Vec<u8> {
+        let mut image = sample_floppy();
+        for cluster in 9..2849 {
+            let offset = cluster_offset(cluster);
+            for (i, byte) in image[offset..offset + 512].iter_mut().enumerate() {
+                *byte = (cluster * 7 + i) as u8;
+            }
+        }
+        image
+    }
+
+    /// Runs a full optimization on `volume` and returns the moved files
+    fn defragment(volume: &FatVolume) -> Vec<FileFragment> {
+        let mut layout = volume.layout();
+        let plan = planner::plan(
+            OptimizationMethod::FullOptimization,
+            Default::default(),
+            &layout.clusters,
+            &layout.files,
+        );
+        for m in plan {
+            let file = &mut layout.files[m.file];
+            for (from, to) in m.from.iter().zip(&m.to) {
+                let index = file.clusters.iter().position(|c| c == from).unwrap();
+                file.relocate(index, *to);
+            }
+        }
+        layout.files
+    }
+
+    #[test]
+    fn test_defragmented_image_keeps_contents() {
+        let volume = FatVolume::parse(stamped_floppy()).unwrap();
+        let files = defragment(&volume);
+        let image = volume.defragmented_image(&files).unwrap();
+        let copy = FatVolume::parse(image).unwrap();
+
+        let readme = copy
+            .entries
+            .iter()
+            .find(|e| e.path == "README.TXT")
+            .unwrap();
+        let chain = copy.chain(readme.start_cluster).unwrap();
+        assert!(chain.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert_eq!(
+            copy.read_chain(&chain),
+            volume.read_chain(&volume.chain(10).unwrap())
+        );
+
+        // The system file did not move
+        assert_eq!(copy.chain(2).unwrap(), [2, 3]);
+        assert!(copy.layout().files.iter().all(|file| !file.is_fragmented));
+    }
+
+    #[test]
+    fn test_moved_directory_keeps_dot_entries() {
+        let volume = FatVolume::parse(stamped_floppy()).unwrap();
+        let files = defragment(&volume);
+        let image = volume.defragmented_image(&files).unwrap();
+        let copy = FatVolume::parse(image.clone()).unwrap();
+
+        let dos = copy.entries.iter().find(|e| e.path == "DOS").unwrap();
+        assert_ne!(dos.start_cluster, 8);
+        let first = copy.boot.cluster_offset(dos.start_cluster);
+        assert_eq!(read_u16(&image, first + 26) as u32, dos.start_cluster);
+        assert_eq!(read_u16(&image, first + 32 + 26), 0);
+        assert!(copy.entries.iter().any(|e| e.path == "DOS\\EDIT.COM"));
+    }
+
+    #[test]
+    fn test_check_rejects_cross_linked_plan() {
+        let volume = FatVolume::parse(sample_floppy()).unwrap();
+        let mut files = volume.layout().files;
+
+        // Move README.TXT onto the system file
+        let readme = files.iter_mut().find(|f| f.name == "README.TXT").unwrap();
+        readme.relocate(0, 0);
+        assert!(volume.check_plan(&files).is_err());
+        assert!(volume.defragmented_image(&files).is_err());
+    }
+
+    #[test]
+    fn test_fat32_copy_keeps_long_names_and_updates_fs_info() {
+        let volume = FatVolume::parse(sample_fat32()).unwrap();
+        let files = defragment(&volume);
+        let copy = FatVolume::parse(volume.defragmented_image(&files).unwrap()).unwrap();
+
+        let document = copy
+            .entries
+            .iter()
+            .find(|e| e.long_path == "Program Files\\Long Document Name.txt")
+            .unwrap();
+        let chain = copy.chain(document.start_cluster).unwrap();
+        assert_eq!(chain.len(), 3);
+        assert!(chain.windows(2).all(|pair| pair[1] == pair[0] + 1));
+
+        let free = copy.fat[2..].iter().filter(|&&value| value == 0).count() as u32;
+        assert_eq!(copy.fs_info.unwrap().free_count, free);
+    }
+}
@@ -1,6 +1,8 @@
 //! Graphical rendering module for Windows 95/98 style interfaces
 //! Uses SDL2 for pixel-perfect recreation of the classic Windows look
 
+pub mod palette;
+
 #[cfg(feature = "graphical")]
 pub mod sdl_backend;
 
@@ -0,0 +1,41 @@
+//! Cluster colors of the Windows 98 defragmenter
+//!
+//! Kept apart from the SDL renderer so image exports can use them in the
+//! terminal build as well.
+
+use crate::models::ClusterState;
+
+pub const DEFRAG_IDLE: [u8; 3] = [0, 0, 128]; // navy (NOT_DEFRAGMENTED)
+pub const DEFRAG_PROGRESS: [u8; 3] = [255, 0, 0]; // red (IN_PROGRESS)
+pub const DEFRAG_DONE: [u8; 3] = [19, 250, 251]; // #13fafb (COMPLETED)
+
+/// Win98 cluster states
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Win98ClusterState {
+    NotDefragmented, // Navy blue
+    InProgress,      // Red
+    Completed,       // Cyan
+}
+
+impl Win98ClusterState {
+    pub fn rgb(&self) -> [u8; 3] {
+        match self {
+            Win98ClusterState::NotDefragmented => DEFRAG_IDLE,
+            Win98ClusterState::InProgress => DEFRAG_PROGRESS,
+            Win98ClusterState::Completed => DEFRAG_DONE,
+        }
+    }
+}
+
+impl From<&ClusterState> for Win98ClusterState {
+    fn from(state: &ClusterState) -> Self {
+        match state {
+            ClusterState::Used => Win98ClusterState::Completed,
+            ClusterState::Pending => Win98ClusterState::NotDefragmented,
+            ClusterState::Reading | ClusterState::Writing => Win98ClusterState::InProgress,
+            ClusterState::Unused | ClusterState::Bad | ClusterState::Unmovable => {
+                Win98ClusterState::NotDefragmented
+            }
+        }
+    }
+}
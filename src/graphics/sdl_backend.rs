@@ -13,8 +13,13 @@ use sdl2::video::{Window, WindowContext};
 
 /// Windows 98 color palette
 pub mod colors {
+    use crate::graphics::palette;
     use sdl2::pixels::Color;
 
+    const fn from_rgb([r, g, b]: [u8; 3]) -> Color {
+        Color::RGB(r, g, b)
+    }
+
     // Window chrome colors
     pub const SURFACE: Color = Color::RGB(192, 192, 192); // silver
     pub const BUTTON_FACE: Color = Color::RGB(223, 223, 223); // #dfdfdf
@@ -28,9 +33,9 @@ pub mod colors {
     pub const DIALOG_GRAY: Color = Color::RGB(128, 128, 128); // inactive
 
     // Defrag specific colors
-    pub const DEFRAG_IDLE: Color = Color::RGB(0, 0, 128); // navy (NOT_DEFRAGMENTED)
-    pub const DEFRAG_PROGRESS: Color = Color::RGB(255, 0, 0); // red (IN_PROGRESS)
-    pub const DEFRAG_DONE: Color = Color::RGB(19, 250, 251); // #13fafb (COMPLETED)
+    pub const DEFRAG_IDLE: Color = from_rgb(palette::DEFRAG_IDLE);
+    pub const DEFRAG_PROGRESS: Color = from_rgb(palette::DEFRAG_PROGRESS);
+    pub const DEFRAG_DONE: Color = from_rgb(palette::DEFRAG_DONE);
 
     // Text and background
     pub const TEXT: Color = Color::RGB(34, 34, 34); // #222
@@ -141,9 +146,6 @@ impl SdlBackend {
                     ..
                 } => {
                     events.push(SdlEvent::KeyDown(keycode));
-                    if keycode == Keycode::Escape {
-                        self.running = false;
-                    }
                 }
                 Event::KeyUp {
                     keycode: Some(keycode),
@@ -7,10 +7,13 @@ use sdl2::pixels::Color;
 use std::time::{Duration, Instant};
 
 use super::sdl_backend::{colors, SdlBackend, SdlConfig, SdlEvent};
-use super::win98_widgets::{Button, ButtonState, ProgressBar, SunkenPanel, Win98WindowWidget};
+use super::win98_widgets::{
+    Button, ButtonState, CheckBox, ProgressBar, Slider, SunkenPanel, Win98WindowWidget,
+};
 use super::ResourceCache;
 use crate::app::App;
-use crate::models::{ClusterState, DefragPhase};
+use crate::audio::SoundCategory;
+use crate::models::{clusters_per_cell, ClusterState, DefragPhase};
 
 /// Cluster size in pixels for the disk grid
 const CLUSTER_SIZE: u32 = 8;
@@ -18,34 +21,130 @@ const CLUSTER_SIZE: u32 = 8;
 /// Spacing between clusters
 const CLUSTER_GAP: u32 = 1;
 
-/// Win98 cluster states
-#[derive(Clone, Copy, PartialEq, Debug)]
-pub enum Win98ClusterState {
-    NotDefragmented, // Navy blue
-    InProgress,      // Red
-    Completed,       // Cyan
-}
+pub use super::palette::Win98ClusterState;
 
 impl Win98ClusterState {
     pub fn color(&self) -> Color {
-        match self {
-            Win98ClusterState::NotDefragmented => colors::DEFRAG_IDLE,
-            Win98ClusterState::InProgress => colors::DEFRAG_PROGRESS,
-            Win98ClusterState::Completed => colors::DEFRAG_DONE,
+        let [r, g, b] = self.rgb();
+        Color::RGB(r, g, b)
+    }
+}
+
+/// The "Settings" dialog: sound volume and the sounds to mute
+struct SettingsDialog {
+    window: Win98WindowWidget,
+    volume_slider: Slider,
+    mute_boxes: Vec<(SoundCategory, CheckBox)>,
+    ok_button: Button,
+    /// Whether the slider thumb follows the mouse
+    dragging: bool,
+}
+
+impl SettingsDialog {
+    fn new(app: &App) -> Self {
+        let (width, height) = (300, 230);
+        let mut window = Win98WindowWidget::new(
+            (640 - width as i32) / 2,
+            (480 - height as i32) / 2,
+            width,
+            height,
+            "Settings",
+        );
+        window.has_minimize = false;
+        window.has_maximize = false;
+        let client = window.client_area();
+
+        let mut volume_slider = Slider::new(client.x + 12, client.y + 30, client.width - 24, 21);
+        volume_slider.value = app.volume;
+
+        let mute_boxes = SoundCategory::ALL
+            .into_iter()
+            .enumerate()
+            .map(|(index, category)| {
+                let mut check_box = CheckBox::new(
+                    client.x + 20,
+                    client.y + 84 + index as i32 * 22,
+                    170,
+                    &format!("Mute {}", category.label()),
+                );
+                check_box.checked = app.audio_settings.is_muted(category);
+                (category, check_box)
+            })
+            .collect();
+
+        let ok_button = Button::new(
+            client.x + client.width as i32 - 87,
+            client.y + client.height as i32 - 35,
+            75,
+            23,
+            "OK",
+        )
+        .with_default();
+
+        Self {
+            window,
+            volume_slider,
+            mute_boxes,
+            ok_button,
+            dragging: false,
         }
     }
 }
 
-impl From<&ClusterState> for Win98ClusterState {
-    fn from(state: &ClusterState) -> Self {
-        match state {
-            ClusterState::Used => Win98ClusterState::Completed,
-            ClusterState::Pending => Win98ClusterState::NotDefragmented,
-            ClusterState::Reading | ClusterState::Writing => Win98ClusterState::InProgress,
-            ClusterState::Unused | ClusterState::Bad | ClusterState::Unmovable => {
-                Win98ClusterState::NotDefragmented
-            }
+/// Message box telling the run is over, which quits when dismissed
+struct MessageBox {
+    window: Win98WindowWidget,
+    text: String,
+    ok_button: Button,
+    /// Whether closing the box ends the run, as the completion box does
+    ends_run: bool,
+}
+
+impl MessageBox {
+    fn new(title: &str, text: String) -> Self {
+        // Wide enough for about 7 pixels a character, within the screen
+        let width = (text.chars().count() as u32 * 7 + 40).clamp(340, 620);
+        let height = 120;
+        let mut window = Win98WindowWidget::new(
+            (640 - width as i32) / 2,
+            (480 - height as i32) / 2,
+            width,
+            height,
+            title,
+        );
+        window.has_minimize = false;
+        window.has_maximize = false;
+        let client = window.client_area();
+        let ok_button = Button::new(
+            client.x + (client.width as i32 - 75) / 2,
+            client.y + client.height as i32 - 35,
+            75,
+            23,
+            "OK",
+        )
+        .with_default();
+        Self {
+            window,
+            text,
+            ok_button,
+            ends_run: false,
+        }
+    }
+
+    fn ending_run(mut self) -> Self {
+        self.ends_run = true;
+        self
+    }
+}
+
+/// Shows `button` hovered while the mouse is over it
+fn update_hover(button: &mut Button, x: i32, y: i32) {
+    if button.area.contains(x, y) {
+        if button.state == ButtonState::Normal {
+            button.state = ButtonState::Hovered;
         }
+    } else if button.state == ButtonState::Hovered {
+        button.state = ButtonState::Normal;
     }
 }
 
@@ -60,9 +159,21 @@ pub struct Win98GraphicalRenderer {
     stop_button: Button,
     progress_bar: ProgressBar,
     disk_panel: SunkenPanel,
+    /// Settings dialog, when open over the main window
+    settings_dialog: Option<SettingsDialog>,
+    /// Message box, when shown
+    message_box: Option<MessageBox>,
+    /// Whether the run was complete at the last frame
+    was_complete: bool,
+    /// Whether saving the audio settings failed already, which is only
+    /// reported once
+    settings_unsaved: bool,
     // Mouse state
     mouse_x: i32,
     mouse_y: i32,
+    /// Whether the mouse was pressed on a control, which clicks again when
+    /// released
+    clicking: bool,
 }
 
 impl Win98GraphicalRenderer {
@@ -152,8 +263,13 @@ impl Win98GraphicalRenderer {
             stop_button,
             progress_bar,
             disk_panel,
+            settings_dialog: None,
+            message_box: None,
+            was_complete: false,
+            settings_unsaved: false,
             mouse_x: 0,
             mouse_y: 0,
+            clicking: false,
         })
     }
 
@@ -168,8 +284,12 @@ impl Win98GraphicalRenderer {
             // Process events
             self.handle_events(app);
 
-            // Update application state
-            app.update();
+            // Update application state; the run waits while a message box
+            // is shown
+            if self.message_box.is_none() {
+                app.update();
+            }
+            self.check_completion(app);
 
             // Update UI state from app
             self.update_ui_state(app);
@@ -227,6 +347,10 @@ impl Win98GraphicalRenderer {
         // Draw button text
         self.draw_button_text();
 
+        // Draw the dialogs over the window
+        self.draw_settings_dialog(app);
+        self.draw_message_box();
+
         // Present
         self.backend.present();
     }
@@ -246,6 +370,11 @@ impl Win98GraphicalRenderer {
                 SdlEvent::MouseMove { x, y } => {
                     self.mouse_x = x;
                     self.mouse_y = y;
+                    if let Some(ref mut dialog) = self.settings_dialog {
+                        if dialog.dragging {
+                            app.set_volume(dialog.volume_slider.value_at(x));
+                        }
+                    }
                     self.update_button_hover();
                 }
                 SdlEvent::MouseDown { x, y, .. } => {
@@ -261,8 +390,23 @@ impl Win98GraphicalRenderer {
 
     /// Handle keyboard input
     fn handle_keydown(&mut self, app: &mut App, keycode: Keycode) {
+        // Dialogs are modal
+        if self.message_box.is_some() {
+            if matches!(keycode, Keycode::Escape | Keycode::Return | Keycode::Space) {
+                self.dismiss_message_box(app);
+            }
+            return;
+        }
+        if self.settings_dialog.is_some() {
+            if matches!(keycode, Keycode::Escape | Keycode::Return) {
+                self.settings_dialog = None;
+            }
+            return;
+        }
+
         match keycode {
             Keycode::Escape | Keycode::Q => {
+                app.interrupted = keycode == Keycode::Escape;
                 app.running = false;
             }
             Keycode::Space | Keycode::Return => {
@@ -275,12 +419,29 @@ impl Win98GraphicalRenderer {
                     audio.toggle();
                 }
             }
+            Keycode::C => {
+                // PNG snapshot of the disk map
+                let text = match app.save_snapshot() {
+                    Ok(path) => format!("Snapshot saved to {}.", path.display()),
+                    Err(e) => format!("Cannot save snapshot: {}", e),
+                };
+                self.message_box = Some(MessageBox::new("Disk Defragmenter", text));
+            }
             _ => {}
         }
     }
 
     /// Update button hover states
     fn update_button_hover(&mut self) {
+        if let Some(ref mut message_box) = self.message_box {
+            update_hover(&mut message_box.ok_button, self.mouse_x, self.mouse_y);
+            return;
+        }
+        if let Some(ref mut dialog) = self.settings_dialog {
+            update_hover(&mut dialog.ok_button, self.mouse_x, self.mouse_y);
+            return;
+        }
+
         // Settings button
         if self
             .settings_button
@@ -319,36 +480,77 @@ impl Win98GraphicalRenderer {
         }
     }
 
-    /// Handle mouse button down
+    /// Handle mouse button down; pressing a control clicks
     fn handle_mouse_down(&mut self, app: &mut App, x: i32, y: i32) {
-        // Play mouse down sound
-        if let Some(ref audio) = app.audio {
-            audio.play_mouse_down();
-        }
-
-        if self.settings_button.area.contains(x, y) {
+        self.clicking = if let Some(ref mut message_box) = self.message_box {
+            let on_button = message_box.ok_button.area.contains(x, y);
+            if on_button {
+                message_box.ok_button.state = ButtonState::Pressed;
+            }
+            on_button
+        } else if let Some(ref mut dialog) = self.settings_dialog {
+            if dialog.ok_button.area.contains(x, y) {
+                dialog.ok_button.state = ButtonState::Pressed;
+                true
+            } else if dialog.volume_slider.area.contains(x, y) {
+                dialog.dragging = true;
+                app.set_volume(dialog.volume_slider.value_at(x));
+                true
+            } else {
+                dialog
+                    .mute_boxes
+                    .iter()
+                    .any(|(_, check_box)| check_box.area.contains(x, y))
+            }
+        } else if self.settings_button.area.contains(x, y) {
             self.settings_button.state = ButtonState::Pressed;
+            true
         } else if self.start_pause_button.area.contains(x, y) {
             self.start_pause_button.state = ButtonState::Pressed;
+            true
         } else if self.stop_button.area.contains(x, y)
             && self.stop_button.state != ButtonState::Disabled
         {
             self.stop_button.state = ButtonState::Pressed;
+            true
+        } else {
+            false
+        };
+
+        if self.clicking {
+            if let Some(ref audio) = app.audio {
+                audio.play_mouse_down();
+            }
         }
     }
 
     /// Handle mouse button up
     fn handle_mouse_up(&mut self, app: &mut App, x: i32, y: i32) {
-        // Play mouse up sound
-        if let Some(ref audio) = app.audio {
-            audio.play_mouse_up();
+        if std::mem::take(&mut self.clicking) {
+            if let Some(ref audio) = app.audio {
+                audio.play_mouse_up();
+            }
+        }
+
+        if let Some(ref mut message_box) = self.message_box {
+            if message_box.ok_button.state == ButtonState::Pressed {
+                message_box.ok_button.state = ButtonState::Normal;
+                if message_box.ok_button.area.contains(x, y) {
+                    self.dismiss_message_box(app);
+                }
+            }
+            return;
+        }
+        if self.settings_dialog.is_some() {
+            self.handle_settings_mouse_up(app, x, y);
+            return;
         }
 
         // Check for button clicks
         if self.settings_button.state == ButtonState::Pressed {
             self.settings_button.state = ButtonState::Normal;
             if self.settings_button.area.contains(x, y) {
-                // Settings clicked - TODO: show settings dialog
+                self.settings_dialog = Some(SettingsDialog::new(app));
             }
         }
 
@@ -367,14 +569,85 @@ impl Win98GraphicalRenderer {
         }
     }
 
+    /// Handle mouse button up in the settings dialog: check boxes mute their
+    /// sound at once, and the choice is saved to the user configuration when
+    /// it can be
+    fn handle_settings_mouse_up(&mut self, app: &mut App, x: i32, y: i32) {
+        let Some(ref mut dialog) = self.settings_dialog else {
+            return;
+        };
+        dialog.dragging = false;
+
+        if dialog.ok_button.state == ButtonState::Pressed {
+            dialog.ok_button.state = ButtonState::Normal;
+            if dialog.ok_button.area.contains(x, y) {
+                self.settings_dialog = None;
+                return;
+            }
+        }
+
+        let mut error = None;
+        for (category, check_box) in &mut dialog.mute_boxes {
+            if check_box.area.contains(x, y) {
+                check_box.checked = !check_box.checked;
+                if let Err(e) = app.set_sound_muted(*category, check_box.checked) {
+                    error = Some(format!("Cannot save audio settings: {}", e));
+                }
+            }
+        }
+        if let Some(text) = error {
+            if !self.settings_unsaved {
+                self.settings_unsaved = true;
+                self.message_box = Some(MessageBox::new("Disk Defragmenter", text));
+            }
+        }
+    }
+
+    /// Shows the completion message box, with its chime, once the run is
+    /// over: the last drive of an "All drives" run, or the only one
+    fn check_completion(&mut self, app: &App) {
+        let complete = app.engine.phase == DefragPhase::Finished
+            && app.drive_sequence.as_ref().is_none_or(|s| s.finished);
+        if complete && !self.was_complete {
+            if let Some(ref audio) = app.audio {
+                audio.play_chimes();
+            }
+            // Demo runs start over on their own
+            if !app.demo_mode {
+                let text = if app.drive_sequence.is_some() {
+                    "Defragmentation of all drives is complete.".to_string()
+                } else {
+                    format!(
+                        "Defragmentation of drive {} is complete.",
+                        app.current_drive.letter()
+                    )
+                };
+                self.settings_dialog = None;
+                self.message_box = Some(MessageBox::new("Disk Defragmenter", text).ending_run());
+            }
+        }
+        self.was_complete = complete;
+    }
+
+    /// Closes the message box; closing the completion one ends the run
+    fn dismiss_message_box(&mut self, app: &mut App) {
+        if self
+            .message_box
+            .take()
+            .is_some_and(|message_box| message_box.ends_run)
+        {
+            app.running = false;
+        }
+    }
+
     /// Toggle between start/pause
     fn toggle_defrag(&mut self, app: &mut App) {
-        match app.phase {
+        match app.engine.phase {
             DefragPhase::Initializing | DefragPhase::Finished => {
                 // Start
-                app.phase = DefragPhase::Analyzing;
-                app.animation_step = 0;
+                app.engine.start_analysis();
             }
+            DefragPhase::Analyzed => app.engine.proceed(),
             DefragPhase::Analyzing | DefragPhase::Defragmenting => {
                 // Pause - for now, just stop
                 // TODO: implement proper pause
@@ -384,13 +657,13 @@ impl Win98GraphicalRenderer {
 
     /// Stop defragmentation
     fn stop_defrag(&mut self, app: &mut App) {
-        app.phase = DefragPhase::Finished;
+        app.engine.stop();
     }
 
     /// Update UI state based on app state
     fn update_ui_state(&mut self, app: &App) {
         // Update window title
-        self.window_widget.title = match app.phase {
+        self.window_widget.title = match app.engine.phase {
             DefragPhase::Defragmenting => {
                 format!("Defragmenting Drive {}", app.current_drive.letter())
             }
@@ -402,14 +675,18 @@ impl Win98GraphicalRenderer {
         };
 
         // Update button text
-        self.start_pause_button.text = match app.phase {
-            DefragPhase::Initializing | DefragPhase::Finished => "Start".to_string(),
+        self.start_pause_button.text = match app.engine.phase {
+            DefragPhase::Initializing | DefragPhase::Analyzed | DefragPhase::Finished => {
+                "Start".to_string()
+            }
             DefragPhase::Analyzing | DefragPhase::Defragmenting => "Pause".to_string(),
         };
 
         // Update stop button state
-        self.stop_button.state = match app.phase {
-            DefragPhase::Initializing | DefragPhase::Finished => ButtonState::Disabled,
+        self.stop_button.state = match app.engine.phase {
+            DefragPhase::Initializing | DefragPhase::Analyzed | DefragPhase::Finished => {
+                ButtonState::Disabled
+            }
             _ => {
                 if self.stop_button.area.contains(self.mouse_x, self.mouse_y) {
                     ButtonState::Hovered
@@ -420,8 +697,8 @@ impl Win98GraphicalRenderer {
         };
 
         // Update progress bar
-        let progress = if app.stats.total_to_defrag > 0 {
-            app.stats.clusters_defragged as f64 / app.stats.total_to_defrag as f64
+        let progress = if app.engine.stats.total_to_defrag > 0 {
+            app.engine.stats.clusters_defragged as f64 / app.engine.stats.total_to_defrag as f64
         } else {
             0.0
         };
@@ -440,7 +717,14 @@ impl Win98GraphicalRenderer {
         // According to requirements, sprites will always be available
         // For now, using colored rectangles to avoid borrowing conflicts with the resource manager
         // The sprites will be properly implemented in a future version that restructures the resource manager
-        for (i, cluster) in app.clusters.iter().enumerate() {
+        // Large disks are drawn with several clusters per cell
+        let per_cell = clusters_per_cell(app.engine.clusters.len(), cols * rows);
+        let cells = app
+            .engine
+            .clusters
+            .chunks(per_cell)
+            .map(ClusterState::summarize);
+        for (i, cluster) in cells.enumerate() {
             let col = i % cols;
             let row = i / cols;
 
@@ -451,7 +735,7 @@ impl Win98GraphicalRenderer {
             let x = inner.x + (col as u32 * (CLUSTER_SIZE + CLUSTER_GAP)) as i32;
             let y = inner.y + (row as u32 * (CLUSTER_SIZE + CLUSTER_GAP)) as i32;
 
-            let win98_state = Win98ClusterState::from(cluster);
+            let win98_state = Win98ClusterState::from(&cluster);
             let color = win98_state.color();
             self.backend
                 .fill_rect(x, y, CLUSTER_SIZE, CLUSTER_SIZE, color);
@@ -493,10 +777,11 @@ impl Win98GraphicalRenderer {
 
     /// Draw progress text
     fn draw_progress_text(&mut self, app: &App) {
-        let progress = if app.stats.total_to_defrag > 0 {
-            (app.stats.clusters_defragged as f64 / app.stats.total_to_defrag as f64 * 100.0) as u32
+        let progress = if app.engine.stats.total_to_defrag > 0 {
+            (app.engine.stats.clusters_defragged as f64 / app.engine.stats.total_to_defrag as f64
+                * 100.0) as u32
         } else {
-            if app.phase == DefragPhase::Finished {
+            if app.engine.phase == DefragPhase::Finished {
                 100
             } else {
                 0
@@ -506,16 +791,20 @@ impl Win98GraphicalRenderer {
         let y = self.progress_bar.area.y - 18;
 
         // Status text on the left
-        let status_text = if let Some(filename) = &app.current_filename {
+        let status_text = if let Some(file) = app.engine.current_file() {
+            // Long paths keep their end, which names the file
             let max_len = 45;
-            let display_name = if filename.len() > max_len {
-                &filename[..max_len]
+            let path = file.display_name();
+            let length = path.chars().count();
+            let display_name = if length > max_len {
+                let tail: String = path.chars().skip(length - (max_len - 3)).collect();
+                format!("...{}", tail)
             } else {
-                filename
+                path.to_string()
             };
             format!("Defragmenting: {}", display_name)
         } else {
-            match app.phase {
+            match app.engine.phase {
                 DefragPhase::Analyzing => "Analyzing drive...".to_string(),
                 DefragPhase::Finished => "Defragmentation is 100% complete.".to_string(),
                 _ => "".to_string(),
@@ -574,6 +863,104 @@ impl Win98GraphicalRenderer {
         );
     }
 
+    /// Draw the settings dialog, if open
+    fn draw_settings_dialog(&mut self, app: &App) {
+        let Some(ref mut dialog) = self.settings_dialog else {
+            return;
+        };
+        dialog.volume_slider.value = app.volume;
+
+        let canvas = &mut self.backend.canvas;
+        dialog.window.draw(canvas, &self.resource_cache);
+        dialog.volume_slider.draw(canvas, &self.resource_cache);
+        for (_, check_box) in &dialog.mute_boxes {
+            check_box.draw(canvas, &self.resource_cache);
+        }
+        dialog.ok_button.draw(canvas, &self.resource_cache);
+
+        let title = dialog.window.title_bar_area();
+        let _ = self.backend.draw_text(
+            &dialog.window.title,
+            title.x + 4,
+            title.y + 2,
+            14,
+            colors::WHITE,
+        );
+
+        let client = dialog.window.client_area();
+        let _ = self
+            .backend
+            .draw_text("Volume:", client.x + 12, client.y + 10, 13, colors::TEXT);
+        let percent = format!("{:.0}%", app.volume * 100.0);
+        if let Ok(width) = self.backend.get_text_width(&percent, 13) {
+            let _ = self.backend.draw_text(
+                &percent,
+                client.x + client.width as i32 - 12 - width as i32,
+                client.y + 10,
+                13,
+                colors::TEXT,
+            );
+        }
+        let _ = self
+            .backend
+            .draw_text("Sounds:", client.x + 12, client.y + 62, 13, colors::TEXT);
+        for (_, check_box) in &dialog.mute_boxes {
+            let _ = self.backend.draw_text(
+                &check_box.label,
+                check_box.area.x + CheckBox::BOX_SIZE as i32 + 6,
+                check_box.area.y - 1,
+                13,
+                colors::TEXT,
+            );
+        }
+        let button = &dialog.ok_button;
+        let _ = self.backend.draw_text_centered(
+            &button.text,
+            button.area.x,
+            button.area.y + 4,
+            button.area.width,
+            13,
+            colors::TEXT,
+        );
+    }
+
+    /// Draw the message box, if shown
+    fn draw_message_box(&mut self) {
+        let Some(ref message_box) = self.message_box else {
+            return;
+        };
+        let canvas = &mut self.backend.canvas;
+        message_box.window.draw(canvas, &self.resource_cache);
+        message_box.ok_button.draw(canvas, &self.resource_cache);
+
+        let title = message_box.window.title_bar_area();
+        let _ = self.backend.draw_text(
+            &message_box.window.title,
+            title.x + 4,
+            title.y + 2,
+            14,
+            colors::WHITE,
+        );
+        let client = message_box.window.client_area();
+        let _ = self.backend.draw_text_centered(
+            &message_box.text,
+            client.x,
+            client.y + 18,
+            client.width,
+            13,
+            colors::TEXT,
+        );
+        let button = &message_box.ok_button;
+        let _ = self.backend.draw_text_centered(
+            &button.text,
+            button.area.x,
+            button.area.y + 4,
+            button.area.width,
+            13,
+            colors::TEXT,
+        );
+    }
+
     /// Draw title bar text
     fn draw_title_text(&mut self) {
         let title_area = self.window_widget.title_bar_area();
@@ -460,3 +460,126 @@ impl SunkenPanel {
         let _ = canvas.draw_line((x + w - 2, y + 1), (x + w - 2, y + h - 2));
     }
 }
+
+/// Win98-style trackbar: a thumb sliding along a sunken groove
+pub struct Slider {
+    pub area: Area,
+    /// Position of the thumb, from 0 (left) to 1 (right)
+    pub value: f32,
+}
+
+impl Slider {
+    const THUMB_WIDTH: u32 = 11;
+
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Self {
+            area: Area::new(x, y, width, height),
+            value: 0.0,
+        }
+    }
+
+    /// Span the thumb center travels along, from its first to its last
+    /// position
+    fn travel(&self) -> (i32, i32) {
+        let half = (Self::THUMB_WIDTH / 2) as i32;
+        (
+            self.area.x + half,
+            self.area.x + self.area.width as i32 - half - 1,
+        )
+    }
+
+    /// Value of the thumb centered on horizontal position `x`
+    pub fn value_at(&self, x: i32) -> f32 {
+        let (start, end) = self.travel();
+        ((x - start) as f32 / (end - start).max(1) as f32).clamp(0.0, 1.0)
+    }
+
+    pub fn thumb_area(&self) -> Area {
+        let (start, end) = self.travel();
+        let center = start + ((end - start) as f32 * self.value.clamp(0.0, 1.0)).round() as i32;
+        Area::new(
+            center - (Self::THUMB_WIDTH / 2) as i32,
+            self.area.y,
+            Self::THUMB_WIDTH,
+            self.area.height,
+        )
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, _resource_cache: &ResourceCache) {
+        let (start, end) = self.travel();
+        let y = self.area.y + self.area.height as i32 / 2 - 2;
+
+        // Groove
+        canvas.set_draw_color(colors::BUTTON_SHADOW);
+        let _ = canvas.draw_line((start, y), (end, y));
+        let _ = canvas.draw_line((start, y), (start, y + 3));
+        canvas.set_draw_color(colors::WINDOW_FRAME);
+        let _ = canvas.draw_line((start + 1, y + 1), (end - 1, y + 1));
+        canvas.set_draw_color(colors::BUTTON_HIGHLIGHT);
+        let _ = canvas.draw_line((start, y + 3), (end, y + 3));
+        let _ = canvas.draw_line((end, y), (end, y + 3));
+
+        // Thumb, a small raised button
+        let thumb = self.thumb_area();
+        let (x, y, w, h) = (thumb.x, thumb.y, thumb.width as i32, thumb.height as i32);
+        canvas.set_draw_color(colors::BUTTON_FACE);
+        let _ = canvas.fill_rect(thumb.to_sdl_rect());
+        canvas.set_draw_color(colors::BUTTON_HIGHLIGHT);
+        let _ = canvas.draw_line((x, y), (x + w - 1, y));
+        let _ = canvas.draw_line((x, y), (x, y + h - 1));
+        canvas.set_draw_color(colors::WINDOW_FRAME);
+        let _ = canvas.draw_line((x, y + h - 1), (x + w - 1, y + h - 1));
+        let _ = canvas.draw_line((x + w - 1, y), (x + w - 1, y + h - 1));
+        canvas.set_draw_color(colors::BUTTON_SHADOW);
+        let _ = canvas.draw_line((x + 1, y + h - 2), (x + w - 2, y + h - 2));
+        let _ = canvas.draw_line((x + w - 2, y + 1), (x + w - 2, y + h - 2));
+    }
+}
+
+/// Win98-style check box; its label is drawn by the caller right of the box
+pub struct CheckBox {
+    /// The box and its label, all of which toggles the box when clicked
+    pub area: Area,
+    pub label: String,
+    pub checked: bool,
+}
+
+impl CheckBox {
+    pub const BOX_SIZE: u32 = 13;
+
+    pub fn new(x: i32, y: i32, width: u32, label: &str) -> Self {
+        Self {
+            area: Area::new(x, y, width, Self::BOX_SIZE),
+            label: label.to_string(),
+            checked: false,
+        }
+    }
+
+    pub fn draw(&self, canvas: &mut Canvas<Window>, _resource_cache: &ResourceCache) {
+        let (x, y) = (self.area.x, self.area.y);
+        let size = Self::BOX_SIZE as i32;
+
+        canvas.set_draw_color(colors::WHITE);
+        let _ = canvas.fill_rect(Rect::new(x, y, Self::BOX_SIZE, Self::BOX_SIZE));
+
+        // Sunken border
+        canvas.set_draw_color(colors::BUTTON_SHADOW);
+        let _ = canvas.draw_line((x, y), (x + size - 1, y));
+        let _ = canvas.draw_line((x, y), (x, y + size - 1));
+        canvas.set_draw_color(colors::WINDOW_FRAME);
+        let _ = canvas.draw_line((x + 1, y + 1), (x + size - 2, y + 1));
+        let _ = canvas.draw_line((x + 1, y + 1), (x + 1, y + size - 2));
+        canvas.set_draw_color(colors::BUTTON_HIGHLIGHT);
+        let _ = canvas.draw_line((x, y + size - 1), (x + size - 1, y + size - 1));
+        let _ = canvas.draw_line((x + size - 1, y), (x + size - 1, y + size - 1));
+
+        // Check mark, three pixels thick
+        if self.checked {
+            canvas.set_draw_color(colors::BLACK);
+            for offset in 0..3 {
+                let _ = canvas.draw_line((x + 3, y + 5 + offset), (x + 5, y + 7 + offset));
+                let _ = canvas.draw_line((x + 5, y + 7 + offset), (x + 9, y + 3 + offset));
+            }
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Disk layout generation
+//!
+//! Builds the initial cluster map together with the files scattered across it,
+//! so the defragmenter has real files to move instead of anonymous clusters.
+
+use crate::constants::files as files_const;
+use crate::dos_files::DosFileProvider;
+use crate::models::{cluster_codes, ClusterState, FileFragment};
+use rand::prelude::{Rng, SliceRandom};
+use serde::{Deserialize, Serialize};
+
+/// A disk's cluster map and the files stored on it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskLayout {
+    #[serde(with = "cluster_codes")]
+    pub clusters: Vec<ClusterState>,
+    pub files: Vec<FileFragment>,
+}
+
+/// A piece of the disk handed out while laying files down
+enum Token {
+    /// Fragment `fragment` of file `file`, `len` clusters long
+    Fragment {
+        file: usize,
+        fragment: usize,
+        len: usize,
+    },
+    /// A run of free clusters
+    Gap(usize),
+}
+
+impl DiskLayout {
+    /// Generates a random, fragmented disk of `total_clusters` clusters
+    ///
+    /// Cluster 0 is the unmovable system area, `bad_ratio` of the clusters are
+    /// bad ones spread over the disk and `fill_percent` of it is filled with
+    /// files, most of them split in several fragments separated by free space.
+    pub fn generate<R: Rng + ?Sized>(
+        total_clusters: usize,
+        fill_percent: f32,
+        bad_ratio: f32,
+        rng: &mut R,
+    ) -> Self {
+        let mut clusters = vec![ClusterState::Unused; total_clusters];
+        if total_clusters == 0 {
+            return Self {
+                clusters,
+                files: Vec::new(),
+            };
+        }
+
+        clusters[0] = ClusterState::Unmovable;
+
+        let num_bad = (total_clusters as f32 * bad_ratio.clamp(0.0, 1.0)) as usize;
+        let mut bad_positions: Vec<usize> = (1..total_clusters).collect();
+        bad_positions.shuffle(rng);
+        for &pos in bad_positions.iter().take(num_bad) {
+            clusters[pos] = ClusterState::Bad;
+        }
+
+        let free_slots: Vec<usize> = (0..total_clusters)
+            .filter(|&i| clusters[i] == ClusterState::Unused)
+            .collect();
+        let data_target =
+            ((total_clusters as f32 * fill_percent.max(0.0)) as usize).min(free_slots.len());
+
+        // Create the files and cut them into fragments
+        let mut file_provider = DosFileProvider::new(rng);
+        let mut names = Vec::new();
+        let mut dates = Vec::new();
+        let mut tokens = Vec::new();
+        let mut allocated = 0;
+        while allocated < data_target {
+            let size = Self::random_file_size(rng).min(data_target - allocated);
+            let file = names.len();
+            names.push(
+                file_provider
+                    .get_random_filename()
+                    .unwrap_or_else(|| format!("~DF{:04X}.TMP", file)),
+            );
+            dates.push(Self::random_timestamp(rng));
+            for (fragment, len) in Self::split_file(size, rng).into_iter().enumerate() {
+                tokens.push(Token::Fragment {
+                    file,
+                    fragment,
+                    len,
+                });
+            }
+            allocated += size;
+        }
+
+        // Spread the free space between the fragments
+        let free = free_slots.len() - data_target;
+        let num_gaps = (tokens.len() / files_const::FRAGMENTS_PER_GAP)
+            .max(1)
+            .min(free);
+        let mut gaps = vec![0; num_gaps];
+        for _ in 0..free {
+            gaps[rng.gen_range(0..num_gaps)] += 1;
+        }
+        tokens.extend(gaps.into_iter().map(Token::Gap));
+        tokens.shuffle(rng);
+
+        // Lay everything down over the usable clusters
+        let mut fragments: Vec<Vec<Vec<usize>>> = vec![Vec::new(); names.len()];
+        let mut slots = free_slots.into_iter();
+        for token in tokens {
+            match token {
+                Token::Fragment {
+                    file,
+                    fragment,
+                    len,
+                } => {
+                    let positions: Vec<usize> = slots.by_ref().take(len).collect();
+                    for &pos in &positions {
+                        clusters[pos] = ClusterState::Pending;
+                    }
+                    let pieces = &mut fragments[file];
+                    if pieces.len() <= fragment {
+                        pieces.resize(fragment + 1, Vec::new());
+                    }
+                    pieces[fragment] = positions;
+                }
+                Token::Gap(len) => {
+                    slots.by_ref().take(len).for_each(drop);
+                }
+            }
+        }
+
+        let files = names
+            .into_iter()
+            .zip(fragments)
+            .zip(dates)
+            .map(|((name, pieces), modified)| {
+                let mut file = FileFragment::new(name, pieces.concat());
+                file.modified = modified;
+                file
+            })
+            .collect();
+
+        Self { clusters, files }
+    }
+
+    /// Picks a file size in clusters, favouring small files
+    fn random_file_size<R: Rng + ?Sized>(rng: &mut R) -> usize {
+        if rng.gen_bool(files_const::SMALL_FILE_RATIO) {
+            rng.gen_range(1..=files_const::SMALL_FILE_CLUSTERS)
+        } else {
+            rng.gen_range(files_const::SMALL_FILE_CLUSTERS + 1..=files_const::MAX_FILE_CLUSTERS)
+        }
+    }
+
+    /// Picks a plausible MS-DOS era modification time
+    fn random_timestamp<R: Rng + ?Sized>(rng: &mut R) -> u32 {
+        let (first_year, last_year) = files_const::MODIFIED_YEARS;
+        FileFragment::dos_timestamp(
+            rng.gen_range(first_year..=last_year),
+            rng.gen_range(1..=12),
+            rng.gen_range(1..=28),
+            rng.gen_range(0..24),
+            rng.gen_range(0..60),
+            rng.gen_range(0..60),
+        )
+    }
+
+    /// Cuts a file of `size` clusters into fragment lengths
+    fn split_file<R: Rng + ?Sized>(size: usize, rng: &mut R) -> Vec<usize> {
+        if size < 2 || !rng.gen_bool(files_const::FRAGMENTED_FILE_RATIO) {
+            return vec![size];
+        }
+
+        let count = rng.gen_range(2..=size.min(files_const::MAX_FRAGMENTS));
+        let mut cuts: Vec<usize> = rand::seq::index::sample(rng, size - 1, count - 1)
+            .into_iter()
+            .map(|cut| cut + 1)
+            .collect();
+        cuts.sort_unstable();
+        cuts.push(size);
+
+        let mut previous = 0;
+        cuts.into_iter()
+            .map(|cut| {
+                let len = cut - previous;
+                previous = cut;
+                len
+            })
+            .collect()
+    }
+
+    /// Total number of clusters held by files
+    pub fn file_clusters(&self) -> usize {
+        self.files.iter().map(|file| file.size).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SimRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_generated_files_cover_data_clusters() {
+        let layout = DiskLayout::generate(78 * 16, 0.65, 0.02, &mut SimRng::seed_from_u64(7));
+
+        let mut owned = vec![false; layout.clusters.len()];
+        for file in &layout.files {
+            assert_eq!(file.size, file.clusters.len());
+            for &cluster in &file.clusters {
+                assert!(!owned[cluster], "cluster {} owned twice", cluster);
+                owned[cluster] = true;
+            }
+        }
+
+        for (i, state) in layout.clusters.iter().enumerate() {
+            assert_eq!(owned[i], *state == ClusterState::Pending);
+        }
+        assert_eq!(layout.clusters[0], ClusterState::Unmovable);
+        assert!(layout.files.iter().any(|file| file.is_fragmented));
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_layout() {
+        let first = DiskLayout::generate(78 * 16, 0.65, 0.02, &mut SimRng::seed_from_u64(42));
+        let second = DiskLayout::generate(78 * 16, 0.65, 0.02, &mut SimRng::seed_from_u64(42));
+
+        assert_eq!(first.clusters, second.clusters);
+        let names = |layout: &DiskLayout| -> Vec<(String, Vec<usize>)> {
+            layout
+                .files
+                .iter()
+                .map(|file| (file.name.clone(), file.clusters.clone()))
+                .collect()
+        };
+        assert_eq!(names(&first), names(&second));
+    }
+}
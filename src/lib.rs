@@ -1,7 +1,15 @@
 pub mod app;
 pub mod audio;
+pub mod clock;
+pub mod config;
 pub mod constants;
 pub mod dos_files;
+pub mod engine;
+pub mod export;
+pub mod fat;
 pub mod graphics;
+pub mod layout;
 pub mod models;
+pub mod planner;
+pub mod scan;
 pub mod ui;
@@ -1,34 +1,265 @@
 use clap::Parser;
-use defrag_simulator_rs::{app, ui};
+use defrag_simulator_rs::{app, audio, config, constants::disk, export, models::DefragPhase, ui};
 use std::io::Result;
+use std::path::{Path, PathBuf};
 
 #[cfg(feature = "graphical")]
 use defrag_simulator_rs::{constants::defrag_type::DefragStyle, graphics};
 
-#[cfg(not(feature = "graphical"))]
-use defrag_simulator_rs::constants::defrag_type::DefragStyle;
+/// Loads the `--image` disk image or `--scan` directory, if any, exiting
+/// with an error when it cannot be read
+fn load_image(app: &mut app::App, args: &app::Args) {
+    if let Some(ref path) = args.image {
+        if let Err(e) = app.load_image(path) {
+            eprintln!("Cannot read disk image {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(ref path) = args.scan {
+        if let Err(e) = app.load_scan(path) {
+            eprintln!("Cannot scan {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads the drive collection and picks the `--drive` one, exiting with an
+/// error when the drives file is invalid or the letter unknown
+///
+/// The last value tells whether every drive should be defragmented in turn.
+fn select_drive(args: &app::Args) -> (app::DiskDriveCollection, app::DiskDrive, bool) {
+    let drives = app::DiskDriveCollection::load(args.drives.as_deref()).unwrap_or_else(|e| {
+        eprintln!("Cannot read drives file: {}", e);
+        std::process::exit(1);
+    });
+
+    if args.drive.eq_ignore_ascii_case("all") {
+        if args.image.is_some() || args.scan.is_some() {
+            eprintln!("Invalid --drive: all cannot be combined with --image or --scan");
+            std::process::exit(2);
+        }
+        let first = drives.get_default().clone();
+        return (drives, first, true);
+    }
+
+    let mut chars = args.drive.trim_end_matches(':').chars();
+    let selected = match (chars.next(), chars.next()) {
+        (Some(letter), None) => drives.select(letter).cloned(),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "expected a drive letter or \"all\"",
+        )),
+    };
+    let drive = selected.unwrap_or_else(|e| {
+        eprintln!("Invalid --drive: {}", e);
+        std::process::exit(2);
+    });
+    (drives, drive, false)
+}
+
+/// Prints the per-drive results of an "All drives" run
+fn print_drive_summary(app: &app::App) {
+    let Some(ref sequence) = app.drive_sequence else {
+        return;
+    };
+    println!("Drive  Time      Fragmented files before  after");
+    for report in &sequence.reports {
+        println!(
+            "{}:     {}  {:>22.0}%  {:>4.0}%",
+            report.letter,
+            ui::format_time(report.elapsed),
+            report.fragmented_before,
+            report.fragmented_after
+        );
+    }
+}
+
+/// Writes the `--output` image, if any, once the run is over
+fn write_output(app: &app::App, args: &app::Args) {
+    if let Some(ref path) = args.output {
+        match app.write_image(path) {
+            Ok(()) if app.engine.phase == DefragPhase::Finished => {
+                println!("Defragmented image written to {}", path);
+            }
+            Ok(()) => println!(
+                "Defragmentation interrupted, partly defragmented image written to {}",
+                path
+            ),
+            Err(e) => {
+                eprintln!("Cannot write defragmented image {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// Writes the `--save-map` disk map, if any, once the run is over
+fn save_map(app: &app::App, args: &app::Args) {
+    if let Some(ref path) = args.save_map {
+        match export::map::save_map(app, Path::new(path)) {
+            Ok(format) => println!("Disk map written to {} ({})", path, format.name()),
+            Err(e) => {
+                eprintln!("Cannot write disk map {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+/// File the run is resumed from and saved to: the `--resume` one, or
+/// `state.json` in the configuration directory
+fn session_path(args: &app::Args) -> Option<PathBuf> {
+    match args.resume {
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => config::config_dir().map(|dir| dir.join(disk::SESSION_FILE)),
+    }
+}
+
+/// Saves a run left with Esc before it finished, so `--resume` continues it
+fn save_session(app: &app::App, args: &app::Args) {
+    if !app.interrupted || app.engine.phase == DefragPhase::Finished {
+        return;
+    }
+    // Quitting stays a normal exit when there is nowhere to save the run
+    let Some(path) = session_path(args) else {
+        eprintln!("Run not saved: no configuration directory, use --resume FILE");
+        return;
+    };
+    match app.save_session(&path) {
+        Ok(()) => println!(
+            "Run saved to {}, continue it with --resume {}",
+            path.display(),
+            path.display()
+        ),
+        Err(e) => {
+            eprintln!("Cannot save the run to {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Creates the app the command line describes
+fn create_app(args: &app::Args, sound: bool) -> app::App {
+    let (width, height) = app::parse_size(&args.size).unwrap_or((78, 16));
+    let (drives, drive, all_drives) = select_drive(args);
+    // A scan is projected onto the --size grid, a drive fills its clusters
+    let height = if args.scan.is_some() {
+        height
+    } else {
+        drive.grid_height(width)
+    };
+    let fill = args.fill.unwrap_or(drive.config.fill_ratio);
+    let mut app = app::App::new(
+        width,
+        height,
+        fill,
+        sound,
+        drive,
+        args.get_ui_style(),
+        args.seed,
+    );
+    app.drive_collection = drives;
+    app.fill_override = args.fill;
+    app.set_volume(args.get_volume());
+    app.set_audio_settings(audio::AudioSettings::load().unwrap_or_else(|e| {
+        eprintln!(
+            "Warning: cannot read audio settings, using the defaults: {}",
+            e
+        );
+        audio::AudioSettings::default()
+    }));
+    if all_drives {
+        app.start_all_drives();
+    }
+    app.engine.method = args.get_method();
+    app.engine.sort = args.get_sort();
+    app.print_dir = args.print_dir.clone().into();
+    app.print_format = args.get_print_format();
+    app.snapshot_dir = args.snapshot_dir.clone().into();
+    app.snapshot_palette = args.get_snapshot_palette();
+    app.snapshot_every = args.snapshot_every;
+    load_image(&mut app, args);
+    // A file that does not exist yet starts a new run, saved there by Esc
+    if let Some(path) = args.resume.as_ref().filter(|path| Path::new(path).exists()) {
+        if let Err(e) = app.resume(Path::new(path)) {
+            eprintln!("Cannot resume {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    app
+}
+
+/// Runs the whole simulation without a screen for `--record`
+fn record(args: &app::Args, path: &str) {
+    let mut app = create_app(args, false);
+    let options = export::record::RecordOptions {
+        every: args.record_every,
+        palette: args.get_snapshot_palette(),
+        downscale: args.record_downscale,
+    };
+    match export::record::record(&mut app, Path::new(path), &options) {
+        Ok(frames) => println!("Recorded {} frames to {}", frames, path),
+        Err(e) => {
+            eprintln!("Cannot record {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    write_output(&app, args);
+    save_map(&app, args);
+    print_drive_summary(&app);
+}
+
+/// Runs the whole simulation without a screen for `--cast`
+fn cast(args: &app::Args, path: &str) {
+    let mut app = create_app(args, false);
+    match export::cast::cast(&mut app, Path::new(path)) {
+        Ok(frames) => println!("Recorded {} terminal frames to {}", frames, path),
+        Err(e) => {
+            eprintln!("Cannot record {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    write_output(&app, args);
+    save_map(&app, args);
+    print_drive_summary(&app);
+}
 
 fn main() -> Result<()> {
     let args = app::Args::parse();
-    let (width, height) = app::parse_size(&args.size).unwrap_or((78, 16));
-    let ui_style = args.get_ui_style();
+
+    if let Some(ref path) = args.record {
+        record(&args, path);
+        return Ok(());
+    }
+    if let Some(ref path) = args.cast {
+        cast(&args, path);
+        return Ok(());
+    }
 
     // Check if we should use graphical mode for Win98/Win95
     #[cfg(feature = "graphical")]
-    if matches!(ui_style, DefragStyle::Windows98 | DefragStyle::Windows95) {
+    if matches!(
+        args.get_ui_style(),
+        DefragStyle::Windows98 | DefragStyle::Windows95
+    ) {
         // Run graphical mode (required for Win98/Win95)
-        let mut app = app::App::new(width, height, args.fill, args.sound, args.drive, ui_style);
+        let mut app = create_app(&args, args.sound);
 
         if let Err(e) = graphics::win98_renderer::run_win98_graphical(&mut app) {
             eprintln!("Graphical mode failed: {}", e);
             std::process::exit(1); // Exit with error as Win98/Win95 requires graphical mode
         } else {
+            write_output(&app, &args);
+            save_map(&app, &args);
+            save_session(&app, &args);
+            print_drive_summary(&app);
             return Ok(());
         }
     }
 
     // Terminal mode (MS-DOS style)
-    use defrag_simulator_rs::ui;
+    // Create app with selected UI style
+    let mut app = create_app(&args, args.sound);
 
     // Setup terminal
     let mut tui = ui::TuiWrapper::new()?;
@@ -40,11 +271,13 @@ fn main() -> Result<()> {
     })
     .expect("Error setting Ctrl-C handler");
 
-    // Create and run app with selected UI style
-    let mut app = app::App::new(width, height, args.fill, args.sound, args.drive, ui_style);
     app.run(&mut tui, rx)?;
 
     // Restore terminal
     tui.cleanup()?;
+    write_output(&app, &args);
+    save_map(&app, &args);
+    save_session(&app, &args);
+    print_drive_summary(&app);
     Ok(())
 }
@@ -1,4 +1,13 @@
-use std::time::Instant;
+use crate::constants::analysis;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::Duration;
+
+/// Random number generator driving the simulation
+///
+/// A seedable generator with a fixed algorithm, so a given seed always
+/// produces the same run.
+pub type SimRng = rand_chacha::ChaCha8Rng;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ClusterState {
@@ -11,17 +20,323 @@ pub enum ClusterState {
     Writing,   // Block being written
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+impl ClusterState {
+    /// State shown for a block of clusters drawn as a single cell
+    ///
+    /// Disk activity wins over data, data over bad or system clusters, and a
+    /// block only shows as free when all of its clusters are.
+    pub fn summarize(block: &[ClusterState]) -> ClusterState {
+        const PRIORITY: [ClusterState; 7] = [
+            ClusterState::Reading,
+            ClusterState::Writing,
+            ClusterState::Pending,
+            ClusterState::Used,
+            ClusterState::Bad,
+            ClusterState::Unmovable,
+            ClusterState::Unused,
+        ];
+        PRIORITY
+            .into_iter()
+            .find(|state| block.contains(state))
+            .unwrap_or(ClusterState::Unused)
+    }
+}
+
+/// Saves a cluster map as a string of one character per cluster
+pub(crate) mod cluster_codes {
+    use super::ClusterState;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const CODES: [(ClusterState, char); 7] = [
+        (ClusterState::Used, 'U'),
+        (ClusterState::Unused, '.'),
+        (ClusterState::Pending, 'P'),
+        (ClusterState::Bad, 'B'),
+        (ClusterState::Unmovable, 'X'),
+        (ClusterState::Reading, 'r'),
+        (ClusterState::Writing, 'w'),
+    ];
+
+    pub fn serialize<S: Serializer>(
+        clusters: &[ClusterState],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let codes: String = clusters
+            .iter()
+            .map(|&cluster| {
+                CODES
+                    .iter()
+                    .find(|(state, _)| *state == cluster)
+                    .map_or('.', |&(_, code)| code)
+            })
+            .collect();
+        serializer.serialize_str(&codes)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<ClusterState>, D::Error> {
+        String::deserialize(deserializer)?
+            .chars()
+            .map(|code| {
+                CODES
+                    .iter()
+                    .find(|&&(_, c)| c == code)
+                    .map(|&(state, _)| state)
+                    .ok_or_else(|| D::Error::custom(format!("unknown cluster `{}`", code)))
+            })
+            .collect()
+    }
+}
+
+/// Number of clusters each cell stands for when `clusters` are drawn in `cells`
+pub fn clusters_per_cell(clusters: usize, cells: usize) -> usize {
+    if cells == 0 {
+        return 1;
+    }
+    clusters.div_ceil(cells).max(1)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DefragPhase {
     Initializing,
     Analyzing,
+    /// Analysis done, waiting for the user to proceed
+    Analyzed,
     Defragmenting,
     Finished,
 }
 
-#[derive(Clone)]
+/// Defragmentation strategies offered by MS-DOS 6.22 DEFRAG
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum OptimizationMethod {
+    /// Make every file contiguous and pack them at the start of the disk
+    #[default]
+    FullOptimization,
+    /// Only make fragmented files contiguous, leaving free space holes
+    UnfragmentFilesOnly,
+}
+
+impl OptimizationMethod {
+    /// All methods, in the order they are offered in the dialog
+    pub const ALL: [OptimizationMethod; 2] = [
+        OptimizationMethod::FullOptimization,
+        OptimizationMethod::UnfragmentFilesOnly,
+    ];
+
+    /// Returns the display name of the method
+    pub fn name(&self) -> &'static str {
+        match self {
+            OptimizationMethod::FullOptimization => "Full Optimization",
+            OptimizationMethod::UnfragmentFilesOnly => "Unfragment Files Only",
+        }
+    }
+}
+
+/// File attribute the defragmenter orders files by
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum SortKey {
+    /// Keep files in the order they appear on disk
+    #[default]
+    Unsorted,
+    Name,
+    Extension,
+    Date,
+    Size,
+}
+
+/// Direction of the file sort
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub enum SortOrder {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// File sort criterion applied to the final layout of a defragmentation pass
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+pub struct FileSort {
+    pub key: SortKey,
+    pub order: SortOrder,
+}
+
+impl FileSort {
+    pub fn new(key: SortKey, order: SortOrder) -> Self {
+        Self { key, order }
+    }
+
+    /// Compares two files according to the sort key and order
+    ///
+    /// Files that compare equal (and every file when unsorted) keep their
+    /// current order on disk.
+    pub fn compare(&self, a: &FileFragment, b: &FileFragment) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Unsorted => Ordering::Equal,
+            SortKey::Name => a.file_name().cmp(b.file_name()),
+            SortKey::Extension => a
+                .extension()
+                .cmp(b.extension())
+                .then_with(|| a.file_name().cmp(b.file_name())),
+            SortKey::Date => a.modified.cmp(&b.modified),
+            SortKey::Size => a.size.cmp(&b.size),
+        };
+        let ordering = match self.order {
+            SortOrder::Ascending => ordering,
+            SortOrder::Descending => ordering.reverse(),
+        };
+        ordering.then_with(|| a.first_cluster().cmp(&b.first_cluster()))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct DefragStats {
     pub total_to_defrag: usize,    // Total number of clusters to defragment
     pub clusters_defragged: usize, // Number of defragmented clusters
-    pub start_time: Instant,
+    pub start_time: Duration,      // Simulation time at which the run started
+}
+
+/// Findings of the analysis pass
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct AnalysisReport {
+    pub total_files: usize,
+    pub fragmented_files: usize,
+    pub free_clusters: usize,
+    /// Number of separate runs the free space is split into
+    pub free_runs: usize,
+    pub largest_free_run: usize,
+    pub bad_clusters: usize,
+}
+
+impl AnalysisReport {
+    /// Share of the files stored in several fragments, 0 to 100
+    pub fn fragmented_files_percent(&self) -> f32 {
+        if self.total_files == 0 {
+            return 0.0;
+        }
+        self.fragmented_files as f32 * 100.0 / self.total_files as f32
+    }
+
+    pub fn is_defrag_recommended(&self) -> bool {
+        self.fragmented_files_percent() >= analysis::RECOMMEND_DEFRAG_PERCENT
+    }
+
+    /// The classic DEFRAG verdict
+    pub fn verdict(&self) -> String {
+        format!(
+            "This drive is {:.0}% fragmented. Defragmentation is {}recommended.",
+            self.fragmented_files_percent(),
+            if self.is_defrag_recommended() {
+                ""
+            } else {
+                "not "
+            }
+        )
+    }
+}
+
+/// Represents a logical file with multiple clusters
+/// This allows simulating files of different sizes during defragmentation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileFragment {
+    /// DOS path of the file (e.g. `DOOM\DOOM.WAD`)
+    pub name: String,
+    /// Path with long (VFAT) file names, when the file has one
+    pub long_name: Option<String>,
+    /// The cluster indices that belong to this file, in logical order
+    pub clusters: Vec<usize>,
+    /// The size of the file in clusters
+    pub size: usize,
+    /// Whether this file is fragmented (clusters not contiguous)
+    pub is_fragmented: bool,
+    /// Last modification time, packed as an MS-DOS date (high word) and time
+    /// (low word) so that it orders chronologically
+    pub modified: u32,
+}
+
+impl FileFragment {
+    /// Create a new file fragment
+    pub fn new(name: impl Into<String>, clusters: Vec<usize>) -> Self {
+        let size = clusters.len();
+        let is_fragmented = Self::check_fragmentation(&clusters);
+        Self {
+            name: name.into(),
+            long_name: None,
+            clusters,
+            size,
+            is_fragmented,
+            modified: 0,
+        }
+    }
+
+    /// Packs a date and time the way MS-DOS stores them in directory entries
+    pub fn dos_timestamp(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> u32 {
+        let date = (year.saturating_sub(1980) << 9) | ((month as u16) << 5) | day as u16;
+        let time = ((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2);
+        ((date as u32) << 16) | time as u32
+    }
+
+    /// Long path of the file when it has one, its DOS path otherwise
+    pub fn display_name(&self) -> &str {
+        self.long_name.as_deref().unwrap_or(&self.name)
+    }
+
+    /// File name without its directory
+    pub fn file_name(&self) -> &str {
+        self.name.rsplit(['\\', '/']).next().unwrap_or(&self.name)
+    }
+
+    /// File extension, empty when the file has none
+    pub fn extension(&self) -> &str {
+        self.file_name()
+            .rsplit_once('.')
+            .map_or("", |(_, extension)| extension)
+    }
+
+    /// Number of runs of consecutive clusters the file is stored in
+    pub fn fragment_count(&self) -> usize {
+        if self.clusters.is_empty() {
+            return 0;
+        }
+        1 + self
+            .clusters
+            .windows(2)
+            .filter(|window| window[1] != window[0] + 1)
+            .count()
+    }
+
+    /// Check if clusters are contiguous (not fragmented)
+    fn check_fragmentation(clusters: &[usize]) -> bool {
+        if clusters.len() <= 1 {
+            return false;
+        }
+        for window in clusters.windows(2) {
+            if window[1] != window[0] + 1 {
+                return true; // Not contiguous = fragmented
+            }
+        }
+        false
+    }
+
+    /// Moves the cluster at logical position `index` to the physical cluster `to`
+    pub fn relocate(&mut self, index: usize, to: usize) {
+        self.clusters[index] = to;
+        self.update_fragmentation();
+    }
+
+    /// Recomputes [`FileFragment::is_fragmented`] from the cluster list
+    pub fn update_fragmentation(&mut self) {
+        self.is_fragmented = Self::check_fragmentation(&self.clusters);
+    }
+
+    /// Get the first cluster of this file
+    pub fn first_cluster(&self) -> Option<usize> {
+        self.clusters.first().copied()
+    }
+
+    /// Get the last cluster of this file
+    pub fn last_cluster(&self) -> Option<usize> {
+        self.clusters.last().copied()
+    }
 }
@@ -0,0 +1,452 @@
+//! Defragmentation planning
+//!
+//! Turns the cluster map and the file list into the ordered list of moves the
+//! defragmenter performs. Planning works on its own copy of the layout, the
+//! caller applies the moves one by one while animating them.
+
+use crate::models::{ClusterState, FileFragment, FileSort, OptimizationMethod};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// One read/write operation: clusters of a single file moved to a new place
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClusterMove {
+    /// Index of the moved file in the file list
+    pub file: usize,
+    /// Source clusters, in the file's logical order
+    pub from: Vec<usize>,
+    /// Destination clusters, one per source cluster
+    pub to: Vec<usize>,
+    /// Whether the file sits at its final place once this move is done
+    pub completes_file: bool,
+}
+
+/// Working copy of the disk used while planning
+struct Planner<'a> {
+    clusters: &'a [ClusterState],
+    /// (file, logical index) stored in each cluster
+    owner: Vec<Option<(usize, usize)>>,
+    /// Current cluster list of each file
+    files: Vec<Vec<usize>>,
+    /// Free clusters that can receive data
+    free: BTreeSet<usize>,
+    moves: Vec<ClusterMove>,
+}
+
+impl<'a> Planner<'a> {
+    fn new(clusters: &'a [ClusterState], files: &[FileFragment]) -> Self {
+        let mut owner = vec![None; clusters.len()];
+        for (file, fragment) in files.iter().enumerate() {
+            for (index, &cluster) in fragment.clusters.iter().enumerate() {
+                owner[cluster] = Some((file, index));
+            }
+        }
+
+        let free = (0..clusters.len())
+            .filter(|&i| Self::is_movable(clusters[i]) && owner[i].is_none())
+            .collect();
+
+        Self {
+            clusters,
+            owner,
+            files: files.iter().map(|file| file.clusters.clone()).collect(),
+            free,
+            moves: Vec::new(),
+        }
+    }
+
+    /// Clusters that can hold file data (everything but bad and system clusters)
+    fn is_movable(state: ClusterState) -> bool {
+        !matches!(state, ClusterState::Bad | ClusterState::Unmovable)
+    }
+
+    /// First position at or after `from` with `size` movable clusters in a row
+    fn find_destination(&self, from: usize, size: usize) -> Option<usize> {
+        let mut run = 0;
+        for i in from..self.clusters.len() {
+            if Self::is_movable(self.clusters[i]) {
+                run += 1;
+                if run == size {
+                    return Some(i + 1 - size);
+                }
+            } else {
+                run = 0;
+            }
+        }
+        None
+    }
+
+    /// First run of `size` free clusters in a row
+    fn find_free_run(&self, size: usize) -> Option<usize> {
+        let mut start = None;
+        let mut run = 0;
+        for &cluster in &self.free {
+            if start.is_some_and(|s| cluster == s + run) {
+                run += 1;
+            } else {
+                start = Some(cluster);
+                run = 1;
+            }
+            if run == size {
+                return start;
+            }
+        }
+        None
+    }
+
+    /// Records a move and updates ownership of the clusters involved
+    fn apply(&mut self, file: usize, indices: &[usize], to: Vec<usize>) {
+        let from: Vec<usize> = indices.iter().map(|&i| self.files[file][i]).collect();
+
+        for &cluster in &from {
+            self.owner[cluster] = None;
+            self.free.insert(cluster);
+        }
+        for (&index, &cluster) in indices.iter().zip(&to) {
+            self.owner[cluster] = Some((file, index));
+            self.free.remove(&cluster);
+            self.files[file][index] = cluster;
+        }
+
+        self.moves.push(ClusterMove {
+            file,
+            from,
+            to,
+            completes_file: false,
+        });
+    }
+
+    /// Takes back the moves recorded after the first `count`, newest first
+    fn rollback(&mut self, count: usize) {
+        while self.moves.len() > count {
+            let Some(step) = self.moves.pop() else {
+                break;
+            };
+            // Sources and destinations of a move may overlap: empty every
+            // destination before filling the sources again
+            let owners: Vec<Option<(usize, usize)>> = step
+                .to
+                .iter()
+                .map(|&cluster| self.owner[cluster].take())
+                .collect();
+            self.free.extend(step.to.iter().copied());
+            for (&cluster, owner) in step.from.iter().zip(owners) {
+                if let Some((file, index)) = owner {
+                    self.owner[cluster] = Some((file, index));
+                    self.free.remove(&cluster);
+                    self.files[file][index] = cluster;
+                }
+            }
+        }
+    }
+
+    /// Moves whatever occupies `targets` (except `keep`) past `after`
+    ///
+    /// Returns false when there is not enough free space left.
+    fn evict(&mut self, targets: &[usize], keep: &[usize], after: usize) -> bool {
+        let occupied: Vec<(usize, usize)> = targets
+            .iter()
+            .filter(|cluster| !keep.contains(cluster))
+            .filter_map(|&cluster| self.owner[cluster])
+            .collect();
+
+        // Only walk as many free clusters as are needed, not all of them
+        if let Some(last) = occupied.len().checked_sub(1) {
+            if self.free.range(after..).nth(last).is_none() {
+                return false;
+            }
+        }
+
+        // Group consecutive clusters of the same file into a single move
+        let mut start = 0;
+        while start < occupied.len() {
+            let file = occupied[start].0;
+            let mut end = start + 1;
+            while end < occupied.len()
+                && occupied[end].0 == file
+                && occupied[end].1 == occupied[end - 1].1 + 1
+            {
+                end += 1;
+            }
+
+            let indices: Vec<usize> = occupied[start..end].iter().map(|&(_, i)| i).collect();
+            let to: Vec<usize> = self
+                .free
+                .range(after..)
+                .take(indices.len())
+                .copied()
+                .collect();
+            self.apply(file, &indices, to);
+            start = end;
+        }
+        true
+    }
+
+    /// Makes `file` contiguous starting at `dest`, one extent at a time
+    ///
+    /// Returns false if the file could not be completely placed, leaving the
+    /// plan as it was.
+    fn place_file(&mut self, file: usize, dest: usize) -> bool {
+        let size = self.files[file].len();
+        let dest_end = dest + size;
+        let first_move = self.moves.len();
+
+        let mut index = 0;
+        while index < size {
+            let source = self.files[file][index];
+            let target = dest + index;
+            if source == target {
+                index += 1;
+                continue;
+            }
+
+            // Extend the extent while the source clusters stay contiguous
+            let mut len = 1;
+            while index + len < size && self.files[file][index + len] == source + len {
+                len += 1;
+            }
+
+            let from: Vec<usize> = (source..source + len).collect();
+            let to: Vec<usize> = (target..target + len).collect();
+            if !self.evict(&to, &from, dest_end) {
+                self.rollback(first_move);
+                return false;
+            }
+
+            let indices: Vec<usize> = (index..index + len).collect();
+            self.apply(file, &indices, to);
+            index += len;
+        }
+
+        match self.moves[first_move..]
+            .iter_mut()
+            .rev()
+            .find(|m| m.file == file)
+        {
+            Some(last) => last.completes_file = true,
+            None => self.moves.push(ClusterMove {
+                file,
+                from: Vec::new(),
+                to: Vec::new(),
+                completes_file: true,
+            }),
+        }
+        true
+    }
+}
+
+/// Plans a defragmentation pass with the given method
+pub fn plan(
+    method: OptimizationMethod,
+    sort: FileSort,
+    clusters: &[ClusterState],
+    files: &[FileFragment],
+) -> Vec<ClusterMove> {
+    match method {
+        OptimizationMethod::FullOptimization => plan_full_optimization(clusters, files, sort),
+        OptimizationMethod::UnfragmentFilesOnly => plan_unfragment_only(clusters, files, sort),
+    }
+}
+
+/// Files holding data, in the order they are visited
+fn visit_order(files: &[FileFragment], sort: FileSort) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..files.len())
+        .filter(|&i| !files[i].clusters.is_empty())
+        .collect();
+    order.sort_by(|&a, &b| sort.compare(&files[a], &files[b]));
+    order
+}
+
+/// Plans a full optimization: every file made contiguous and packed at the
+/// start of the disk, in `sort` order
+pub fn plan_full_optimization(
+    clusters: &[ClusterState],
+    files: &[FileFragment],
+    sort: FileSort,
+) -> Vec<ClusterMove> {
+    let mut planner = Planner::new(clusters, files);
+    let mut cursor = 0;
+    for file in visit_order(files, sort) {
+        let size = planner.files[file].len();
+        let Some(dest) = planner.find_destination(cursor, size) else {
+            continue;
+        };
+        if planner.place_file(file, dest) {
+            cursor = dest + size;
+        }
+    }
+    planner.moves
+}
+
+/// Plans an "unfragment files only" pass: each fragmented file is moved as a
+/// whole to the first free region large enough to hold it
+///
+/// Files are visited in `sort` order, which decides who gets the free
+/// regions first.
+/// Files that are already contiguous stay where they are and free space is
+/// left wherever it ends up, so holes remain between files. A file with no
+/// free region large enough is left fragmented.
+pub fn plan_unfragment_only(
+    clusters: &[ClusterState],
+    files: &[FileFragment],
+    sort: FileSort,
+) -> Vec<ClusterMove> {
+    let mut planner = Planner::new(clusters, files);
+    for file in visit_order(files, sort) {
+        if !files[file].is_fragmented {
+            planner.place_file(file, files[file].first_cluster().unwrap_or(0));
+            continue;
+        }
+
+        let size = planner.files[file].len();
+        if let Some(dest) = planner.find_free_run(size) {
+            planner.place_file(file, dest);
+        }
+    }
+    planner.moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::DiskLayout;
+    use crate::models::{SimRng, SortKey, SortOrder};
+    use rand::SeedableRng;
+
+    /// Replays `moves` on a copy of `files`, checking every write lands on free space
+    fn replay(
+        clusters: &[ClusterState],
+        files: &[FileFragment],
+        moves: &[ClusterMove],
+    ) -> Vec<FileFragment> {
+        let mut files = files.to_vec();
+        let mut used = vec![false; clusters.len()];
+        for file in &files {
+            for &cluster in &file.clusters {
+                used[cluster] = true;
+            }
+        }
+
+        for m in moves {
+            assert_eq!(m.from.len(), m.to.len());
+            let indices: Vec<usize> = m
+                .from
+                .iter()
+                .map(|c| files[m.file].clusters.iter().position(|x| x == c).unwrap())
+                .collect();
+            for &cluster in &m.from {
+                used[cluster] = false;
+            }
+            for (&index, &cluster) in indices.iter().zip(&m.to) {
+                assert!(!used[cluster], "cluster {} written twice", cluster);
+                assert!(Planner::is_movable(clusters[cluster]));
+                used[cluster] = true;
+                files[m.file].relocate(index, cluster);
+            }
+        }
+        files
+    }
+
+    #[test]
+    fn test_full_optimization_packs_contiguous_files() {
+        let layout = DiskLayout::generate(78 * 16, 0.65, 0.02, &mut SimRng::seed_from_u64(1));
+        let moves = plan_full_optimization(&layout.clusters, &layout.files, FileSort::default());
+        let files = replay(&layout.clusters, &layout.files, &moves);
+
+        let completed = moves.iter().filter(|m| m.completes_file).count();
+        assert_eq!(completed, files.len());
+        for file in &files {
+            assert!(!file.is_fragmented, "{} is still fragmented", file.name);
+        }
+
+        // Files end up packed in the order they were visited
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        order.sort_by_key(|&i| layout.files[i].first_cluster());
+        for pair in order.windows(2) {
+            assert!(files[pair[0]].last_cluster() < files[pair[1]].first_cluster());
+        }
+    }
+
+    #[test]
+    fn test_full_optimization_follows_sort_order() {
+        let layout = DiskLayout::generate(78 * 16, 0.5, 0.02, &mut SimRng::seed_from_u64(2));
+        for key in [
+            SortKey::Name,
+            SortKey::Extension,
+            SortKey::Date,
+            SortKey::Size,
+        ] {
+            for order in [SortOrder::Ascending, SortOrder::Descending] {
+                let sort = FileSort::new(key, order);
+                let moves = plan_full_optimization(&layout.clusters, &layout.files, sort);
+                let mut files = replay(&layout.clusters, &layout.files, &moves);
+
+                // Reading the disk front to back gives the files in sort order
+                files.sort_by_key(|file| file.first_cluster());
+                for pair in files.windows(2) {
+                    assert_ne!(
+                        sort.compare(&pair[0], &pair[1]),
+                        std::cmp::Ordering::Greater,
+                        "{:?} {:?}: {} placed before {}",
+                        key,
+                        order,
+                        pair[0].name,
+                        pair[1].name
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unfragment_only_leaves_contiguous_files_in_place() {
+        let layout = DiskLayout::generate(78 * 16, 0.5, 0.02, &mut SimRng::seed_from_u64(3));
+        let moves = plan_unfragment_only(&layout.clusters, &layout.files, FileSort::default());
+        let files = replay(&layout.clusters, &layout.files, &moves);
+
+        for (before, after) in layout.files.iter().zip(&files) {
+            if !before.is_fragmented {
+                assert_eq!(before.clusters, after.clusters);
+            } else if after.clusters != before.clusters {
+                assert!(!after.is_fragmented, "{} is still fragmented", after.name);
+            }
+        }
+
+        // Nothing is packed: files are never pushed aside to make room
+        for m in &moves {
+            assert!(layout.files[m.file].is_fragmented || m.from.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_partly_placed_file_is_rolled_back() {
+        let mut clusters = vec![ClusterState::Used; 7];
+        clusters[6] = ClusterState::Unused;
+        let files = [
+            FileFragment::new("A", vec![0, 2]),
+            FileFragment::new("B", vec![1]),
+            FileFragment::new("C", vec![3]),
+            FileFragment::new("D", vec![4]),
+            FileFragment::new("E", vec![5]),
+        ];
+
+        // D moves to the last free cluster, then nothing is left for E
+        let mut planner = Planner::new(&clusters, &files);
+        assert!(!planner.place_file(0, 4));
+
+        let fresh = Planner::new(&clusters, &files);
+        assert!(planner.moves.is_empty());
+        assert_eq!(planner.owner, fresh.owner);
+        assert_eq!(planner.files, fresh.files);
+        assert_eq!(planner.free, fresh.free);
+    }
+
+    #[test]
+    fn test_full_disk_does_not_panic() {
+        let layout = DiskLayout::generate(40 * 10, 1.0, 0.02, &mut SimRng::seed_from_u64(4));
+        for method in OptimizationMethod::ALL {
+            let moves = plan(method, FileSort::default(), &layout.clusters, &layout.files);
+            replay(&layout.clusters, &layout.files, &moves);
+        }
+    }
+}
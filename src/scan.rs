@@ -0,0 +1,422 @@
+//! Fragmentation of real files
+//!
+//! Reads where the files below a directory physically live on disk (FIEMAP,
+//! or FIBMAP on file systems without it) and projects their extents onto the
+//! cluster grid, so the defragmenter replays a hypothetical defrag of a real
+//! disk. Files are only inspected, never moved.
+
+use crate::layout::DiskLayout;
+use crate::models::{ClusterState, FileFragment};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// A run of physically contiguous bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    /// Byte offset on the device
+    pub physical: u64,
+    /// Length in bytes
+    pub length: u64,
+}
+
+/// A file found by [`scan`] and where it lives on disk
+#[derive(Debug, Clone)]
+pub struct ScannedFile {
+    /// Path relative to the scanned directory
+    pub path: String,
+    /// Physical extents, in the file's logical order
+    pub extents: Vec<Extent>,
+    /// Last modification time, packed as in [`FileFragment::modified`]
+    pub modified: u32,
+}
+
+/// Lists the regular files below `root` with their physical extents
+///
+/// The walk stays on the file system of `root` and skips symbolic links.
+/// Files whose extents cannot be read (no permission, no block mapping, too
+/// many blocks for FIBMAP) are left out.
+pub fn scan(root: &Path) -> Result<Vec<ScannedFile>> {
+    let root_device = device(&std::fs::metadata(root)?);
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if device(&metadata) != root_device {
+                continue;
+            }
+            if metadata.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            if !metadata.is_file() || metadata.len() == 0 {
+                continue;
+            }
+
+            let Ok(extents) =
+                File::open(&path).and_then(|file| sys::extents(&file, metadata.len()))
+            else {
+                continue;
+            };
+            if extents.is_empty() {
+                continue;
+            }
+            files.push(ScannedFile {
+                path: path
+                    .strip_prefix(root)
+                    .unwrap_or(&path)
+                    .to_string_lossy()
+                    .into_owned(),
+                extents,
+                modified: metadata.modified().map_or(0, dos_time),
+            });
+        }
+    }
+
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(files)
+}
+
+/// File system the metadata belongs to
+#[cfg(unix)]
+fn device(metadata: &std::fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.dev()
+}
+
+#[cfg(not(unix))]
+fn device(_metadata: &std::fs::Metadata) -> u64 {
+    0
+}
+
+/// Merges extents that follow each other on disk
+fn merge(extents: Vec<Extent>) -> Vec<Extent> {
+    let mut merged: Vec<Extent> = Vec::with_capacity(extents.len());
+    for extent in extents {
+        match merged.last_mut() {
+            Some(last) if last.physical + last.length == extent.physical => {
+                last.length += extent.length;
+            }
+            _ => merged.push(extent),
+        }
+    }
+    merged
+}
+
+/// Converts a file time into an MS-DOS packed date and time
+fn dos_time(time: SystemTime) -> u32 {
+    let seconds = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let days = (seconds / 86_400) as i64;
+    let of_day = seconds % 86_400;
+
+    // Civil date from days since 1970-01-01 (proleptic Gregorian calendar)
+    let era_days = days + 719_468;
+    let era = era_days.div_euclid(146_097);
+    let day_of_era = era_days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    // MS-DOS dates cover 1980 to 2107
+    if year < 1980 {
+        return FileFragment::dos_timestamp(1980, 1, 1, 0, 0, 0);
+    }
+    FileFragment::dos_timestamp(
+        year.min(2107) as u16,
+        month as u8,
+        day as u8,
+        (of_day / 3600) as u8,
+        (of_day / 60 % 60) as u8,
+        (of_day % 60) as u8,
+    )
+}
+
+/// Projects scanned files onto a grid of `cells` clusters
+///
+/// The grid spans the physical range the files occupy, each cell standing for
+/// an equal share of it. A cell belongs to the first file (in disk order)
+/// found in it; files too small to get a cell of their own are left out.
+/// Cells no scanned file uses are shown as free.
+pub fn project(files: &[ScannedFile], cells: usize) -> DiskLayout {
+    let mut clusters = vec![ClusterState::Unused; cells];
+    let extents = files.iter().flat_map(|file| &file.extents);
+    let (Some(start), Some(end)) = (
+        extents.clone().map(|extent| extent.physical).min(),
+        extents.map(|extent| extent.physical + extent.length).max(),
+    ) else {
+        return DiskLayout {
+            clusters,
+            files: Vec::new(),
+        };
+    };
+    if cells == 0 {
+        return DiskLayout {
+            clusters,
+            files: Vec::new(),
+        };
+    }
+    let bytes_per_cell = (end - start).div_ceil(cells as u64).max(1);
+
+    let mut order: Vec<&ScannedFile> = files.iter().filter(|f| !f.extents.is_empty()).collect();
+    order.sort_by_key(|file| file.extents.iter().map(|e| e.physical).min());
+
+    let mut claimed = vec![false; cells];
+    let mut projected = Vec::new();
+    for file in order {
+        let mut file_cells = Vec::new();
+        for extent in file.extents.iter().filter(|extent| extent.length > 0) {
+            let first = ((extent.physical - start) / bytes_per_cell) as usize;
+            let last = ((extent.physical + extent.length - 1 - start) / bytes_per_cell) as usize;
+            for cell in first..=last.min(cells - 1) {
+                if !claimed[cell] {
+                    claimed[cell] = true;
+                    clusters[cell] = ClusterState::Pending;
+                    file_cells.push(cell);
+                }
+            }
+        }
+        if file_cells.is_empty() {
+            continue;
+        }
+
+        let mut fragment = FileFragment::new(file.path.clone(), file_cells);
+        fragment.modified = file.modified;
+        projected.push(fragment);
+    }
+
+    DiskLayout {
+        clusters,
+        files: projected,
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    //! Block mapping ioctls of Linux
+
+    use super::{merge, Extent};
+    use std::fs::File;
+    use std::io::{Error, ErrorKind, Result};
+    use std::os::fd::AsRawFd;
+
+    const FS_IOC_FIEMAP: u64 = 0xC020_660B;
+    const FIBMAP: u64 = 1;
+    const FIGETBSZ: u64 = 2;
+
+    /// Flush delayed allocations first, so new files have a location
+    const FIEMAP_FLAG_SYNC: u32 = 0x1;
+    const FIEMAP_EXTENT_LAST: u32 = 0x1;
+    const FIEMAP_EXTENT_UNKNOWN: u32 = 0x2;
+    const FIEMAP_EXTENT_DATA_INLINE: u32 = 0x200;
+
+    /// Extents fetched per ioctl call
+    const BATCH: usize = 64;
+    /// Most blocks mapped with FIBMAP, which takes one ioctl call per block
+    const FIBMAP_MAX_BLOCKS: u64 = 1 << 16;
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct FiemapExtent {
+        fe_logical: u64,
+        fe_physical: u64,
+        fe_length: u64,
+        fe_reserved64: [u64; 2],
+        fe_flags: u32,
+        fe_reserved: [u32; 3],
+    }
+
+    #[repr(C)]
+    struct Fiemap {
+        fm_start: u64,
+        fm_length: u64,
+        fm_flags: u32,
+        fm_mapped_extents: u32,
+        fm_extent_count: u32,
+        fm_reserved: u32,
+        fm_extents: [FiemapExtent; BATCH],
+    }
+
+    /// Physical extents of `file`, from FIEMAP or else FIBMAP
+    pub fn extents(file: &File, size: u64) -> Result<Vec<Extent>> {
+        fiemap(file).or_else(|_| fibmap(file, size))
+    }
+
+    fn fiemap(file: &File) -> Result<Vec<Extent>> {
+        let mut extents = Vec::new();
+        let mut start = 0;
+        loop {
+            // SAFETY: Fiemap is plain old data, all zeroes is a valid value
+            let mut map: Fiemap = unsafe { std::mem::zeroed() };
+            map.fm_start = start;
+            map.fm_length = u64::MAX - start;
+            map.fm_flags = FIEMAP_FLAG_SYNC;
+            map.fm_extent_count = BATCH as u32;
+
+            // SAFETY: `map` is a valid fiemap with room for BATCH extents
+            let result = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FIEMAP as _, &mut map) };
+            if result < 0 {
+                return Err(Error::last_os_error());
+            }
+
+            let mapped = &map.fm_extents[..(map.fm_mapped_extents as usize).min(BATCH)];
+            let Some(last) = mapped.last() else {
+                break;
+            };
+            for extent in mapped {
+                if extent.fe_flags & (FIEMAP_EXTENT_UNKNOWN | FIEMAP_EXTENT_DATA_INLINE) == 0 {
+                    extents.push(Extent {
+                        physical: extent.fe_physical,
+                        length: extent.fe_length,
+                    });
+                }
+            }
+            if last.fe_flags & FIEMAP_EXTENT_LAST != 0 {
+                break;
+            }
+            start = last.fe_logical + last.fe_length;
+        }
+        Ok(merge(extents))
+    }
+
+    fn fibmap(file: &File, size: u64) -> Result<Vec<Extent>> {
+        let fd = file.as_raw_fd();
+        let mut block_size: libc::c_int = 0;
+        // SAFETY: FIGETBSZ writes a single int
+        if unsafe { libc::ioctl(fd, FIGETBSZ as _, &mut block_size) } < 0 {
+            return Err(Error::last_os_error());
+        }
+        let block_size = block_size.max(1) as u64;
+        let blocks = size.div_ceil(block_size);
+        if blocks > FIBMAP_MAX_BLOCKS {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("{} blocks are too many to map one by one", blocks),
+            ));
+        }
+
+        let mut extents = Vec::new();
+        for index in 0..blocks {
+            let mut block = index as libc::c_int;
+            // SAFETY: FIBMAP reads and writes a single int
+            if unsafe { libc::ioctl(fd, FIBMAP as _, &mut block) } < 0 {
+                return Err(Error::last_os_error());
+            }
+            // Block 0 is a hole
+            if block > 0 {
+                extents.push(Extent {
+                    physical: block as u64 * block_size,
+                    length: block_size,
+                });
+            }
+        }
+        Ok(merge(extents))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::Extent;
+    use std::fs::File;
+    use std::io::{Error, ErrorKind, Result};
+
+    pub fn extents(_file: &File, _size: u64) -> Result<Vec<Extent>> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "reading file extents needs Linux",
+        ))
+    }
+}
+
+/// Scans `root` and projects it onto a grid of `cells` clusters
+///
+/// Fails when no file below `root` has a known location on disk.
+pub fn scan_layout(root: &Path, cells: usize) -> Result<DiskLayout> {
+    let files = scan(root)?;
+    if files.is_empty() {
+        return Err(Error::new(
+            ErrorKind::NotFound,
+            "no file with a known location on disk (FIEMAP/FIBMAP unsupported?)",
+        ));
+    }
+    Ok(project(&files, cells))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, extents: &[(u64, u64)], modified: u32) -> ScannedFile {
+        ScannedFile {
+            path: path.to_string(),
+            extents: extents
+                .iter()
+                .map(|&(physical, length)| Extent { physical, length })
+                .collect(),
+            modified,
+        }
+    }
+
+    #[test]
+    fn test_project_scales_extents_to_grid() {
+        let files = [
+            file("a.txt", &[(1000, 100), (1600, 200)], 1),
+            file("b/c.bin", &[(1100, 500)], 2),
+        ];
+        // 800 bytes over 8 cells: 100 bytes per cell
+        let layout = project(&files, 8);
+
+        assert_eq!(layout.files[0].name, "a.txt");
+        assert_eq!(layout.files[0].clusters, [0, 6, 7]);
+        assert!(layout.files[0].is_fragmented);
+        assert_eq!(layout.files[1].clusters, [1, 2, 3, 4, 5]);
+        assert!(!layout.files[1].is_fragmented);
+        assert_eq!(layout.files[1].modified, 2);
+        assert!(layout.clusters.iter().all(|&c| c == ClusterState::Pending));
+    }
+
+    #[test]
+    fn test_dos_time_conversion() {
+        // 1996-08-24 13:45:30 UTC
+        let time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(840_894_330);
+        assert_eq!(
+            dos_time(time),
+            FileFragment::dos_timestamp(1996, 8, 24, 13, 45, 30)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a temporary directory with block mapping (not tmpfs)"]
+    fn test_scan_reads_written_file() {
+        let dir = std::env::temp_dir().join(format!("defrag-scan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.bin"), vec![0x5A; 64 * 1024]).unwrap();
+        let files = scan(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let files = files.unwrap();
+        let file = files.iter().find(|f| f.path == "data.bin").unwrap();
+        let bytes: u64 = file.extents.iter().map(|e| e.length).sum();
+        assert!(bytes >= 64 * 1024);
+        let layout = project(&files, 100);
+        assert!(!layout.files[0].clusters.is_empty());
+    }
+}
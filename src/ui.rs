@@ -1,11 +1,13 @@
-use crate::app::App;
-use crate::models::{ClusterState, DefragPhase};
+use crate::app::{App, FileColumn};
+use crate::models::{
+    clusters_per_cell, ClusterState, DefragPhase, OptimizationMethod, SortKey, SortOrder,
+};
 use ratatui::{
     layout::{Alignment, Constraint, Direction, Layout},
     prelude::*,
     style::{Color, Style},
     text::{Line, Span},
-    widgets::{Block, BorderType, Borders, Paragraph, Widget},
+    widgets::{Block, BorderType, Borders, Clear, Paragraph, Widget},
 };
 
 // -- UI Components ------------------------------------------------------------
@@ -58,10 +60,21 @@ pub fn render_app(app: &App, frame: &mut Frame) {
 
     render_header(app, frame, main_layout[0]);
 
-    let main_window_block = Block::new()
+    let mut main_window_block = Block::new()
         .borders(Borders::ALL)
         .border_type(BorderType::Double)
         .style(Style::new().on_blue());
+    if let Some(ref sequence) = app.drive_sequence {
+        main_window_block = main_window_block
+            .title(format!(
+                " Drive {}: {} ({} of {}) ",
+                app.current_drive.letter(),
+                app.current_drive.label,
+                sequence.current + 1,
+                app.drive_collection.get_all().len()
+            ))
+            .title_alignment(Alignment::Center);
+    }
 
     let grid_area = main_window_block.inner(main_layout[1]);
 
@@ -71,6 +84,13 @@ pub fn render_app(app: &App, frame: &mut Frame) {
     render_footer(app, frame, main_layout[2]);
     render_menu_dropdown(app, frame, frame.area());
     render_about_box(app, frame);
+    render_method_dialog(app, frame);
+    render_drive_dialog(app, frame);
+    render_drive_summary(app, frame);
+    render_file_dialog(app, frame);
+    render_analysis_dialog(app, frame);
+    render_save_dialog(app, frame);
+    render_message_box(app, frame);
 }
 
 fn render_header(app: &App, frame: &mut Frame, area: Rect) {
@@ -103,7 +123,7 @@ fn render_header(app: &App, frame: &mut Frame, area: Rect) {
     }
 
     let current_len: usize = spans.iter().map(|s| s.content.len()).sum();
-    let padding = area.width as usize - current_len - 9;
+    let padding = (area.width as usize).saturating_sub(current_len + 9);
     spans.push(Span::styled(
         " ".repeat(padding),
         Style::new().black().on_white(),
@@ -127,7 +147,7 @@ fn render_menu_dropdown(app: &App, frame: &mut Frame, area: Rect) {
     let menu_positions = [1, 12, 22, 29, 36];
     let menu_x = menu_positions.get(app.selected_menu).copied().unwrap_or(1) as u16;
 
-    let max_width = items.iter().map(|s| s.len()).max().unwrap_or(10) + 4;
+    let max_width = items.iter().map(|s| s.len()).max().unwrap_or(10) + 5;
     let menu_height = items.len() as u16 + 2;
 
     let menu_area = Rect::new(area.x + menu_x, area.y + 1, max_width as u16, menu_height);
@@ -145,6 +165,12 @@ fn render_menu_dropdown(app: &App, frame: &mut Frame, area: Rect) {
             break;
         }
 
+        let mark = if is_menu_item_checked(app, app.selected_menu, i) {
+            '•'
+        } else {
+            ' '
+        };
+
         let item_area = Rect::new(inner.x, inner.y + i as u16, inner.width, 1);
 
         if item.is_empty() {
@@ -153,14 +179,15 @@ fn render_menu_dropdown(app: &App, frame: &mut Frame, area: Rect) {
             frame.render_widget(sep, item_area);
         } else if i == app.selected_item {
             let selected = Paragraph::new(format!(
-                " {:<width$}",
+                "{} {:<width$}",
+                mark,
                 item,
-                width = inner.width as usize - 1
+                width = inner.width as usize - 2
             ))
             .style(Style::new().fg(Color::White).bg(Color::Black));
             frame.render_widget(selected, item_area);
         } else {
-            let normal = Paragraph::new(format!(" {}", item))
+            let normal = Paragraph::new(format!("{} {}", mark, item))
                 .style(Style::new().fg(Color::Black).bg(Color::White));
             frame.render_widget(normal, item_area);
         }
@@ -169,7 +196,8 @@ fn render_menu_dropdown(app: &App, frame: &mut Frame, area: Rect) {
 
 fn render_grid(app: &App, frame: &mut Frame, area: Rect) {
     let grid_widget = DiskGridWidget {
-        clusters: &app.clusters,
+        clusters: &app.engine.clusters,
+        flashing: app.flashing_clusters(),
     };
     frame.render_widget(grid_widget, area);
 }
@@ -195,15 +223,16 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
         footer_layout[0],
     );
 
-    let percent = if app.stats.total_to_defrag == 0 {
+    let percent = if app.engine.stats.total_to_defrag == 0 {
         100.0
     } else {
-        (app.stats.clusters_defragged as f32 / app.stats.total_to_defrag as f32) * 100.0
+        (app.engine.stats.clusters_defragged as f32 / app.engine.stats.total_to_defrag as f32)
+            * 100.0
     };
     let line2_spans = vec![
         Span::raw(format!(
             "│ Cluster {:<6}                    {:>3}% │",
-            app.stats.clusters_defragged,
+            app.engine.stats.clusters_defragged,
             percent.min(100.0) as u8
         )),
         Span::raw("│ "),
@@ -231,20 +260,9 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
         footer_layout[2],
     );
 
-    let elapsed = app.stats.start_time.elapsed();
-    let elapsed_str = format!(
-        "{:02}:{:02}:{:02}",
-        elapsed.as_secs() / 3600,
-        (elapsed.as_secs() % 3600) / 60,
-        elapsed.as_secs() % 60
-    );
-    let remaining_str = if let Some(remaining) = app.estimated_time_remaining() {
-        format!(
-            " ETA {:02}:{:02}:{:02}",
-            remaining.as_secs() / 3600,
-            (remaining.as_secs() % 3600) / 60,
-            remaining.as_secs() % 60
-        )
+    let elapsed_str = format_time(app.engine.elapsed());
+    let remaining_str = if let Some(remaining) = app.engine.estimated_time_remaining() {
+        format!(" ETA {}", format_time(remaining))
     } else {
         String::new()
     };
@@ -262,22 +280,23 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
         footer_layout[3],
     );
 
-    let status_text = if let Some(filename) = &app.current_filename {
+    let status_text = if let Some(filename) = &app.engine.current_filename {
+        // Cut on a character boundary, as paths from --scan are any UTF-8
         let max_len = 38;
-        let display_name = if filename.len() > max_len {
-            &filename[..max_len]
-        } else {
-            filename
+        let display_name = match filename.char_indices().nth(max_len) {
+            Some((end, _)) => &filename[..end],
+            None => filename,
         };
         format!("File: {}", display_name)
     } else {
-        "Full optimization".to_string()
+        app.engine.method.name().to_string()
     };
 
     let line5_content = format!("{:^38}", status_text);
     let line5 = format!(
-        "│{}  ││ Drive C: ░ = Unused space              │",
-        line5_content
+        "│{}  ││ Drive {}: ░ = Unused space              │",
+        line5_content,
+        app.current_drive.letter()
     );
 
     frame.render_widget(
@@ -295,10 +314,11 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
     let action_text = if app.paused {
         "[ PAUSED ]"
     } else {
-        match app.phase {
+        match app.engine.phase {
             DefragPhase::Initializing => "Initializing...",
             DefragPhase::Analyzing => "Analyzing disk...",
-            DefragPhase::Defragmenting => match app.animation_step % 3 {
+            DefragPhase::Analyzed => "Analysis complete",
+            DefragPhase::Defragmenting => match app.engine.animation_step % 3 {
                 0 => "Reading...",
                 1 => "Writing...",
                 _ => "Updating FAT...",
@@ -310,9 +330,9 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
     let demo_indicator = if app.demo_mode { "[DEMO] " } else { "" };
 
     let sound_indicator = match &app.audio {
-        Some(audio) if audio.is_enabled() => " [♪ ON] ",
-        Some(_) => " [♪ OFF]",
-        None => " [S=Sound]",
+        Some(audio) if audio.is_enabled() => format!(" [♪ {:>3.0}%]", app.volume * 100.0),
+        Some(_) => " [♪ OFF]".to_string(),
+        None => " [S=Sound]".to_string(),
     };
 
     let version_text = "| MS-DOS defrag ";
@@ -334,9 +354,20 @@ fn render_footer(app: &App, frame: &mut Frame, area: Rect) {
     frame.render_widget(action_line, footer_layout[6]);
 }
 
+/// Formats a duration as HH:MM:SS
+pub fn format_time(time: std::time::Duration) -> String {
+    let seconds = time.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds % 3600) / 60,
+        seconds % 60
+    )
+}
+
 fn create_progress_bar(percent: f32) -> String {
     let bar_width: usize = 38;
-    let clamped_percent = percent.min(100.0).max(0.0);
+    let clamped_percent = percent.clamp(0.0, 100.0);
     let filled_width = ((clamped_percent / 100.0) * bar_width as f32) as usize;
     let empty_width = bar_width.saturating_sub(filled_width);
     format!("{}{}", "█".repeat(filled_width), "░".repeat(empty_width))
@@ -354,16 +385,35 @@ pub fn get_menu_items(menu_idx: usize) -> Vec<&'static str> {
         1 => vec!["Analyze drive", "File fragmentation..."],
         2 => vec!["Print disk map", "Save disk map..."],
         3 => vec![
+            "Unsorted",
             "Sort by name",
             "Sort by extension",
             "Sort by date",
             "Sort by size",
+            "",
+            "Ascending",
+            "Descending",
         ],
         4 => vec!["Contents", "About MS-DOS Defrag..."],
         _ => vec![],
     }
 }
 
+/// Whether a menu item shows a check mark for the current settings
+fn is_menu_item_checked(app: &App, menu_idx: usize, item_idx: usize) -> bool {
+    let sort = app.engine.sort;
+    match (menu_idx, item_idx) {
+        (3, 0) => sort.key == SortKey::Unsorted,
+        (3, 1) => sort.key == SortKey::Name,
+        (3, 2) => sort.key == SortKey::Extension,
+        (3, 3) => sort.key == SortKey::Date,
+        (3, 4) => sort.key == SortKey::Size,
+        (3, 6) => sort.order == SortOrder::Ascending,
+        (3, 7) => sort.order == SortOrder::Descending,
+        _ => false,
+    }
+}
+
 fn get_menu_names() -> Vec<&'static str> {
     vec!["Optimize", "Analyze", "File", "Sort", "Help"]
 }
@@ -395,6 +445,7 @@ fn render_about_box(app: &App, frame: &mut Frame) {
         .title_alignment(Alignment::Center)
         .style(Style::new().bg(Color::Gray).fg(Color::Black));
 
+    frame.render_widget(Clear, about_area);
     frame.render_widget(about_block.clone(), about_area);
 
     let inner = about_block.inner(about_area);
@@ -462,10 +513,459 @@ fn render_about_box(app: &App, frame: &mut Frame) {
     frame.render_widget(ok_button, button_area);
 }
 
+/// Gray dialog box with a shadow, centered on the screen; returns its inner
+/// area
+fn render_dialog_frame(frame: &mut Frame, title: &str, width: u16, height: u16) -> Rect {
+    let area = frame.area();
+    let box_x = (area.width.saturating_sub(width)) / 2;
+    let box_y = (area.height.saturating_sub(height)) / 2;
+
+    let dialog_area = Rect::new(box_x, box_y, width, height);
+
+    let shadow_area = Rect::new(box_x + 2, box_y + 1, width, height);
+    frame.render_widget(
+        Block::new().style(Style::new().bg(Color::Black)),
+        shadow_area,
+    );
+
+    let dialog_block = Block::new()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Double)
+        .title(title)
+        .title_alignment(Alignment::Center)
+        .style(Style::new().bg(Color::Gray).fg(Color::Black));
+
+    frame.render_widget(Clear, dialog_area);
+    frame.render_widget(dialog_block.clone(), dialog_area);
+    dialog_block.inner(dialog_area)
+}
+
+fn render_method_dialog(app: &App, frame: &mut Frame) {
+    let Some(selected) = app.method_dialog else {
+        return;
+    };
+
+    let inner = render_dialog_frame(frame, " Select Optimization Method ", 56, 14);
+
+    let mut lines = vec![Line::from("")];
+    for method in OptimizationMethod::ALL {
+        let marker = if method == selected { "(•)" } else { "( )" };
+        let style = if method == selected {
+            Style::new().fg(Color::White).bg(Color::Black).bold()
+        } else {
+            Style::new().fg(Color::Black)
+        };
+        lines.push(Line::from(vec![
+            Span::raw("  "),
+            Span::styled(format!("{} {}", marker, method.name()), style),
+        ]));
+        let description: &[&str] = match method {
+            OptimizationMethod::FullOptimization => &[
+                "Fully defragments all files and moves them",
+                "to the front of the disk.",
+            ],
+            OptimizationMethod::UnfragmentFilesOnly => &[
+                "Defragments files, but may leave free",
+                "space holes between them.",
+            ],
+        };
+        for text in description {
+            lines.push(Line::from(Span::styled(
+                format!("      {}", text),
+                Style::new().fg(Color::DarkGray),
+            )));
+        }
+        lines.push(Line::from(""));
+    }
+
+    let paragraph = Paragraph::new(lines).style(Style::new().bg(Color::Gray));
+    frame.render_widget(paragraph, inner);
+
+    let buttons_width = 24;
+    let buttons_x = inner.x + (inner.width.saturating_sub(buttons_width)) / 2;
+    let buttons_y = inner.y + inner.height - 2;
+    let buttons_area = Rect::new(buttons_x, buttons_y, buttons_width, 1);
+
+    let buttons = Paragraph::new("[   OK   ]  [ Cancel ]")
+        .style(Style::new().fg(Color::White).bg(Color::DarkGray).bold())
+        .alignment(Alignment::Center);
+    frame.render_widget(buttons, buttons_area);
+}
+
+fn render_drive_dialog(app: &App, frame: &mut Frame) {
+    let Some(selected) = app.drive_dialog else {
+        return;
+    };
+
+    let area = frame.area();
+    let drives = app.drive_collection.get_all();
+
+    let box_width = 60;
+    let box_height = (drives.len() as u16 + 9).min(area.height);
+    let inner = render_dialog_frame(frame, " Select Drive ", box_width, box_height);
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            format!(
+                "  {:<6} {:<24} {:>9} {:>8}",
+                "Drive", "Name", "Capacity", "IOPS"
+            ),
+            Style::new().fg(Color::DarkGray),
+        )),
+    ];
+    for (index, drive) in drives.iter().enumerate() {
+        let style = if index == selected {
+            Style::new().fg(Color::White).bg(Color::Black).bold()
+        } else {
+            Style::new().fg(Color::Black)
+        };
+        let label: String = drive.label.chars().take(24).collect();
+        lines.push(Line::from(vec![
+            Span::raw("  "),
+            Span::styled(
+                format!(
+                    "{:<6} {:<24} {:>9} {:>8}",
+                    format!("{}:", drive.letter()),
+                    label,
+                    drive.capacity(),
+                    drive.iops()
+                ),
+                style,
+            ),
+        ]));
+    }
+    let all_style = if selected == drives.len() {
+        Style::new().fg(Color::White).bg(Color::Black).bold()
+    } else {
+        Style::new().fg(Color::Black)
+    };
+    lines.push(Line::from(vec![
+        Span::raw("  "),
+        Span::styled(
+            format!("{:<6} {:<43}", "All", "All drives, one after another"),
+            all_style,
+        ),
+    ]));
+
+    let paragraph = Paragraph::new(lines).style(Style::new().bg(Color::Gray));
+    frame.render_widget(paragraph, inner);
+
+    let buttons_width = 24;
+    let buttons_x = inner.x + (inner.width.saturating_sub(buttons_width)) / 2;
+    let buttons_y = inner.y + inner.height.saturating_sub(2);
+    let buttons_area = Rect::new(buttons_x, buttons_y, buttons_width, 1);
+
+    let buttons = Paragraph::new("[   OK   ]  [ Cancel ]")
+        .style(Style::new().fg(Color::White).bg(Color::DarkGray).bold())
+        .alignment(Alignment::Center);
+    frame.render_widget(buttons, buttons_area);
+}
+
+fn render_drive_summary(app: &App, frame: &mut Frame) {
+    let Some(ref sequence) = app.drive_sequence else {
+        return;
+    };
+    if !sequence.finished {
+        return;
+    }
+
+    let area = frame.area();
+
+    let box_width = 60;
+    let box_height = (sequence.reports.len() as u16 + 9).min(area.height);
+    let inner = render_dialog_frame(frame, " All Drives Defragmented ", box_width, box_height);
+
+    let mut lines = vec![
+        Line::from(""),
+        Line::from(Span::styled(
+            format!(
+                "  {:<6} {:<18} {:>9} {:>9} {:>8}",
+                "Drive", "Name", "Time", "Before", "After"
+            ),
+            Style::new().fg(Color::DarkGray),
+        )),
+    ];
+    for report in &sequence.reports {
+        let label: String = report.label.chars().take(18).collect();
+        lines.push(Line::from(format!(
+            "  {:<6} {:<18} {:>9} {:>8.0}% {:>7.0}%",
+            format!("{}:", report.letter),
+            label,
+            format_time(report.elapsed),
+            report.fragmented_before,
+            report.fragmented_after
+        )));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(Span::styled(
+        "  Before/After: share of fragmented files",
+        Style::new().fg(Color::DarkGray),
+    )));
+
+    let paragraph = Paragraph::new(lines).style(Style::new().bg(Color::Gray));
+    frame.render_widget(paragraph, inner);
+
+    let button_width = 10;
+    let button_x = inner.x + (inner.width.saturating_sub(button_width)) / 2;
+    let button_y = inner.y + inner.height.saturating_sub(2);
+    let button_area = Rect::new(button_x, button_y, button_width, 1);
+
+    let ok_button = Paragraph::new("[  OK  ]")
+        .style(Style::new().fg(Color::White).bg(Color::DarkGray).bold())
+        .alignment(Alignment::Center);
+    frame.render_widget(ok_button, button_area);
+}
+
+fn render_file_dialog(app: &App, frame: &mut Frame) {
+    let Some(ref dialog) = app.file_dialog else {
+        return;
+    };
+
+    let area = frame.area();
+
+    let box_width = 74.min(area.width);
+    let box_height = area.height.saturating_sub(4).max(8).min(area.height);
+    let inner = render_dialog_frame(frame, " File Fragmentation ", box_width, box_height);
+
+    // Header, with an arrow on the column the list is sorted by
+    let headers: Vec<String> = FileColumn::ALL
+        .iter()
+        .map(|&column| {
+            if column == dialog.column {
+                let arrow = if dialog.descending { '▼' } else { '▲' };
+                format!("{}{}", column.name(), arrow)
+            } else {
+                column.name().to_string()
+            }
+        })
+        .collect();
+    let mut lines = vec![Line::from(Span::styled(
+        format!(
+            " {:<30} {:>10} {:>9} {:>7} {:>11}",
+            headers[0], headers[1], headers[2], headers[3], headers[4]
+        ),
+        Style::new().fg(Color::DarkGray),
+    ))];
+
+    // The list scrolls one page at a time
+    let visible = (inner.height as usize).saturating_sub(3).max(1);
+    let top = dialog.selected / visible * visible;
+    for (row, &file_index) in dialog.rows.iter().enumerate().skip(top).take(visible) {
+        let file = &app.engine.files[file_index];
+        let name = file.display_name();
+        let name = if name.chars().count() > 30 {
+            let tail: String = name.chars().skip(name.chars().count() - 27).collect();
+            format!("...{}", tail)
+        } else {
+            name.to_string()
+        };
+        let style = if row == dialog.selected {
+            Style::new().fg(Color::White).bg(Color::Black).bold()
+        } else if file.is_fragmented {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new().fg(Color::Black)
+        };
+        lines.push(Line::from(Span::styled(
+            format!(
+                " {:<30} {:>10} {:>9} {:>7} {:>11}",
+                name,
+                file.fragment_count(),
+                file.size,
+                file.first_cluster()
+                    .map_or(String::new(), |c| c.to_string()),
+                if file.is_fragmented {
+                    "Fragmented"
+                } else {
+                    "Contiguous"
+                }
+            ),
+            style,
+        )));
+    }
+    if dialog.rows.is_empty() {
+        lines.push(Line::from(" No files on this drive."));
+    }
+
+    let paragraph = Paragraph::new(lines).style(Style::new().bg(Color::Gray));
+    frame.render_widget(paragraph, inner);
+
+    let fragmented = app.engine.files.iter().filter(|f| f.is_fragmented).count();
+    let hint = format!(
+        "{}/{} fragmented   ↑↓ Select  ←→ Sort  Space Reverse  Esc Close",
+        fragmented,
+        app.engine.files.len()
+    );
+    let hint_area = Rect::new(
+        inner.x,
+        inner.y + inner.height.saturating_sub(1),
+        inner.width,
+        1,
+    );
+    frame.render_widget(
+        Paragraph::new(hint)
+            .style(Style::new().fg(Color::White).bg(Color::DarkGray))
+            .alignment(Alignment::Center),
+        hint_area,
+    );
+}
+
+fn render_analysis_dialog(app: &App, frame: &mut Frame) {
+    let (Some(optimize), Some(report)) = (app.analysis_dialog, app.engine.analysis) else {
+        return;
+    };
+
+    let inner = render_dialog_frame(frame, " Recommendation ", 64, 16);
+
+    let (sentence, advice) = report
+        .verdict()
+        .split_once(". ")
+        .map(|(a, b)| (format!("{}.", a), b.to_string()))
+        .unwrap_or_default();
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!(
+            "  Analysis of drive {}: ({})",
+            app.current_drive.letter(),
+            app.current_drive.label
+        )),
+        Line::from(""),
+        Line::from(format!(
+            "    Files:            {:>7}    Fragmented:   {:>7} ({:.0}%)",
+            report.total_files,
+            report.fragmented_files,
+            report.fragmented_files_percent()
+        )),
+        Line::from(format!(
+            "    Free clusters:    {:>7}    Free runs:    {:>7}",
+            report.free_clusters, report.free_runs
+        )),
+        Line::from(format!(
+            "    Largest free run: {:>7}    Bad clusters: {:>7}",
+            report.largest_free_run, report.bad_clusters
+        )),
+        Line::from(""),
+        Line::from(Span::styled(
+            format!("  {}", sentence),
+            Style::new().fg(Color::Black).bold(),
+        )),
+        Line::from(Span::styled(
+            format!("  {}", advice),
+            Style::new().fg(if report.is_defrag_recommended() {
+                Color::Red
+            } else {
+                Color::Black
+            }),
+        )),
+    ];
+
+    let paragraph = Paragraph::new(lines).style(Style::new().bg(Color::Gray));
+    frame.render_widget(paragraph, inner);
+
+    let buttons_x = inner.x + (inner.width.saturating_sub(26)) / 2;
+    let buttons_y = inner.y + inner.height.saturating_sub(2);
+    let selected = Style::new().fg(Color::White).bg(Color::Black).bold();
+    let normal = Style::new().fg(Color::White).bg(Color::DarkGray).bold();
+    let buttons = Line::from(vec![
+        Span::styled("[ Optimize ]", if optimize { selected } else { normal }),
+        Span::raw("  "),
+        Span::styled("[ Cancel ]", if optimize { normal } else { selected }),
+    ]);
+    frame.render_widget(
+        Paragraph::new(buttons),
+        Rect::new(buttons_x, buttons_y, 26, 1),
+    );
+}
+
+fn render_save_dialog(app: &App, frame: &mut Frame) {
+    let Some(ref name) = app.save_dialog else {
+        return;
+    };
+
+    let inner = render_dialog_frame(frame, " Save Disk Map ", 60, 10);
+
+    // Keep the end of a long name, and the cursor, in view
+    let field_width = 40;
+    let shown: String = name
+        .chars()
+        .skip(name.chars().count().saturating_sub(field_width - 1))
+        .collect();
+    let lines = vec![
+        Line::from(""),
+        Line::from(vec![
+            Span::raw("  File name: "),
+            Span::styled(
+                format!("{:<width$}", format!("{}_", shown), width = field_width),
+                Style::new().fg(Color::White).bg(Color::Blue),
+            ),
+        ]),
+        Line::from(""),
+        Line::from("  The extension selects the format:"),
+        Line::from("    .TXT text  .437 code page 437  .ANS ANSI  .HTM HTML"),
+        Line::from(""),
+        Line::from(Span::styled(
+            "  Enter=Save   Esc=Cancel",
+            Style::new().fg(Color::DarkGray),
+        )),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).style(Style::new().bg(Color::Gray)),
+        inner,
+    );
+}
+
+fn render_message_box(app: &App, frame: &mut Frame) {
+    let Some(ref message) = app.message else {
+        return;
+    };
+
+    let width = (message.chars().count() as u16 + 6).clamp(30, frame.area().width);
+    let inner = render_dialog_frame(frame, " MS-DOS Defrag ", width, 7);
+
+    let lines = vec![
+        Line::from(""),
+        Line::from(format!("  {}", message)),
+        Line::from(""),
+        Line::from(Span::styled(
+            "[ OK ]",
+            Style::new().fg(Color::White).bg(Color::Black).bold(),
+        ))
+        .alignment(Alignment::Center),
+    ];
+    frame.render_widget(
+        Paragraph::new(lines).style(Style::new().bg(Color::Gray)),
+        inner,
+    );
+}
+
 // -- Custom Grid Widget -------------------------------------------------------
 
+/// Symbol and colors a cluster is drawn with
+pub fn cluster_glyph(cluster: ClusterState) -> (&'static str, Style) {
+    match cluster {
+        ClusterState::Used => (
+            "•",
+            Style::new()
+                .fg(Color::Rgb(0, 200, 0))
+                .bg(Color::Rgb(0, 100, 0)),
+        ),
+        ClusterState::Unused => ("░", Style::new().fg(Color::Gray).bg(Color::Blue)),
+        ClusterState::Pending => ("•", Style::new().fg(Color::Black).bg(Color::White)),
+        ClusterState::Bad => ("B", Style::new().fg(Color::Red).bg(Color::Black)),
+        ClusterState::Unmovable => ("X", Style::new().fg(Color::White).bg(Color::Blue)),
+        ClusterState::Reading => (
+            "r",
+            Style::new().fg(Color::Yellow).bg(Color::Rgb(0, 0, 139)),
+        ),
+        ClusterState::Writing => ("W", Style::new().fg(Color::Green).bg(Color::Rgb(0, 0, 139))),
+    }
+}
+
 struct DiskGridWidget<'a> {
     clusters: &'a [ClusterState],
+    /// Clusters drawn highlighted
+    flashing: &'a [usize],
 }
 
 impl Widget for DiskGridWidget<'_> {
@@ -475,7 +975,22 @@ impl Widget for DiskGridWidget<'_> {
             return;
         }
 
-        for (y, row_chunks) in self.clusters.chunks(display_width).enumerate() {
+        // Large disks are drawn with several clusters per cell
+        let per_cell = clusters_per_cell(self.clusters.len(), display_width * area.height as usize);
+        let cells: Vec<ClusterState> = self
+            .clusters
+            .chunks(per_cell)
+            .map(ClusterState::summarize)
+            .collect();
+
+        let mut flashing = vec![false; cells.len()];
+        for &cluster in self.flashing {
+            if let Some(cell) = flashing.get_mut(cluster / per_cell) {
+                *cell = true;
+            }
+        }
+
+        for (y, row_chunks) in cells.chunks(display_width).enumerate() {
             let row = y as u16;
             if row >= area.height {
                 break;
@@ -485,24 +1000,11 @@ impl Widget for DiskGridWidget<'_> {
                 if col >= area.width {
                     break;
                 }
-                let (symbol, style) = match cluster {
-                    ClusterState::Used => (
-                        "•",
-                        Style::new()
-                            .fg(Color::Rgb(0, 200, 0))
-                            .bg(Color::Rgb(0, 100, 0)),
-                    ),
-                    ClusterState::Unused => ("░", Style::new().fg(Color::Gray).bg(Color::Blue)),
-                    ClusterState::Pending => ("•", Style::new().fg(Color::Black).bg(Color::White)),
-                    ClusterState::Bad => ("B", Style::new().fg(Color::Red).bg(Color::Black)),
-                    ClusterState::Unmovable => ("X", Style::new().fg(Color::White).bg(Color::Blue)),
-                    ClusterState::Reading => (
-                        "r",
-                        Style::new().fg(Color::Yellow).bg(Color::Rgb(0, 0, 139)),
-                    ),
-                    ClusterState::Writing => {
-                        ("W", Style::new().fg(Color::Green).bg(Color::Rgb(0, 0, 139)))
-                    }
+                let (symbol, style) = cluster_glyph(*cluster);
+                let (symbol, style) = if flashing[y * display_width + x] {
+                    ("▓", Style::new().fg(Color::Yellow).bg(Color::Black))
+                } else {
+                    (symbol, style)
                 };
                 if let Some(cell) = buf.cell_mut((area.x + col, area.y + row)) {
                     cell.set_symbol(symbol).set_style(style);
@@ -511,3 +1013,37 @@ impl Widget for DiskGridWidget<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_app;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn test_footer_cuts_multibyte_names_on_a_character() {
+        let mut app = test_app(40, 8, 3);
+        // 'é' takes bytes 37 and 38, so a byte cut at 38 would split it
+        let name = format!("{}é日本語/ファイル.txt", "a".repeat(37));
+        app.engine.current_filename = Some(name);
+
+        let mut terminal = Terminal::new(TestBackend::new(84, 25)).unwrap();
+        terminal.draw(|frame| render_app(&app, frame)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        let status = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect::<String>()
+            })
+            .find(|line| line.contains("File: "))
+            .unwrap();
+        assert!(
+            status.contains(&format!("File: {}é", "a".repeat(37))),
+            "{}",
+            status
+        );
+        assert!(!status.contains('日'), "{}", status);
+    }
+}